@@ -0,0 +1,125 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use std::hint::black_box;
+
+use banking_rs::account::{AccountId, Cents};
+use banking_rs::banking_system::BankingSystem;
+use banking_rs::currency::Money;
+
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+
+fn name(i: usize) -> String {
+    format!("user{i}")
+}
+
+/// System with accounts of the given number, and the ids of the first and last of them.
+fn system(size: usize) -> (BankingSystem, AccountId, AccountId) {
+    let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+    for i in 0..size {
+        bs.create(&name(i), Money::usd(Cents::from_cents(1_000_000)))
+            .unwrap();
+    }
+    let first = bs.accounts()[0].id();
+    let last = bs.accounts()[size - 1].id();
+    (bs, first, last)
+}
+
+fn lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lookup");
+    for size in SIZES {
+        let (bs, _, last) = system(size);
+        let last_name = name(size - 1);
+
+        group.bench_function(BenchmarkId::new("id", size), |b| {
+            b.iter(|| bs.get_account(black_box(last)).unwrap())
+        });
+        group.bench_function(BenchmarkId::new("name", size), |b| {
+            b.iter(|| bs.find_account(black_box(&last_name)).unwrap())
+        });
+        // The linear scan lookups used before accounts were indexed
+        group.bench_function(BenchmarkId::new("scan", size), |b| {
+            b.iter(|| {
+                bs.accounts()
+                    .iter()
+                    .find(|x| x.name() == black_box(&last_name))
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn transfer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("transfer");
+    for size in SIZES {
+        let (mut bs, first, last) = system(size);
+        let first_name = name(0);
+        let last_name = name(size - 1);
+
+        group.bench_function(BenchmarkId::new("id", size), |b| {
+            b.iter(|| {
+                bs.transfer(
+                    black_box(first),
+                    black_box(last),
+                    Money::usd(Cents::from_cents(1)),
+                )
+                .unwrap();
+                bs.transfer(
+                    black_box(last),
+                    black_box(first),
+                    Money::usd(Cents::from_cents(1)),
+                )
+                .unwrap();
+            })
+        });
+        // Transfers between accounts given by name, which were found by a linear scan before
+        // accounts were indexed
+        group.bench_function(BenchmarkId::new("name", size), |b| {
+            b.iter(|| {
+                bs.transfer_str(black_box(&first_name), black_box(&last_name), "0.01")
+                    .unwrap();
+                bs.transfer_str(black_box(&last_name), black_box(&first_name), "0.01")
+                    .unwrap();
+            })
+        });
+        group.bench_function(BenchmarkId::new("scan", size), |b| {
+            let scan = |bs: &BankingSystem, name: &str| {
+                bs.accounts()
+                    .iter()
+                    .find(|x| x.name() == black_box(name))
+                    .unwrap()
+                    .id()
+            };
+            b.iter(|| {
+                let from = scan(&bs, &first_name);
+                let to = scan(&bs, &last_name);
+                bs.transfer(from, to, Money::usd(Cents::from_cents(1)))
+                    .unwrap();
+                let from = scan(&bs, &last_name);
+                let to = scan(&bs, &first_name);
+                bs.transfer(from, to, Money::usd(Cents::from_cents(1)))
+                    .unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+fn load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("load");
+    group.sample_size(10);
+    for size in SIZES {
+        let (bs, _, _) = system(size);
+
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter_batched(
+                || (bs.accounts().to_vec(), bs.journal().to_vec()),
+                |(accounts, journal)| BankingSystem::new(accounts, journal).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lookup, transfer, load);
+criterion_main!(benches);
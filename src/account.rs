@@ -1,301 +1,1019 @@
-use std::fmt::Display;
-use std::str::FromStr;
-
-use serde::{Deserialize, Serialize};
-use thiserror::Error;
-
-use crate::banking_system::BankingSystemError;
-
-#[derive(Error, Debug, Clone, PartialEq)]
-pub enum AccountError {
-    #[error(
-        "account {name} would overdraft if {withdraw_amount} was withdrawn from balance {balance}"
-    )]
-    AccountOverdraft {
-        name: String,
-        balance: Cents,
-        withdraw_amount: Cents,
-    },
-    #[error("account {name} would have balance overflow if {deposit_amount} was deposited")]
-    BalanceOverflow { name: String, deposit_amount: Cents },
-    #[error("account name cannot not be empty")]
-    EmptyAccountName,
-}
-
-#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Cents(pub(crate) u64);
-
-/// Display Cents as base currency unit.
-impl Display for Cents {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "${}.{:02}", self.0 / 100, self.0 % 100)
-    }
-}
-
-/// Parse a string into Cents. The string represents a non-negative number up to two decimal places.
-/// The string must only contain digits and up to one period as a decimal separator.
-impl FromStr for Cents {
-    type Err = BankingSystemError;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        // Has no decimal part
-        if !s.contains('.') {
-            let num = s
-                .parse::<u64>()
-                .map_err(|_| BankingSystemError::InvalidAmount(s.to_owned()))?
-                .checked_mul(100)
-                .ok_or(BankingSystemError::AmountOverflow(s.to_owned()))?;
-            return Ok(Self(num));
-        }
-
-        let (int_part_str, dec_part_str) = s
-            .rsplit_once('.')
-            .expect("from_str string should have period");
-
-        let integer_part = if int_part_str.chars().count() < 1 {
-            0 // Number has no leading zero
-        } else {
-            int_part_str
-                .parse::<u64>()
-                .map_err(|_| BankingSystemError::InvalidAmount(s.to_owned()))?
-        };
-
-        // Invalid decimal part length
-        if dec_part_str.chars().count() < 1 || dec_part_str.chars().count() > 2 {
-            return Err(BankingSystemError::InvalidAmount(s.to_owned()));
-        }
-        let decimal_part = dec_part_str
-            .parse::<u64>()
-            .map_err(|_| BankingSystemError::InvalidAmount(s.to_owned()))?;
-        let decimal_part = if dec_part_str.chars().count() == 1 {
-            decimal_part * 10 // Decimal part only goes to the tenth place
-        } else {
-            decimal_part
-        };
-
-        let num = integer_part
-            .checked_mul(100)
-            .ok_or(BankingSystemError::AmountOverflow(s.to_owned()))?
-            .checked_add(decimal_part)
-            .ok_or(BankingSystemError::AmountOverflow(s.to_owned()))?;
-
-        Ok(Self(num))
-    }
-}
-
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
-pub struct Account {
-    pub(crate) name: String,
-    pub(crate) balance: Cents,
-}
-
-impl Display for Account {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "name: {}\tbalance: {}", self.name, self.balance)
-    }
-}
-
-impl Account {
-    pub(crate) fn new(name: String, balance: Cents) -> Result<Self, AccountError> {
-        if name.chars().count() < 1 {
-            return Err(AccountError::EmptyAccountName);
-        }
-        Ok(Self { name, balance })
-    }
-
-    pub(crate) fn deposit(&mut self, amount: Cents) -> Result<&mut Self, AccountError> {
-        self.balance.0 =
-            self.balance
-                .0
-                .checked_add(amount.0)
-                .ok_or(AccountError::BalanceOverflow {
-                    name: self.name.to_owned(),
-                    deposit_amount: amount,
-                })?;
-        Ok(self)
-    }
-
-    pub(crate) fn withdraw(&mut self, amount: Cents) -> Result<&mut Self, AccountError> {
-        self.balance.0 =
-            self.balance
-                .0
-                .checked_sub(amount.0)
-                .ok_or(AccountError::AccountOverdraft {
-                    name: self.name.to_owned(),
-                    balance: self.balance,
-                    withdraw_amount: amount,
-                })?;
-        Ok(self)
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    const DEFAULT_NAME: &str = "user";
-
-    #[test]
-    fn test_empty_account_name() {
-        assert_eq!(
-            Account::new(String::from(""), Cents(23)),
-            Err(AccountError::EmptyAccountName)
-        );
-    }
-
-    #[test]
-    fn test_balance_overflow() {
-        assert_eq!(
-            Account::new(DEFAULT_NAME.to_owned(), Cents(u64::MAX - 1))
-                .unwrap()
-                .deposit(Cents(200)),
-            Err(AccountError::BalanceOverflow {
-                name: DEFAULT_NAME.to_owned(),
-                deposit_amount: Cents(200)
-            })
-        );
-    }
-
-    #[test]
-    fn test_account_overdraft() {
-        assert_eq!(
-            Account::new(DEFAULT_NAME.to_owned(), Cents(2))
-                .unwrap()
-                .withdraw(Cents(10)),
-            Err(AccountError::AccountOverdraft {
-                name: DEFAULT_NAME.to_owned(),
-                balance: Cents(2),
-                withdraw_amount: Cents(10)
-            })
-        );
-    }
-
-    #[test]
-    fn test_deposit() {
-        assert_eq!(
-            Account::new(DEFAULT_NAME.to_owned(), Cents(20))
-                .unwrap()
-                .deposit(Cents(100))
-                .unwrap()
-                .balance,
-            Cents(120)
-        );
-    }
-
-    #[test]
-    fn test_withdraw() {
-        assert_eq!(
-            Account::new(DEFAULT_NAME.to_owned(), Cents(120))
-                .unwrap()
-                .withdraw(Cents(100))
-                .unwrap()
-                .balance,
-            Cents(20)
-        );
-    }
-
-    #[test]
-    fn test_parse_to_cents_no_decimal() {
-        assert_eq!(Cents::from_str("0").unwrap(), Cents(0));
-        assert_eq!(Cents::from_str("2").unwrap(), Cents(200));
-        assert_eq!(Cents::from_str("30").unwrap(), Cents(3000));
-        assert_eq!(
-            Cents::from_str("-2"),
-            Err(BankingSystemError::InvalidAmount("-2".to_owned()))
-        );
-        assert_eq!(
-            Cents::from_str("2a"),
-            Err(BankingSystemError::InvalidAmount("2a".to_owned()))
-        );
-        assert_eq!(
-            Cents::from_str("wef"),
-            Err(BankingSystemError::InvalidAmount("wef".to_owned()))
-        );
-        assert_eq!(
-            Cents::from_str(u64::MAX.to_string().as_str()),
-            Err(BankingSystemError::AmountOverflow(u64::MAX.to_string()))
-        );
-    }
-
-    #[test]
-    fn test_parse_to_cents_has_decimal() {
-        assert_eq!(Cents::from_str(".0").unwrap(), Cents(0));
-        assert_eq!(Cents::from_str(".02").unwrap(), Cents(2));
-        assert_eq!(Cents::from_str(".2").unwrap(), Cents(20));
-        assert_eq!(Cents::from_str("0.0").unwrap(), Cents(0));
-        assert_eq!(Cents::from_str("0.00").unwrap(), Cents(0));
-        assert_eq!(Cents::from_str("1.00").unwrap(), Cents(100));
-        assert_eq!(Cents::from_str("1.02").unwrap(), Cents(102));
-        assert_eq!(Cents::from_str("3.1").unwrap(), Cents(310));
-        assert_eq!(Cents::from_str("30.2").unwrap(), Cents(3020));
-        assert_eq!(Cents::from_str("40.02").unwrap(), Cents(4002));
-        assert_eq!(Cents::from_str("40.12").unwrap(), Cents(4012));
-        assert_eq!(Cents::from_str("40.20").unwrap(), Cents(4020));
-        assert_eq!(Cents::from_str("50.99").unwrap(), Cents(5099));
-        assert_eq!(Cents::from_str(".1").unwrap(), Cents(10));
-        assert_eq!(
-            Cents::from_str("-0.0"),
-            Err(BankingSystemError::InvalidAmount("-0.0".to_owned()))
-        );
-        assert_eq!(
-            Cents::from_str("-1.0"),
-            Err(BankingSystemError::InvalidAmount("-1.0".to_owned()))
-        );
-        assert_eq!(
-            Cents::from_str("1."),
-            Err(BankingSystemError::InvalidAmount("1.".to_owned()))
-        );
-        assert_eq!(
-            Cents::from_str("2.002"),
-            Err(BankingSystemError::InvalidAmount("2.002".to_owned()))
-        );
-        assert_eq!(
-            Cents::from_str(".002"),
-            Err(BankingSystemError::InvalidAmount(".002".to_owned()))
-        );
-        assert_eq!(
-            Cents::from_str("1.1.2"),
-            Err(BankingSystemError::InvalidAmount("1.1.2".to_owned()))
-        );
-        assert_eq!(
-            Cents::from_str(".1.2"),
-            Err(BankingSystemError::InvalidAmount(".1.2".to_owned()))
-        );
-        assert_eq!(
-            Cents::from_str(".1a"),
-            Err(BankingSystemError::InvalidAmount(".1a".to_owned()))
-        );
-        assert_eq!(
-            Cents::from_str("a.2"),
-            Err(BankingSystemError::InvalidAmount("a.2".to_owned()))
-        );
-        assert_eq!(
-            Cents::from_str("..2"),
-            Err(BankingSystemError::InvalidAmount("..2".to_owned()))
-        );
-        let int_part_overflow = u64::MAX.to_string() + ".1";
-        assert_eq!(
-            Cents::from_str(int_part_overflow.as_str()),
-            Err(BankingSystemError::AmountOverflow(int_part_overflow))
-        );
-        let dec_part_overflow = (u64::MAX - 1).to_string() + ".9";
-        assert_eq!(
-            Cents::from_str(dec_part_overflow.as_str()),
-            Err(BankingSystemError::AmountOverflow(dec_part_overflow))
-        );
-    }
-
-    #[test]
-    fn test_display_cents() {
-        assert_eq!(Cents(0).to_string(), "$0.00");
-        assert_eq!(Cents(9).to_string(), "$0.09");
-        assert_eq!(Cents(10).to_string(), "$0.10");
-        assert_eq!(Cents(12).to_string(), "$0.12");
-        assert_eq!(Cents(99).to_string(), "$0.99");
-        assert_eq!(Cents(100).to_string(), "$1.00");
-        assert_eq!(Cents(109).to_string(), "$1.09");
-        assert_eq!(Cents(199).to_string(), "$1.99");
-        assert_eq!(Cents(4023).to_string(), "$40.23");
-        assert_eq!(Cents(5000).to_string(), "$50.00");
-    }
-}
+use std::fmt::Display;
+use std::str::FromStr;
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::banking_system::BankingSystemError;
+use crate::currency::{Currency, Money};
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum AccountError {
+    #[error(
+        "account {name} would overdraft if {withdraw_amount} was withdrawn from balance {balance}, only {available} is available"
+    )]
+    AccountOverdraft {
+        name: String,
+        balance: Money<SignedCents>,
+        /// Headroom left before the overdraft limit is reached.
+        available: Money,
+        withdraw_amount: Money,
+    },
+    #[error("account {name} would have balance overflow if {deposit_amount} was deposited")]
+    BalanceOverflow { name: String, deposit_amount: Money },
+    #[error("account name cannot not be empty")]
+    EmptyAccountName,
+    #[error("account name {0} is reserved, names cannot start with '@'")]
+    ReservedAccountName(String),
+    #[error("account name {0} is a valid account id, names must not be mistaken for ids")]
+    AccountIdName(String),
+    #[error("account {name} is frozen and cannot be debited")]
+    AccountFrozen { name: String },
+    #[error("account {name} is dormant and cannot be debited until it is reactivated")]
+    AccountDormant { name: String },
+    #[error("account {name} is closed")]
+    AccountClosed { name: String },
+    #[error("account {name} cannot be closed with balance {balance}, it must be zero or swept to another account")]
+    NonZeroBalance {
+        name: String,
+        balance: Money<SignedCents>,
+    },
+    #[error("account {name} cannot hold {amount}, only {available} is available")]
+    InsufficientFunds {
+        name: String,
+        available: Money,
+        amount: Money,
+    },
+    #[error("account {name} in {currency} cannot be posted an amount in {amount_currency}")]
+    CurrencyMismatch {
+        name: String,
+        currency: Currency,
+        amount_currency: Currency,
+    },
+}
+
+impl AccountError {
+    /// Stable identifier of the error for machine-readable output.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::AccountOverdraft { .. } => "account_overdraft",
+            Self::BalanceOverflow { .. } => "balance_overflow",
+            Self::EmptyAccountName => "empty_account_name",
+            Self::ReservedAccountName(_) => "reserved_account_name",
+            Self::AccountIdName(_) => "account_id_name",
+            Self::AccountFrozen { .. } => "account_frozen",
+            Self::AccountDormant { .. } => "account_dormant",
+            Self::AccountClosed { .. } => "account_closed",
+            Self::NonZeroBalance { .. } => "nonzero_balance",
+            Self::InsufficientFunds { .. } => "insufficient_funds",
+            Self::CurrencyMismatch { .. } => "currency_mismatch",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cents(pub(crate) u64);
+
+impl Cents {
+    pub const ZERO: Self = Self(0);
+
+    pub const fn from_cents(cents: u64) -> Self {
+        Self(cents)
+    }
+
+    /// Amount from a whole number of the base currency unit and cents, e.g. `(12, 34)` is $12.34.
+    /// Returns None if `minor` is not below 100 or the amount would overflow.
+    pub fn from_major_minor(major: u64, minor: u64) -> Option<Self> {
+        if minor >= 100 {
+            return None;
+        }
+        major.checked_mul(100)?.checked_add(minor).map(Self)
+    }
+
+    /// Total amount in cents.
+    pub const fn cents(self) -> u64 {
+        self.0
+    }
+
+    /// Whole base currency units of the amount.
+    pub const fn major(self) -> u64 {
+        self.0 / 100
+    }
+
+    /// Cents left over after the whole base currency units.
+    pub const fn minor(self) -> u64 {
+        self.0 % 100
+    }
+
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        self.0.checked_add(rhs.0).map(Self)
+    }
+
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        self.0.checked_sub(rhs.0).map(Self)
+    }
+}
+
+/// Display Cents as US dollars, the default currency.
+impl Display for Cents {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "${}.{:02}", self.major(), self.minor())
+    }
+}
+
+impl TryFrom<SignedCents> for Cents {
+    type Error = std::num::TryFromIntError;
+
+    fn try_from(value: SignedCents) -> Result<Self, Self::Error> {
+        u64::try_from(value.0).map(Self)
+    }
+}
+
+/// Signed amount of cents, such as a balance that may be overdrawn.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SignedCents(pub(crate) i64);
+
+impl SignedCents {
+    pub const ZERO: Self = Self(0);
+
+    pub const fn from_cents(cents: i64) -> Self {
+        Self(cents)
+    }
+
+    /// Total amount in cents.
+    pub const fn cents(self) -> i64 {
+        self.0
+    }
+
+    pub const fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    /// Amount without its sign.
+    pub const fn unsigned_abs(self) -> Cents {
+        Cents(self.0.unsigned_abs())
+    }
+
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        self.0.checked_add(rhs.0).map(Self)
+    }
+
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        self.0.checked_sub(rhs.0).map(Self)
+    }
+
+    pub fn checked_neg(self) -> Option<Self> {
+        self.0.checked_neg().map(Self)
+    }
+
+    /// Add an unsigned amount, such as a deposit.
+    pub fn checked_add_cents(self, rhs: Cents) -> Option<Self> {
+        self.0.checked_add_unsigned(rhs.0).map(Self)
+    }
+
+    /// Subtract an unsigned amount, such as a withdrawal.
+    pub fn checked_sub_cents(self, rhs: Cents) -> Option<Self> {
+        self.0.checked_sub_unsigned(rhs.0).map(Self)
+    }
+}
+
+impl TryFrom<Cents> for SignedCents {
+    type Error = std::num::TryFromIntError;
+
+    fn try_from(value: Cents) -> Result<Self, Self::Error> {
+        i64::try_from(value.0).map(Self)
+    }
+}
+
+/// Display SignedCents as US dollars, with a leading minus sign if negative.
+impl Display for SignedCents {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.is_negative() { "-" } else { "" };
+        write!(f, "{sign}{}", self.unsigned_abs())
+    }
+}
+
+/// Parse a string into SignedCents. The string is written like Cents with an optional leading `-`
+/// or `+` sign.
+impl FromStr for SignedCents {
+    type Err = BankingSystemError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Currency::USD.parse_signed_amount(s)
+    }
+}
+
+/// Parse a string into Cents. The string represents a non-negative number up to two decimal places.
+/// The string must only contain digits and up to one period as a decimal separator.
+impl FromStr for Cents {
+    type Err = BankingSystemError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Currency::USD.parse_amount(s)
+    }
+}
+
+/// Immutable identifier of an account, assigned in sequence when the account is created.
+///
+/// Written as the sequence number padded to seven digits followed by a Luhn check digit, e.g.
+/// `00000018` for the first account, so mistyped ids are rejected instead of finding another
+/// account.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AccountId(pub(crate) u64);
+
+impl AccountId {
+    /// Digits of the shortest written id, including the check digit.
+    const MIN_LEN: usize = 8;
+
+    pub const fn sequence(self) -> u64 {
+        self.0
+    }
+
+    /// Id with the next sequence number, none once the sequence numbers are used up.
+    pub const fn next(self) -> Option<Self> {
+        match self.0.checked_add(1) {
+            Some(sequence) => Some(Self(sequence)),
+            None => None,
+        }
+    }
+
+    /// Luhn check digit of the sequence number.
+    fn check_digit(self) -> u64 {
+        let mut sum = 0;
+        let mut n = self.0;
+        // Digits are doubled starting with the rightmost, as the check digit is appended after it
+        let mut double = true;
+        while n > 0 {
+            let mut digit = n % 10;
+            if double {
+                digit *= 2;
+                if digit > 9 {
+                    digit -= 9;
+                }
+            }
+            sum += digit;
+            double = !double;
+            n /= 10;
+        }
+        (10 - sum % 10) % 10
+    }
+}
+
+impl Display for AccountId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:07}{}", self.0, self.check_digit())
+    }
+}
+
+/// Parse an account id written with its check digit.
+impl FromStr for AccountId {
+    type Err = BankingSystemError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || BankingSystemError::InvalidAccountId(s.to_owned());
+        if s.len() < Self::MIN_LEN || !s.bytes().all(|x| x.is_ascii_digit()) {
+            return Err(invalid());
+        }
+
+        let (sequence, check_digit) = s.split_at(s.len() - 1);
+        let id = Self(sequence.parse().map_err(|_| invalid())?);
+        if check_digit.parse::<u64>() != Ok(id.check_digit()) {
+            return Err(invalid());
+        }
+        Ok(id)
+    }
+}
+
+impl Serialize for AccountId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for AccountId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Normalize an account name to NFC, so names that look the same are the same.
+pub fn normalize_name(name: &str) -> String {
+    name.nfc().collect()
+}
+
+/// Lifecycle state of an account, which limits the entries that can be posted to it.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountStatus {
+    #[default]
+    Open,
+    /// Blocked, e.g. because it was compromised. Can be credited but not debited.
+    Frozen,
+    /// Inactive for a long time. Can be credited but not debited until reactivated.
+    Dormant,
+    /// No longer in use. Nothing can be posted to it, but its id and history are kept.
+    Closed,
+}
+
+impl Display for AccountStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            Self::Open => "open",
+            Self::Frozen => "frozen",
+            Self::Dormant => "dormant",
+            Self::Closed => "closed",
+        };
+        write!(f, "{status}")
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub(crate) id: AccountId,
+    pub(crate) name: String,
+    /// Booked balance, which is negative while the account is overdrawn.
+    pub(crate) balance: SignedCents,
+    /// Accounts stored before they had a status are open.
+    #[serde(default)]
+    pub(crate) status: AccountStatus,
+    /// How far the balance may go below zero.
+    #[serde(default)]
+    pub(crate) overdraft_limit: Cents,
+    /// Currency of the balance and every amount posted to the account. Accounts stored before
+    /// they had a currency are in US dollars.
+    #[serde(default)]
+    pub(crate) currency: Currency,
+    /// Name of the savings product the account earns interest under.
+    #[serde(default)]
+    pub(crate) product: Option<String>,
+    /// Last day interest has been accrued for.
+    #[serde(default)]
+    pub(crate) accrued_to: Option<NaiveDate>,
+    /// Interest accrued but not yet posted, in billionths of the minor unit.
+    #[serde(default)]
+    pub(crate) accrued_interest: u64,
+    /// Total of the open holds on the account, which are stored separately.
+    #[serde(skip)]
+    pub(crate) held: Cents,
+}
+
+impl Display for Account {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "id: {}\tname: {}\tledger balance: {}\tavailable: {}\tstatus: {}",
+            self.id,
+            self.name,
+            self.money(self.balance),
+            self.money(self.available()),
+            self.status
+        )?;
+        if self.held != Cents::ZERO {
+            write!(f, "\theld: {}", self.money(self.held))?;
+        }
+        if let Some(product) = &self.product {
+            write!(f, "\tproduct: {product}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Account {
+    pub(crate) fn new(
+        id: AccountId,
+        name: &str,
+        balance: SignedCents,
+    ) -> Result<Self, AccountError> {
+        Ok(Self {
+            id,
+            name: Self::validate_name(name)?,
+            balance,
+            status: AccountStatus::Open,
+            overdraft_limit: Cents::ZERO,
+            currency: Currency::USD,
+            product: None,
+            accrued_to: None,
+            accrued_interest: 0,
+            held: Cents::ZERO,
+        })
+    }
+
+    /// Check that a name can be given to an account, returning it normalized.
+    pub(crate) fn validate_name(name: &str) -> Result<String, AccountError> {
+        let name = normalize_name(name);
+        if name.chars().count() < 1 {
+            return Err(AccountError::EmptyAccountName);
+        }
+        // Names starting with '@' are used by ledger accounts such as the external account
+        if name.starts_with('@') {
+            return Err(AccountError::ReservedAccountName(name));
+        }
+        // Accounts are looked up by id or name, so a name must not be read as an id
+        if name.parse::<AccountId>().is_ok() {
+            return Err(AccountError::AccountIdName(name));
+        }
+        Ok(name)
+    }
+
+    pub fn id(&self) -> AccountId {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn balance(&self) -> SignedCents {
+        self.balance
+    }
+
+    pub fn overdraft_limit(&self) -> Cents {
+        self.overdraft_limit
+    }
+
+    /// Amount that can be withdrawn before the overdraft limit is reached, less the funds
+    /// reserved by holds.
+    pub fn available(&self) -> Cents {
+        let available =
+            self.balance.0 as i128 + self.overdraft_limit.0 as i128 - self.held.0 as i128;
+        Cents(u64::try_from(available.max(0)).unwrap_or(u64::MAX))
+    }
+
+    /// Funds reserved by open holds.
+    pub fn held(&self) -> Cents {
+        self.held
+    }
+
+    pub fn status(&self) -> AccountStatus {
+        self.status
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn product(&self) -> Option<&str> {
+        self.product.as_deref()
+    }
+
+    pub fn accrued_to(&self) -> Option<NaiveDate> {
+        self.accrued_to
+    }
+
+    /// Interest accrued since it was last posted, in billionths of the minor unit.
+    pub fn accrued_interest(&self) -> u64 {
+        self.accrued_interest
+    }
+
+    /// Amount in the currency of the account.
+    pub fn money<A: Copy>(&self, amount: A) -> Money<A> {
+        Money::new(amount, self.currency)
+    }
+
+    pub(crate) fn check_currency(&self, amount: Money) -> Result<Cents, AccountError> {
+        if amount.currency != self.currency {
+            return Err(AccountError::CurrencyMismatch {
+                name: self.name.to_owned(),
+                currency: self.currency,
+                amount_currency: amount.currency,
+            });
+        }
+        Ok(amount.amount)
+    }
+
+    pub(crate) fn deposit(&mut self, amount: Money) -> Result<&mut Self, AccountError> {
+        if self.status == AccountStatus::Closed {
+            return Err(AccountError::AccountClosed {
+                name: self.name.to_owned(),
+            });
+        }
+        let cents = self.check_currency(amount)?;
+
+        self.balance =
+            self.balance
+                .checked_add_cents(cents)
+                .ok_or(AccountError::BalanceOverflow {
+                    name: self.name.to_owned(),
+                    deposit_amount: amount,
+                })?;
+        Ok(self)
+    }
+
+    /// Check that the status of the account allows it to be debited.
+    fn check_debit(&self) -> Result<(), AccountError> {
+        let name = || self.name.to_owned();
+        match self.status {
+            AccountStatus::Open => Ok(()),
+            AccountStatus::Frozen => Err(AccountError::AccountFrozen { name: name() }),
+            AccountStatus::Dormant => Err(AccountError::AccountDormant { name: name() }),
+            AccountStatus::Closed => Err(AccountError::AccountClosed { name: name() }),
+        }
+    }
+
+    /// Reserve funds for a hold, which must be available.
+    pub(crate) fn hold(&mut self, amount: Money) -> Result<&mut Self, AccountError> {
+        self.check_debit()?;
+        let cents = self.check_currency(amount)?;
+        if cents > self.available() {
+            return Err(AccountError::InsufficientFunds {
+                name: self.name.to_owned(),
+                available: self.money(self.available()),
+                amount,
+            });
+        }
+        self.held = Cents(self.held.0 + cents.0);
+        Ok(self)
+    }
+
+    pub(crate) fn withdraw(&mut self, amount: Money) -> Result<&mut Self, AccountError> {
+        self.check_debit()?;
+        let cents = self.check_currency(amount)?;
+        if cents > self.available() {
+            return Err(self.overdraft(amount));
+        }
+        self.debit(amount)
+    }
+
+    /// Debit the account like `withdraw`, without checking that the funds are available. Used for
+    /// postings whose overdraft limit is checked on their net change instead.
+    pub(crate) fn debit(&mut self, amount: Money) -> Result<&mut Self, AccountError> {
+        self.check_debit()?;
+        let cents = self.check_currency(amount)?;
+        self.balance = self
+            .balance
+            .checked_sub_cents(cents)
+            .ok_or_else(|| self.overdraft(amount))?;
+        Ok(self)
+    }
+
+    fn overdraft(&self, amount: Money) -> AccountError {
+        AccountError::AccountOverdraft {
+            name: self.name.to_owned(),
+            balance: self.money(self.balance),
+            available: self.money(self.available()),
+            withdraw_amount: amount,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULT_NAME: &str = "user";
+
+    #[test]
+    fn test_empty_account_name() {
+        assert_eq!(
+            Account::new(AccountId(1), "", SignedCents(23)),
+            Err(AccountError::EmptyAccountName)
+        );
+    }
+
+    #[test]
+    fn test_reserved_account_name() {
+        assert_eq!(
+            Account::new(AccountId(1), "@external", SignedCents(23)),
+            Err(AccountError::ReservedAccountName(String::from("@external")))
+        );
+    }
+
+    #[test]
+    fn test_balance_overflow() {
+        assert_eq!(
+            Account::new(AccountId(1), DEFAULT_NAME, SignedCents(i64::MAX - 1))
+                .unwrap()
+                .deposit(Money::usd(Cents(200))),
+            Err(AccountError::BalanceOverflow {
+                name: DEFAULT_NAME.to_owned(),
+                deposit_amount: Money::usd(Cents(200))
+            })
+        );
+    }
+
+    #[test]
+    fn test_account_overdraft() {
+        assert_eq!(
+            Account::new(AccountId(1), DEFAULT_NAME, SignedCents(2))
+                .unwrap()
+                .withdraw(Money::usd(Cents(10))),
+            Err(AccountError::AccountOverdraft {
+                name: DEFAULT_NAME.to_owned(),
+                balance: Money::new(SignedCents(2), Currency::USD),
+                available: Money::usd(Cents(2)),
+                withdraw_amount: Money::usd(Cents(10))
+            })
+        );
+    }
+
+    #[test]
+    fn test_overdraft_limit() {
+        let mut account = Account::new(AccountId(1), DEFAULT_NAME, SignedCents(200)).unwrap();
+        account.overdraft_limit = Cents(500);
+        assert_eq!(account.available(), Cents(700));
+
+        account.withdraw(Money::usd(Cents(600))).unwrap();
+        assert_eq!(account.balance(), SignedCents(-400));
+        assert_eq!(account.available(), Cents(100));
+        assert_eq!(
+            account.withdraw(Money::usd(Cents(101))),
+            Err(AccountError::AccountOverdraft {
+                name: DEFAULT_NAME.to_owned(),
+                balance: Money::new(SignedCents(-400), Currency::USD),
+                available: Money::usd(Cents(100)),
+                withdraw_amount: Money::usd(Cents(101))
+            })
+        );
+        account.withdraw(Money::usd(Cents(100))).unwrap();
+        assert_eq!(account.balance(), SignedCents(-500));
+
+        // Lowering the limit below the overdraft leaves nothing available
+        account.overdraft_limit = Cents(100);
+        assert_eq!(account.available(), Cents(0));
+        account.deposit(Money::usd(Cents(550))).unwrap();
+        assert_eq!(account.balance(), SignedCents(50));
+    }
+
+    #[test]
+    fn test_hold() {
+        let mut account = Account::new(AccountId(1), DEFAULT_NAME, SignedCents(1000)).unwrap();
+        account.overdraft_limit = Cents(500);
+
+        account.hold(Money::usd(Cents(1200))).unwrap();
+        assert_eq!(account.held(), Cents(1200));
+        assert_eq!(account.available(), Cents(300));
+        assert_eq!(account.balance(), SignedCents(1000));
+        assert_eq!(
+            account.hold(Money::usd(Cents(301))),
+            Err(AccountError::InsufficientFunds {
+                name: DEFAULT_NAME.to_owned(),
+                available: Money::usd(Cents(300)),
+                amount: Money::usd(Cents(301)),
+            })
+        );
+        // Held funds cannot be withdrawn
+        assert!(account.withdraw(Money::usd(Cents(301))).is_err());
+        account.withdraw(Money::usd(Cents(300))).unwrap();
+        assert_eq!(account.available(), Cents(0));
+
+        account.status = AccountStatus::Frozen;
+        assert_eq!(
+            account.hold(Money::usd(Cents(0))),
+            Err(AccountError::AccountFrozen {
+                name: DEFAULT_NAME.to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn test_display_account() {
+        let mut account = Account::new(AccountId(1), DEFAULT_NAME, SignedCents(-105)).unwrap();
+        account.overdraft_limit = Cents(1000);
+
+        assert_eq!(
+            account.to_string(),
+            "id: 00000018\tname: user\tledger balance: -$1.05\tavailable: $8.95\tstatus: open"
+        );
+
+        account.product = Some(String::from("savings"));
+        assert!(account
+            .to_string()
+            .ends_with("\tstatus: open\tproduct: savings"));
+    }
+
+    #[test]
+    fn test_display_signed_cents() {
+        assert_eq!(SignedCents(105).to_string(), "$1.05");
+        assert_eq!(SignedCents(-105).to_string(), "-$1.05");
+        assert_eq!(SignedCents(i64::MIN).to_string(), "-$92233720368547758.08");
+    }
+
+    #[test]
+    fn test_deposit() {
+        assert_eq!(
+            Account::new(AccountId(1), DEFAULT_NAME, SignedCents(20))
+                .unwrap()
+                .deposit(Money::usd(Cents(100)))
+                .unwrap()
+                .balance,
+            SignedCents(120)
+        );
+    }
+
+    #[test]
+    fn test_withdraw() {
+        assert_eq!(
+            Account::new(AccountId(1), DEFAULT_NAME, SignedCents(120))
+                .unwrap()
+                .withdraw(Money::usd(Cents(100)))
+                .unwrap()
+                .balance,
+            SignedCents(20)
+        );
+    }
+
+    #[test]
+    fn test_parse_to_cents_no_decimal() {
+        assert_eq!(Cents::from_str("0").unwrap(), Cents(0));
+        assert_eq!(Cents::from_str("2").unwrap(), Cents(200));
+        assert_eq!(Cents::from_str("30").unwrap(), Cents(3000));
+        assert_eq!(
+            Cents::from_str("-2"),
+            Err(BankingSystemError::InvalidAmount("-2".to_owned()))
+        );
+        assert_eq!(
+            Cents::from_str("2a"),
+            Err(BankingSystemError::InvalidAmount("2a".to_owned()))
+        );
+        assert_eq!(
+            Cents::from_str("wef"),
+            Err(BankingSystemError::InvalidAmount("wef".to_owned()))
+        );
+        assert_eq!(
+            Cents::from_str(u64::MAX.to_string().as_str()),
+            Err(BankingSystemError::AmountOverflow(u64::MAX.to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_to_cents_has_decimal() {
+        assert_eq!(Cents::from_str(".0").unwrap(), Cents(0));
+        assert_eq!(Cents::from_str(".02").unwrap(), Cents(2));
+        assert_eq!(Cents::from_str(".2").unwrap(), Cents(20));
+        assert_eq!(Cents::from_str("0.0").unwrap(), Cents(0));
+        assert_eq!(Cents::from_str("0.00").unwrap(), Cents(0));
+        assert_eq!(Cents::from_str("1.00").unwrap(), Cents(100));
+        assert_eq!(Cents::from_str("1.02").unwrap(), Cents(102));
+        assert_eq!(Cents::from_str("3.1").unwrap(), Cents(310));
+        assert_eq!(Cents::from_str("30.2").unwrap(), Cents(3020));
+        assert_eq!(Cents::from_str("40.02").unwrap(), Cents(4002));
+        assert_eq!(Cents::from_str("40.12").unwrap(), Cents(4012));
+        assert_eq!(Cents::from_str("40.20").unwrap(), Cents(4020));
+        assert_eq!(Cents::from_str("50.99").unwrap(), Cents(5099));
+        assert_eq!(Cents::from_str(".1").unwrap(), Cents(10));
+        assert_eq!(
+            Cents::from_str("-0.0"),
+            Err(BankingSystemError::InvalidAmount("-0.0".to_owned()))
+        );
+        assert_eq!(
+            Cents::from_str("-1.0"),
+            Err(BankingSystemError::InvalidAmount("-1.0".to_owned()))
+        );
+        assert_eq!(
+            Cents::from_str("1."),
+            Err(BankingSystemError::InvalidAmount("1.".to_owned()))
+        );
+        assert_eq!(
+            Cents::from_str("2.002"),
+            Err(BankingSystemError::InvalidAmount("2.002".to_owned()))
+        );
+        assert_eq!(
+            Cents::from_str(".002"),
+            Err(BankingSystemError::InvalidAmount(".002".to_owned()))
+        );
+        assert_eq!(
+            Cents::from_str("1.1.2"),
+            Err(BankingSystemError::InvalidAmount("1.1.2".to_owned()))
+        );
+        assert_eq!(
+            Cents::from_str(".1.2"),
+            Err(BankingSystemError::InvalidAmount(".1.2".to_owned()))
+        );
+        assert_eq!(
+            Cents::from_str(".1a"),
+            Err(BankingSystemError::InvalidAmount(".1a".to_owned()))
+        );
+        assert_eq!(
+            Cents::from_str("a.2"),
+            Err(BankingSystemError::InvalidAmount("a.2".to_owned()))
+        );
+        assert_eq!(
+            Cents::from_str("..2"),
+            Err(BankingSystemError::InvalidAmount("..2".to_owned()))
+        );
+        let int_part_overflow = u64::MAX.to_string() + ".1";
+        assert_eq!(
+            Cents::from_str(int_part_overflow.as_str()),
+            Err(BankingSystemError::AmountOverflow(int_part_overflow))
+        );
+        let dec_part_overflow = (u64::MAX - 1).to_string() + ".9";
+        assert_eq!(
+            Cents::from_str(dec_part_overflow.as_str()),
+            Err(BankingSystemError::AmountOverflow(dec_part_overflow))
+        );
+    }
+
+    #[test]
+    fn test_parse_to_signed_cents() {
+        assert_eq!(SignedCents::from_str("1.05"), Ok(SignedCents(105)));
+        assert_eq!(SignedCents::from_str("+1.05"), Ok(SignedCents(105)));
+        assert_eq!(SignedCents::from_str("-1.05"), Ok(SignedCents(-105)));
+        assert_eq!(SignedCents::from_str("-.5"), Ok(SignedCents(-50)));
+        assert_eq!(SignedCents::from_str("-0"), Ok(SignedCents(0)));
+        assert_eq!(
+            SignedCents::from_str("-92233720368547758.08"),
+            Ok(SignedCents(i64::MIN))
+        );
+        for s in ["", "-", "--1", "-+1", "+-1", "1-", "- 1", "-1.001", "-a"] {
+            assert_eq!(
+                SignedCents::from_str(s),
+                Err(BankingSystemError::InvalidSignedAmount(s.to_owned()))
+            );
+        }
+        for s in [
+            "92233720368547758.08",
+            "-92233720368547758.09",
+            "-184467440737095516.16",
+        ] {
+            assert_eq!(
+                SignedCents::from_str(s),
+                Err(BankingSystemError::AmountOverflow(s.to_owned()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_signed_cents_arithmetic() {
+        assert_eq!(
+            SignedCents(100).checked_sub_cents(Cents(250)),
+            Some(SignedCents(-150))
+        );
+        assert_eq!(
+            SignedCents(-150).checked_add_cents(Cents(250)),
+            Some(SignedCents(100))
+        );
+        assert_eq!(SignedCents(-1).checked_add_cents(Cents(u64::MAX)), None);
+        assert_eq!(
+            SignedCents(i64::MIN).checked_add_cents(Cents(u64::MAX)),
+            Some(SignedCents(i64::MAX))
+        );
+        assert_eq!(SignedCents(-105).checked_neg(), Some(SignedCents(105)));
+        assert_eq!(SignedCents(i64::MIN).checked_neg(), None);
+        assert_eq!(SignedCents(i64::MIN).checked_sub_cents(Cents(1)), None);
+        assert_eq!(SignedCents(i64::MIN).unsigned_abs(), Cents(1 << 63));
+        assert_eq!(Cents::try_from(SignedCents(-1)).ok(), None);
+        assert_eq!(SignedCents::try_from(Cents(u64::MAX)).ok(), None);
+    }
+
+    #[test]
+    fn test_cents_constructors() {
+        assert_eq!(Cents::from_cents(1234), Cents(1234));
+        assert_eq!(Cents::from_major_minor(12, 34), Some(Cents(1234)));
+        assert_eq!(Cents::from_major_minor(0, 5), Some(Cents(5)));
+        assert_eq!(Cents::from_major_minor(1, 100), None);
+        assert_eq!(Cents::from_major_minor(u64::MAX / 100, 99), None);
+        assert_eq!(Cents::ZERO, Cents(0));
+    }
+
+    #[test]
+    fn test_cents_accessors() {
+        let cents = Cents(1234);
+
+        assert_eq!(cents.cents(), 1234);
+        assert_eq!(cents.major(), 12);
+        assert_eq!(cents.minor(), 34);
+    }
+
+    #[test]
+    fn test_cents_checked_arithmetic() {
+        assert_eq!(Cents(2).checked_add(Cents(3)), Some(Cents(5)));
+        assert_eq!(Cents(u64::MAX).checked_add(Cents(1)), None);
+        assert_eq!(Cents(3).checked_sub(Cents(2)), Some(Cents(1)));
+        assert_eq!(Cents(2).checked_sub(Cents(3)), None);
+    }
+
+    #[test]
+    fn test_display_cents() {
+        assert_eq!(Cents(0).to_string(), "$0.00");
+        assert_eq!(Cents(9).to_string(), "$0.09");
+        assert_eq!(Cents(10).to_string(), "$0.10");
+        assert_eq!(Cents(12).to_string(), "$0.12");
+        assert_eq!(Cents(99).to_string(), "$0.99");
+        assert_eq!(Cents(100).to_string(), "$1.00");
+        assert_eq!(Cents(109).to_string(), "$1.09");
+        assert_eq!(Cents(199).to_string(), "$1.99");
+        assert_eq!(Cents(4023).to_string(), "$40.23");
+        assert_eq!(Cents(5000).to_string(), "$50.00");
+    }
+
+    #[test]
+    fn test_display_account_id() {
+        assert_eq!(AccountId(1).to_string(), "00000018");
+        assert_eq!(AccountId(2).to_string(), "00000026");
+        assert_eq!(AccountId(12345).to_string(), "00123455");
+        assert_eq!(AccountId(123456789).to_string(), "1234567897");
+    }
+
+    #[test]
+    fn test_parse_account_id() {
+        assert_eq!(AccountId::from_str("00000018"), Ok(AccountId(1)));
+        assert_eq!(AccountId::from_str("1234567897"), Ok(AccountId(123456789)));
+
+        for s in ["00000017", "0000018", "0000001a8", "", "-0000018"] {
+            assert_eq!(
+                AccountId::from_str(s),
+                Err(BankingSystemError::InvalidAccountId(s.to_owned()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_account_id_serde() {
+        let mut wtr = csv::Writer::from_writer(Vec::new());
+        wtr.serialize(Account::new(AccountId(1), DEFAULT_NAME, SignedCents(20)).unwrap())
+            .unwrap();
+        let data = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            data,
+            "id,name,balance,status,overdraft_limit,currency,product,accrued_to,accrued_interest\n\
+             00000018,user,20,open,0,USD,,,0\n"
+        );
+
+        let account = csv::Reader::from_reader(data.as_bytes())
+            .deserialize::<Account>()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(account.id(), AccountId(1));
+
+        // Accounts saved before currencies are in dollars
+        let account = csv::Reader::from_reader(
+            "id,name,balance,status,overdraft_limit\n00000018,user,20,open,0\n".as_bytes(),
+        )
+        .deserialize::<Account>()
+        .next()
+        .unwrap()
+        .unwrap();
+        assert_eq!(account.currency(), Currency::USD);
+    }
+
+    #[test]
+    fn test_account_name_normalized() {
+        let account = Account::new(AccountId(1), "Andre\u{301}", SignedCents(0)).unwrap();
+
+        assert_eq!(account.name(), "Andr\u{e9}");
+    }
+
+    #[test]
+    fn test_account_id_name() {
+        assert_eq!(
+            Account::new(AccountId(1), "00000026", SignedCents(0)),
+            Err(AccountError::AccountIdName(String::from("00000026")))
+        );
+        // Numbers that are not valid ids are allowed
+        assert!(Account::new(AccountId(1), "00000027", SignedCents(0)).is_ok());
+    }
+
+    #[test]
+    fn test_account_status() {
+        let mut account = Account::new(AccountId(1), DEFAULT_NAME, SignedCents(100)).unwrap();
+
+        account.status = AccountStatus::Frozen;
+        assert!(account.deposit(Money::usd(Cents(10))).is_ok());
+        assert_eq!(
+            account.withdraw(Money::usd(Cents(10))),
+            Err(AccountError::AccountFrozen {
+                name: DEFAULT_NAME.to_owned()
+            })
+        );
+
+        account.status = AccountStatus::Dormant;
+        assert!(account.deposit(Money::usd(Cents(10))).is_ok());
+        assert_eq!(
+            account.withdraw(Money::usd(Cents(10))),
+            Err(AccountError::AccountDormant {
+                name: DEFAULT_NAME.to_owned()
+            })
+        );
+
+        account.status = AccountStatus::Closed;
+        for result in [
+            account.clone().deposit(Money::usd(Cents(10))).cloned(),
+            account.clone().withdraw(Money::usd(Cents(10))).cloned(),
+        ] {
+            assert_eq!(
+                result,
+                Err(AccountError::AccountClosed {
+                    name: DEFAULT_NAME.to_owned()
+                })
+            );
+        }
+        assert_eq!(account.balance, SignedCents(120));
+    }
+
+    #[test]
+    fn test_account_status_default() {
+        let account = csv::Reader::from_reader("id,name,balance\n00000018,user,20\n".as_bytes())
+            .deserialize::<Account>()
+            .next()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(account.status(), AccountStatus::Open);
+        assert_eq!(account.overdraft_limit(), Cents::ZERO);
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+
+use crate::account::{normalize_name, Account, AccountId};
+
+/// Accounts in the order they were created, indexed by id and name for constant time lookup.
+#[derive(Debug, Clone, Default)]
+pub struct AccountStore {
+    accounts: Vec<Account>,
+    ids: HashMap<AccountId, usize>,
+    names: HashMap<String, usize>,
+    /// Highest id of any account, so ids are never reused.
+    last_id: AccountId,
+}
+
+impl From<Vec<Account>> for AccountStore {
+    fn from(accounts: Vec<Account>) -> Self {
+        let mut store = Self::with_capacity(accounts.len());
+        for account in accounts {
+            // Keep the first of duplicate ids, which was the one found by lookups before
+            let _ = store.insert(account);
+        }
+        store
+    }
+}
+
+impl AccountStore {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            accounts: Vec::with_capacity(capacity),
+            ids: HashMap::with_capacity(capacity),
+            names: HashMap::with_capacity(capacity),
+            last_id: AccountId::default(),
+        }
+    }
+
+    pub fn as_slice(&self) -> &[Account] {
+        &self.accounts
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    /// Highest id assigned to an account.
+    pub(crate) fn last_id(&self) -> AccountId {
+        self.last_id
+    }
+
+    /// Id to assign to the next account created, none once every id has been used.
+    pub fn next_id(&self) -> Option<AccountId> {
+        self.last_id.next()
+    }
+
+    pub fn contains(&self, id: AccountId) -> bool {
+        self.ids.contains_key(&id)
+    }
+
+    /// Whether an account has the name, after normalization.
+    pub fn contains_name(&self, name: &str) -> bool {
+        self.names.contains_key(&normalize_name(name))
+    }
+
+    /// Position of the account in creation order.
+    pub fn position(&self, id: AccountId) -> Option<usize> {
+        self.ids.get(&id).copied()
+    }
+
+    pub fn get(&self, id: AccountId) -> Option<&Account> {
+        self.position(id).map(|i| &self.accounts[i])
+    }
+
+    /// Mutable access to the account. Its id and name must not be changed, as they are the keys of
+    /// the index.
+    pub fn get_mut(&mut self, id: AccountId) -> Option<&mut Account> {
+        self.position(id).map(|i| &mut self.accounts[i])
+    }
+
+    /// Account with the name, after normalization.
+    pub fn find(&self, name: &str) -> Option<&Account> {
+        self.names
+            .get(&normalize_name(name))
+            .map(|&i| &self.accounts[i])
+    }
+
+    /// Add an account, returning it back if an account with the same id already exists.
+    ///
+    /// Names are expected to be unique, but stored accounts sharing a name are still added and
+    /// only the first of them can be found by name.
+    pub(crate) fn insert(&mut self, account: Account) -> Result<(), Account> {
+        if self.contains(account.id) {
+            return Err(account);
+        }
+        let position = self.accounts.len();
+        self.ids.insert(account.id, position);
+        self.names
+            .entry(normalize_name(&account.name))
+            .or_insert(position);
+        self.last_id = self.last_id.max(account.id);
+        self.accounts.push(account);
+        Ok(())
+    }
+
+    /// Remove every account after the first ones, restoring the last id assigned before they
+    /// were added.
+    pub(crate) fn truncate(&mut self, len: usize, last_id: AccountId) {
+        for account in self.accounts.drain(len..) {
+            self.ids.remove(&account.id);
+            let name = normalize_name(&account.name);
+            if self.names.get(&name).is_some_and(|&x| x >= len) {
+                self.names.remove(&name);
+            }
+        }
+        self.last_id = last_id;
+    }
+
+    /// Replace the account at a position with an updated copy of it.
+    pub(crate) fn replace(&mut self, position: usize, account: Account) {
+        debug_assert_eq!(
+            (self.accounts[position].id, &self.accounts[position].name),
+            (account.id, &account.name),
+            "replaced account should keep its id and name"
+        );
+        self.accounts[position] = account;
+    }
+
+    /// Change the name of an account, which must already be validated and not in use.
+    pub(crate) fn rename(&mut self, id: AccountId, name: String) -> Option<()> {
+        let position = self.position(id)?;
+        let account = &mut self.accounts[position];
+        let old_name = normalize_name(&account.name);
+        if self.names.get(&old_name) == Some(&position) {
+            self.names.remove(&old_name);
+        }
+        self.names.insert(normalize_name(&name), position);
+        account.name = name;
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::{AccountStatus, Cents, SignedCents};
+    use crate::currency::Currency;
+
+    fn account(id: u64, name: &str, balance: i64) -> Account {
+        Account {
+            id: AccountId(id),
+            name: name.to_owned(),
+            balance: SignedCents(balance),
+            status: AccountStatus::Open,
+            overdraft_limit: Cents::ZERO,
+            currency: Currency::USD,
+            product: None,
+            accrued_to: None,
+            accrued_interest: 0,
+            held: Cents::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let store = AccountStore::from(Vec::from([
+            account(1, "user1", 100),
+            account(2, "user2", 200),
+        ]));
+
+        assert_eq!(store.len(), 2);
+        assert!(store.contains(AccountId(2)));
+        assert!(!store.contains(AccountId(3)));
+        assert!(store.contains_name("user2"));
+        assert_eq!(store.position(AccountId(2)), Some(1));
+        assert_eq!(store.get(AccountId(1)), Some(&account(1, "user1", 100)));
+        assert_eq!(store.get(AccountId(3)), None);
+        assert_eq!(store.find("user2"), Some(&account(2, "user2", 200)));
+        assert_eq!(store.find("user3"), None);
+    }
+
+    #[test]
+    fn test_find_normalized_name() {
+        // Composed "é" stored, decomposed "e" and combining acute accent looked up
+        let store = AccountStore::from(Vec::from([account(1, "Andr\u{e9}", 100)]));
+
+        assert_eq!(store.find("Andre\u{301}").unwrap().id, AccountId(1));
+        assert!(store.contains_name("Andre\u{301}"));
+
+        // Names stored before normalization are found by their normalized form
+        let mut store = AccountStore::from(Vec::from([account(1, "Andre\u{301}", 100)]));
+        assert_eq!(store.find("Andr\u{e9}").unwrap().id, AccountId(1));
+        assert_eq!(store.find("Andre\u{301}").unwrap().id, AccountId(1));
+        store.rename(AccountId(1), String::from("user1")).unwrap();
+        assert!(!store.contains_name("Andr\u{e9}"));
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut store = AccountStore::default();
+        assert_eq!(store.next_id(), Some(AccountId(1)));
+
+        store.insert(account(1, "user1", 100)).unwrap();
+        store.insert(account(5, "user2", 200)).unwrap();
+        assert_eq!(
+            store.insert(account(1, "user3", 300)),
+            Err(account(1, "user3", 300))
+        );
+
+        assert_eq!(
+            store.as_slice(),
+            [account(1, "user1", 100), account(5, "user2", 200)]
+        );
+        assert_eq!(store.position(AccountId(5)), Some(1));
+        assert_eq!(store.next_id(), Some(AccountId(6)));
+
+        store.insert(account(u64::MAX, "user3", 300)).unwrap();
+        assert_eq!(store.next_id(), None);
+    }
+
+    #[test]
+    fn test_duplicate_names_keep_first() {
+        let store = AccountStore::from(Vec::from([
+            account(1, "user1", 100),
+            account(2, "user1", 200),
+        ]));
+
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.find("user1"), Some(&account(1, "user1", 100)));
+        assert_eq!(store.get(AccountId(2)), Some(&account(2, "user1", 200)));
+    }
+
+    #[test]
+    fn test_get_mut() {
+        let mut store = AccountStore::from(Vec::from([account(1, "user1", 100)]));
+
+        store.get_mut(AccountId(1)).unwrap().balance = SignedCents(500);
+        assert_eq!(store.get(AccountId(1)), Some(&account(1, "user1", 500)));
+        assert!(store.get_mut(AccountId(2)).is_none());
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut store = AccountStore::from(Vec::from([account(1, "user1", 100)]));
+
+        store.rename(AccountId(1), String::from("user2")).unwrap();
+        assert_eq!(store.find("user1"), None);
+        assert_eq!(store.find("user2"), Some(&account(1, "user2", 100)));
+        assert_eq!(store.rename(AccountId(2), String::from("user3")), None);
+    }
+}
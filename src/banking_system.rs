@@ -1,309 +1,3946 @@
-use std::str::FromStr;
-
-use anyhow::Result;
-use thiserror::Error;
-
-use crate::account::{Account, Cents};
-
-#[derive(Error, Debug, Clone, PartialEq)]
-pub enum BankingSystemError {
-    #[error("account with name {0} already exists")]
-    DuplicateAccountName(String),
-    #[error("account with name {0} not found")]
-    AccountNotFound(String),
-    #[error("invalid amount {0:?}, must be a non-negative number only containing digits up to two decimal places")]
-    InvalidAmount(String),
-    #[error("amount {0} would overflow")]
-    AmountOverflow(String),
-}
-
-/// System to process user input and execute the specified command.
-#[derive(Debug, Clone)]
-pub struct BankingSystem(pub Vec<Account>);
-
-impl BankingSystem {
-    pub fn show(&self) {
-        for account in self.0.iter() {
-            println!("{account}");
-        }
-    }
-
-    fn account_exists(&self, name: &str) -> bool {
-        self.0.iter().any(|x| x.name == name)
-    }
-
-    fn get_account_mut(&mut self, name: &str) -> Result<&mut Account, BankingSystemError> {
-        self.0
-            .iter_mut()
-            .find(|x| x.name == name)
-            .ok_or(BankingSystemError::AccountNotFound(name.to_owned()))
-    }
-
-    pub fn create(&mut self, name: &str, balance: &str) -> Result<()> {
-        if self.account_exists(name) {
-            return Err(BankingSystemError::DuplicateAccountName(name.to_owned()).into());
-        }
-
-        match Account::new(name.to_owned(), balance.parse()?) {
-            Ok(account) => {
-                println!(
-                    "Account created with name {} and balance {}",
-                    account.name, account.balance
-                );
-                self.0.push(account);
-                Ok(())
-            },
-            Err(account) => Err(account.into()),
-        }
-    }
-
-    pub fn deposit(&mut self, name: &str, amount: &str) -> Result<()> {
-        let account = self.get_account_mut(name)?;
-
-        match account.deposit(amount.parse()?) {
-            Ok(account) => {
-                println!("Account balance is now {}", account.balance);
-                Ok(())
-            },
-            Err(account) => Err(account.into()),
-        }
-    }
-
-    pub fn withdraw(&mut self, name: &str, amount: &str) -> Result<()> {
-        let account = self.get_account_mut(name)?;
-
-        match account.withdraw(amount.parse()?) {
-            Ok(account) => {
-                println!("Account balance is now {}", account.balance);
-                Ok(())
-            },
-            Err(account) => Err(account.into()),
-        }
-    }
-
-    pub fn transfer(&mut self, from: &str, to: &str, amount: &str) -> Result<()> {
-        let mut cloned_system = self.clone();
-        let cloned_from = cloned_system.get_account_mut(from)?;
-        let orig_to = self.get_account_mut(to)?;
-        let amount = Cents::from_str(amount)?;
-
-        // HACK: To perform transfer atomically without needing two mutable references to self
-        // If withdrawal on cloned and actual deposit are successful, perform actual withdrawal on orig.
-        cloned_from.withdraw(amount)?;
-        orig_to.deposit(amount)?;
-
-        let from_balance = cloned_from.balance;
-        let to_balance = orig_to.balance;
-
-        self.get_account_mut(from)
-            .expect("from account should be found")
-            .withdraw(amount)
-            .expect("transfer withdrawal should succeed");
-
-        println!("{from} balance is now {from_balance}, {to} balance is now {to_balance}");
-
-        Ok(())
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::account::AccountError;
-
-    const DEFAULT_NAME: &str = "user";
-
-    #[test]
-    fn test_account_exists() {
-        let bs = BankingSystem(Vec::from([Account {
-            name: DEFAULT_NAME.to_owned(),
-            balance: Cents(20),
-        }]));
-
-        assert!(bs.account_exists(DEFAULT_NAME));
-        assert!(!bs.account_exists("user1"));
-    }
-
-    #[test]
-    fn test_get_account_mut() {
-        let account = Account {
-            name: DEFAULT_NAME.to_owned(),
-            balance: Cents(20),
-        };
-        let mut bs = BankingSystem(Vec::from([account]));
-
-        assert_eq!(bs.get_account_mut(DEFAULT_NAME).unwrap().name, DEFAULT_NAME);
-        assert_eq!(
-            bs.get_account_mut("user1"),
-            Err(BankingSystemError::AccountNotFound(String::from("user1")))
-        );
-    }
-
-    #[test]
-    fn test_create_duplicate_account_name() {
-        let mut bs = BankingSystem(Vec::from([Account {
-            name: DEFAULT_NAME.to_owned(),
-            balance: Cents(20),
-        }]));
-
-        assert_eq!(
-            bs.create(DEFAULT_NAME, "1000")
-                .unwrap_err()
-                .downcast::<BankingSystemError>()
-                .unwrap(),
-            BankingSystemError::DuplicateAccountName(DEFAULT_NAME.to_owned())
-        );
-    }
-
-    #[test]
-    fn test_create_account_success() {
-        let mut bs = BankingSystem(Vec::new());
-        bs.create(DEFAULT_NAME, "20").unwrap();
-
-        assert!(bs.account_exists(DEFAULT_NAME));
-    }
-
-    #[test]
-    fn test_create_account_failure() {
-        let mut bs = BankingSystem(Vec::new());
-
-        assert_eq!(
-            bs.create("", "20")
-                .unwrap_err()
-                .downcast::<AccountError>()
-                .unwrap(),
-            AccountError::EmptyAccountName
-        );
-    }
-
-    #[test]
-    fn test_deposit_success() {
-        let mut bs = BankingSystem(Vec::from([Account {
-            name: DEFAULT_NAME.to_owned(),
-            balance: Cents(20),
-        }]));
-        bs.deposit(DEFAULT_NAME, "20").unwrap();
-
-        assert_eq!(
-            bs.get_account_mut(DEFAULT_NAME).unwrap().balance,
-            Cents(2020)
-        );
-    }
-
-    #[test]
-    fn test_deposit_failure() {
-        let mut bs = BankingSystem(Vec::from([Account {
-            name: DEFAULT_NAME.to_owned(),
-            balance: Cents(u64::MAX),
-        }]));
-
-        assert_eq!(
-            bs.deposit(DEFAULT_NAME, "2")
-                .unwrap_err()
-                .downcast::<AccountError>()
-                .unwrap(),
-            AccountError::BalanceOverflow {
-                name: DEFAULT_NAME.to_owned(),
-                deposit_amount: Cents(200)
-            }
-        );
-    }
-
-    #[test]
-    fn test_withdraw_success() {
-        let mut bs = BankingSystem(Vec::from([Account {
-            name: DEFAULT_NAME.to_owned(),
-            balance: Cents(2000),
-        }]));
-        bs.withdraw(DEFAULT_NAME, "20").unwrap();
-
-        assert_eq!(bs.get_account_mut(DEFAULT_NAME).unwrap().balance, Cents(0));
-    }
-
-    #[test]
-    fn test_withdraw_failure() {
-        let mut bs = BankingSystem(Vec::from([Account {
-            name: DEFAULT_NAME.to_owned(),
-            balance: Cents(2),
-        }]));
-
-        assert_eq!(
-            bs.withdraw(DEFAULT_NAME, "2")
-                .unwrap_err()
-                .downcast::<AccountError>()
-                .unwrap(),
-            AccountError::AccountOverdraft {
-                name: DEFAULT_NAME.to_owned(),
-                balance: Cents(2),
-                withdraw_amount: Cents(200)
-            }
-        );
-    }
-
-    #[test]
-    fn test_transfer_success() {
-        let mut bs = BankingSystem(Vec::from([
-            Account {
-                name: String::from("user1"),
-                balance: Cents(2000),
-            },
-            Account {
-                name: String::from("user2"),
-                balance: Cents(1000),
-            },
-        ]));
-        bs.transfer("user1", "user2", "10").unwrap();
-
-        assert_eq!(bs.get_account_mut("user1").unwrap().balance, Cents(1000));
-        assert_eq!(bs.get_account_mut("user2").unwrap().balance, Cents(2000));
-    }
-
-    #[test]
-    fn test_transfer_failure() {
-        let mut bs = BankingSystem(Vec::from([
-            Account {
-                name: String::from("user1"),
-                balance: Cents(2000),
-            },
-            Account {
-                name: String::from("user2"),
-                balance: Cents(u64::MAX),
-            },
-        ]));
-
-        // test failed withdrawal
-        assert_eq!(
-            bs.transfer("user1", "user2", "30")
-                .unwrap_err()
-                .downcast::<AccountError>()
-                .unwrap(),
-            AccountError::AccountOverdraft {
-                name: String::from("user1"),
-                balance: Cents(2000),
-                withdraw_amount: Cents(3000)
-            }
-        );
-        assert_eq!(bs.get_account_mut("user1").unwrap().balance, Cents(2000));
-        assert_eq!(
-            bs.get_account_mut("user2").unwrap().balance,
-            Cents(u64::MAX)
-        );
-
-        // test failed deposit
-        assert_eq!(
-            bs.transfer("user1", "user2", "10")
-                .unwrap_err()
-                .downcast::<AccountError>()
-                .unwrap(),
-            AccountError::BalanceOverflow {
-                name: String::from("user2"),
-                deposit_amount: Cents(1000)
-            }
-        );
-        assert_eq!(bs.get_account_mut("user1").unwrap().balance, Cents(2000));
-        assert_eq!(
-            bs.get_account_mut("user2").unwrap().balance,
-            Cents(u64::MAX)
-        );
-    }
-}
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::account::{
+    normalize_name, Account, AccountError, AccountId, AccountStatus, Cents, SignedCents,
+};
+use crate::account_store::AccountStore;
+use crate::currency::{Currency, Money};
+use crate::exchange::{Conversion, ForeignExchange};
+use crate::fee::{Fee, FeeSchedule};
+use crate::hold::{Hold, HoldStatus};
+use crate::idempotency::IdempotencyKey;
+use crate::interest::{is_posting_date, Product, ACCRUAL_SCALE};
+use crate::journal::{Transaction, TransactionKind};
+use crate::ledger::{
+    Entry, Ledger, LedgerSavepoint, Side, TrialBalance, EXCHANGE_ACCOUNT, EXTERNAL_ACCOUNT,
+    FEE_ACCOUNT, HOUSE_ACCOUNT, INTEREST_ACCOUNT, SYSTEM_ACCOUNTS,
+};
+use crate::standing_order::{OrderRun, RunStatus, Schedule, StandingOrder};
+
+/// Ledger account a posting is made to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    /// Customer account, whose balance is updated by the posting.
+    Account(AccountId),
+    /// Account of the system itself, such as the external account, that only exists in the
+    /// ledger.
+    System(&'static str),
+}
+
+/// Posting to the external account.
+const EXTERNAL: Target = Target::System(EXTERNAL_ACCOUNT);
+
+/// Updated copies of accounts by their position in the account store.
+type StagedAccounts = Vec<(usize, Account)>;
+
+/// State of a system to return to with `BankingSystem::rollback`, undoing the transactions
+/// posted after it.
+#[derive(Debug)]
+pub(crate) struct Savepoint {
+    journal: usize,
+    accounts: usize,
+    last_id: AccountId,
+    modified: BTreeSet<AccountId>,
+    ledger: LedgerSavepoint,
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum BankingSystemError {
+    #[error("account with name {0} already exists")]
+    DuplicateAccountName(String),
+    #[error("account {0} not found")]
+    AccountNotFound(String),
+    #[error("account id {0} is stored more than once")]
+    DuplicateAccountId(AccountId),
+    #[error("no account ids are left to assign")]
+    AccountIdsExhausted,
+    #[error("invalid amount {0:?}, must be a non-negative number only containing digits up to the decimal places of its currency")]
+    InvalidAmount(String),
+    #[error("invalid amount {0:?}, must be a number with an optional leading sign only containing digits up to the decimal places of its currency")]
+    InvalidSignedAmount(String),
+    #[error("amount {0} would overflow")]
+    AmountOverflow(String),
+    #[error("invalid account id {0:?}, must be at least 8 digits ending in a valid check digit")]
+    InvalidAccountId(String),
+    #[error("account {0} cannot be swept into itself")]
+    SweepToSelf(String),
+    #[error("account {0} cannot have a standing order to itself")]
+    OrderToSelf(String),
+    #[error("standing order amount must be greater than zero")]
+    ZeroOrderAmount,
+    #[error("unknown currency {0:?}, must be an ISO 4217 currency code")]
+    UnknownCurrency(String),
+    #[error("account {from} in {from_currency} and account {to} in {to_currency} have different currencies")]
+    CurrencyMismatch {
+        from: String,
+        from_currency: Currency,
+        to: String,
+        to_currency: Currency,
+    },
+    #[error("invalid exchange rate {0:?}, must be a positive number with up to 9 decimal places")]
+    InvalidRate(String),
+    #[error("no exchange rate from {from} to {to} is in effect on {date}")]
+    NoExchangeRate {
+        from: Currency,
+        to: Currency,
+        date: NaiveDate,
+    },
+    #[error("invalid spread of {0} basis points, must be at most 10000")]
+    InvalidSpread(u32),
+    #[error("product {0} is not defined")]
+    UnknownProduct(String),
+    #[error("date {0} is in the future")]
+    FutureDate(NaiveDate),
+    #[error("fees of product {product} are invalid for accounts in {currency}: {reason}")]
+    InvalidFee {
+        product: String,
+        currency: Currency,
+        reason: Box<BankingSystemError>,
+    },
+    #[error("fee minimum {min} is more than the maximum {max}")]
+    FeeMinAboveMax { min: Money, max: Money },
+    #[error("hold {0} not found")]
+    HoldNotFound(u64),
+    #[error("hold {id} is {status} and can no longer be captured or released")]
+    HoldNotOpen { id: u64, status: HoldStatus },
+    #[error("cannot capture {amount} of hold {id}, only {held} is held")]
+    CaptureExceedsHold { id: u64, amount: Money, held: Money },
+    #[error("invalid schedule {0:?}, must be daily, weekly:<weekday> or monthly:<day of month>")]
+    InvalidSchedule(String),
+    #[error("standing order {0} not found")]
+    OrderNotFound(u64),
+    #[error("transfer needs an account to transfer to")]
+    MissingTransferTarget,
+    #[error("transaction has no debits or credits")]
+    EmptyTransaction,
+    #[error("transaction {0} not found")]
+    TransactionNotFound(u64),
+    #[error("transaction {id} was already reversed by transaction {by}")]
+    AlreadyReversed { id: u64, by: u64 },
+    #[error("transaction {id} is a {kind} and cannot be reversed")]
+    NotReversible { id: u64, kind: TransactionKind },
+    #[error(
+        "transaction debits {debits} but credits {credits}, it must balance in every currency"
+    )]
+    UnbalancedTransaction { debits: Money, credits: Money },
+    #[error("idempotency key {key:?} was already used for another request: {request}")]
+    IdempotencyKeyReused { key: String, request: String },
+}
+
+impl BankingSystemError {
+    /// Stable identifier of the error for machine-readable output.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::DuplicateAccountName(_) => "duplicate_account_name",
+            Self::AccountNotFound(_) => "account_not_found",
+            Self::DuplicateAccountId(_) => "duplicate_account_id",
+            Self::AccountIdsExhausted => "account_ids_exhausted",
+            Self::InvalidAmount(_) => "invalid_amount",
+            Self::InvalidSignedAmount(_) => "invalid_amount",
+            Self::AmountOverflow(_) => "amount_overflow",
+            Self::InvalidAccountId(_) => "invalid_account_id",
+            Self::SweepToSelf(_) => "sweep_to_self",
+            Self::OrderToSelf(_) => "order_to_self",
+            Self::ZeroOrderAmount => "invalid_amount",
+            Self::UnknownCurrency(_) => "unknown_currency",
+            Self::CurrencyMismatch { .. } => "currency_mismatch",
+            Self::InvalidRate(_) => "invalid_rate",
+            Self::NoExchangeRate { .. } => "no_exchange_rate",
+            Self::InvalidSpread(_) => "invalid_spread",
+            Self::UnknownProduct(_) => "unknown_product",
+            Self::FutureDate(_) => "future_date",
+            Self::InvalidFee { .. } => "invalid_fee",
+            Self::FeeMinAboveMax { .. } => "invalid_fee",
+            Self::HoldNotFound(_) => "hold_not_found",
+            Self::HoldNotOpen { .. } => "hold_not_open",
+            Self::CaptureExceedsHold { .. } => "capture_exceeds_hold",
+            Self::InvalidSchedule(_) => "invalid_schedule",
+            Self::OrderNotFound(_) => "order_not_found",
+            Self::MissingTransferTarget => "missing_transfer_target",
+            Self::EmptyTransaction => "empty_transaction",
+            Self::TransactionNotFound(_) => "transaction_not_found",
+            Self::AlreadyReversed { .. } => "already_reversed",
+            Self::NotReversible { .. } => "not_reversible",
+            Self::UnbalancedTransaction { .. } => "unbalanced_transaction",
+            Self::IdempotencyKeyReused { .. } => "idempotency_key_reused",
+        }
+    }
+}
+
+/// Check the fees of a product for an account in a currency.
+fn validate_fees(
+    name: &str,
+    product: &Product,
+    currency: Currency,
+) -> Result<(), BankingSystemError> {
+    product
+        .fees
+        .validate(currency)
+        .map_err(|reason| BankingSystemError::InvalidFee {
+            product: name.to_owned(),
+            currency,
+            reason: Box::new(reason),
+        })
+}
+
+/// Stable identifier of an error returned by an operation, `error` if it is neither an account
+/// error nor a banking system error.
+pub fn error_code(error: &anyhow::Error) -> &'static str {
+    if let Some(error) = error.downcast_ref::<AccountError>() {
+        error.code()
+    } else if let Some(error) = error.downcast_ref::<BankingSystemError>() {
+        error.code()
+    } else {
+        "error"
+    }
+}
+
+/// System to process user input and execute the specified command.
+///
+/// Every successful operation posts balanced debit and credit entries to the ledger and is recorded
+/// in the journal, so the history of any balance can be traced back. Deposits and withdrawals are
+/// posted against the external account.
+///
+/// Accounts are referred to by their id, both by operations and in the ledger. Names are only
+/// used to look up an id and can be changed.
+///
+/// Transfers between accounts in different currencies are only possible once a foreign exchange
+/// is set, and are posted through the exchange account.
+///
+/// Accounts with a savings product earn interest, which is accrued daily and posted monthly
+/// against the interest account. Withdrawals and transfers are charged the fees of the product of
+/// the account debited, which are posted to the fee account in the same transaction.
+///
+/// Holds reserve funds of an account without posting anything until they are captured.
+///
+/// Standing orders transfer between accounts on a schedule, and every instance that falls due is
+/// run once and recorded whether or not it could be transferred.
+///
+/// Operations run with an idempotency key are applied once, and retrying them with the same key
+/// returns the receipt of the first run.
+#[derive(Debug, Clone)]
+pub struct BankingSystem {
+    accounts: AccountStore,
+    journal: Vec<Transaction>,
+    ledger: Ledger,
+    /// Number of transactions at the start of the journal that have been persisted.
+    saved: usize,
+    /// Accounts changed since they were last persisted.
+    modified: BTreeSet<AccountId>,
+    exchange: Option<ForeignExchange>,
+    /// Savings products by name.
+    products: BTreeMap<String, Product>,
+    /// Every hold ever placed by id, including those no longer open.
+    holds: BTreeMap<u64, Hold>,
+    /// Holds changed since they were last persisted.
+    modified_holds: BTreeSet<u64>,
+    /// Every standing order by id, including those that finished.
+    orders: BTreeMap<u64, StandingOrder>,
+    /// Standing orders changed since they were last persisted.
+    modified_orders: BTreeSet<u64>,
+    /// Instances of standing orders run, in the order they were run.
+    runs: Vec<OrderRun>,
+    /// Number of runs at the start of `runs` that have been persisted.
+    saved_runs: usize,
+    /// Ids of reversed transactions with the ids of the reversals that reversed them.
+    reversals: BTreeMap<u64, u64>,
+    /// Idempotency keys of every request applied with one, by key.
+    idempotency_keys: BTreeMap<String, IdempotencyKey>,
+    /// Idempotency keys used since they were last persisted.
+    unsaved_keys: BTreeSet<String>,
+}
+
+impl BankingSystem {
+    /// Create a system from stored accounts and journal.
+    ///
+    /// Accounts without any entries in the journal get an opening balance transaction, so their
+    /// balance is accounted for by the ledger. Accounts sharing an id or a name are refused, as
+    /// dropping all but one of them would lose the others on the next save.
+    pub fn new(
+        accounts: Vec<Account>,
+        journal: Vec<Transaction>,
+    ) -> Result<Self, BankingSystemError> {
+        let ledger = Ledger::from_journal(&journal);
+        let reversals = journal
+            .iter()
+            .filter_map(|x| Some((x.reverses?, x.id)))
+            .collect();
+        let mut bs = Self {
+            accounts: AccountStore::with_capacity(accounts.len()),
+            saved: journal.len(),
+            journal,
+            ledger,
+            modified: BTreeSet::new(),
+            exchange: None,
+            products: BTreeMap::new(),
+            holds: BTreeMap::new(),
+            modified_holds: BTreeSet::new(),
+            orders: BTreeMap::new(),
+            modified_orders: BTreeSet::new(),
+            runs: Vec::new(),
+            saved_runs: 0,
+            reversals,
+            idempotency_keys: BTreeMap::new(),
+            unsaved_keys: BTreeSet::new(),
+        };
+
+        for account in accounts {
+            if bs.accounts.contains(account.id) {
+                return Err(BankingSystemError::DuplicateAccountId(account.id));
+            }
+            if bs.accounts.contains_name(&account.name) {
+                return Err(BankingSystemError::DuplicateAccountName(account.name));
+            }
+            if bs.ledger.contains(&account.id.to_string()) {
+                bs.accounts
+                    .insert(account)
+                    .expect("account id should not be in use");
+                continue;
+            }
+
+            let id = account.id;
+            let balance = account.balance;
+            let status = account.status;
+            let overdraft_limit = account.overdraft_limit;
+            let amount = account.money(balance.unsigned_abs());
+            // The opening balance is posted regardless of the status and limit of the account
+            bs.accounts
+                .insert(Account {
+                    balance: SignedCents::ZERO,
+                    status: AccountStatus::Open,
+                    overdraft_limit: Cents(u64::MAX),
+                    ..account
+                })
+                .expect("account id should not be in use");
+            let (external, side) = if balance.is_negative() {
+                (Side::Credit, Side::Debit)
+            } else {
+                (Side::Debit, Side::Credit)
+            };
+            bs.post(
+                TransactionKind::Opening,
+                &[
+                    (EXTERNAL, external, amount),
+                    (Target::Account(id), side, amount),
+                ],
+            )
+            .expect("opening balance should be posted to an empty account");
+            let account = bs
+                .accounts
+                .get_mut(id)
+                .expect("account should have been inserted");
+            account.status = status;
+            account.overdraft_limit = overdraft_limit;
+        }
+
+        Ok(bs)
+    }
+
+    /// Create a system from stored accounts that may be outdated or incomplete, such as a backup.
+    ///
+    /// Balances are restored from the ledger and accounts missing from the list are restored from
+    /// the journal. Names are not journaled, so restored accounts are named after their id.
+    pub fn recover(
+        mut accounts: Vec<Account>,
+        journal: Vec<Transaction>,
+    ) -> Result<Self, BankingSystemError> {
+        let ledger = Ledger::from_journal(&journal);
+        let mut ids = accounts.iter().map(|x| x.id).collect::<HashSet<_>>();
+
+        for entry in journal.iter().flat_map(|x| x.entries()) {
+            if let Ok(id) = entry.account.parse::<AccountId>() {
+                if ids.insert(id) {
+                    accounts.push(Account {
+                        id,
+                        name: id.to_string(),
+                        balance: SignedCents::ZERO,
+                        status: AccountStatus::Open,
+                        overdraft_limit: Cents::ZERO,
+                        currency: entry.currency,
+                        product: None,
+                        accrued_to: None,
+                        accrued_interest: 0,
+                        held: Cents::ZERO,
+                    });
+                }
+            }
+        }
+
+        for account in accounts.iter_mut() {
+            let code = account.id.to_string();
+            if !ledger.contains(&code) {
+                continue;
+            }
+            if let Ok(balance) = i64::try_from(ledger.balance(&code, account.currency)) {
+                account.balance = SignedCents(balance);
+            }
+        }
+
+        Self::new(accounts, journal)
+    }
+
+    /// Create a system from accounts stored by name only, before accounts had ids.
+    ///
+    /// Accounts are given ids in their stored order and journal entries posted to them by name
+    /// are changed to refer to them by id.
+    pub fn from_named_accounts(
+        accounts: Vec<(String, SignedCents)>,
+        mut journal: Vec<Transaction>,
+    ) -> Result<Self, BankingSystemError> {
+        let accounts = accounts
+            .into_iter()
+            .zip(1..)
+            .map(|((name, balance), id)| Account {
+                id: AccountId(id),
+                name: normalize_name(&name),
+                balance,
+                status: AccountStatus::Open,
+                overdraft_limit: Cents::ZERO,
+                currency: Currency::USD,
+                product: None,
+                accrued_to: None,
+                accrued_interest: 0,
+                held: Cents::ZERO,
+            })
+            .collect::<Vec<_>>();
+
+        let store = AccountStore::from(accounts.clone());
+        for entry in journal.iter_mut().flat_map(|x| x.entries.iter_mut()) {
+            if let Some(account) = store.find(&entry.account) {
+                entry.account = account.id.to_string();
+            }
+        }
+
+        Self::new(accounts, journal)
+    }
+
+    pub fn accounts(&self) -> &[Account] {
+        self.accounts.as_slice()
+    }
+
+    pub fn journal(&self) -> &[Transaction] {
+        &self.journal
+    }
+
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    pub fn exchange(&self) -> Option<&ForeignExchange> {
+        self.exchange.as_ref()
+    }
+
+    /// Convert transfers between accounts in different currencies with the exchange.
+    pub fn set_exchange(&mut self, exchange: ForeignExchange) {
+        self.exchange = Some(exchange);
+    }
+
+    pub fn products(&self) -> &BTreeMap<String, Product> {
+        &self.products
+    }
+
+    /// Define the savings products accounts can earn interest under, by name. The fees of the
+    /// products accounts are on must be valid in the currencies of those accounts, so a mistake
+    /// in them is found now rather than when a fee is charged.
+    pub fn set_products(
+        &mut self,
+        products: BTreeMap<String, Product>,
+    ) -> Result<(), BankingSystemError> {
+        for account in self.accounts() {
+            if let Some((name, product)) = account
+                .product
+                .as_ref()
+                .and_then(|x| products.get_key_value(x))
+            {
+                validate_fees(name, product, account.currency)?;
+            }
+        }
+        self.products = products;
+        Ok(())
+    }
+
+    pub fn holds(&self) -> impl Iterator<Item = &Hold> + '_ {
+        self.holds.values()
+    }
+
+    pub fn get_hold(&self, id: u64) -> Result<&Hold, BankingSystemError> {
+        self.holds
+            .get(&id)
+            .ok_or(BankingSystemError::HoldNotFound(id))
+    }
+
+    /// Restore stored holds, reserving the funds of those that are open.
+    pub fn set_holds(&mut self, holds: Vec<Hold>) {
+        let accounts = self.accounts().iter().map(|x| x.id).collect::<Vec<_>>();
+        for id in accounts {
+            self.accounts
+                .get_mut(id)
+                .expect("account should exist")
+                .held = Cents::ZERO;
+        }
+        self.holds = holds.into_iter().map(|x| (x.id, x)).collect();
+        for hold in self.holds.values() {
+            if let Some(account) = self.accounts.get_mut(hold.account) {
+                account.held = account
+                    .held
+                    .checked_add(hold.reserved())
+                    .unwrap_or(Cents(u64::MAX));
+            }
+        }
+    }
+
+    /// Holds changed since the system was loaded or last marked as saved.
+    pub fn modified_holds(&self) -> impl Iterator<Item = &Hold> + '_ {
+        self.modified_holds
+            .iter()
+            .filter_map(|id| self.holds.get(id))
+    }
+
+    pub fn orders(&self) -> impl Iterator<Item = &StandingOrder> + '_ {
+        self.orders.values()
+    }
+
+    pub fn get_order(&self, id: u64) -> Result<&StandingOrder, BankingSystemError> {
+        self.orders
+            .get(&id)
+            .ok_or(BankingSystemError::OrderNotFound(id))
+    }
+
+    /// Instances of standing orders run so far, in the order they were run.
+    pub fn runs(&self) -> &[OrderRun] {
+        &self.runs
+    }
+
+    /// Restore stored standing orders and the record of their runs.
+    ///
+    /// Transfers in the journal for instances missing from the runs, as the orders were not saved
+    /// after the transfer was, are recorded as executed runs and their orders moved on past them,
+    /// so an instance is never transferred twice.
+    pub fn set_orders(&mut self, orders: Vec<StandingOrder>, runs: Vec<OrderRun>) {
+        self.orders = orders.into_iter().map(|x| (x.id, x)).collect();
+        self.saved_runs = runs.len();
+        self.runs = runs;
+
+        let recorded = self
+            .runs
+            .iter()
+            .filter_map(|x| x.transaction_id)
+            .collect::<BTreeSet<_>>();
+        for transaction in &self.journal {
+            let Some((id, date)) = transaction.order else {
+                continue;
+            };
+            if recorded.contains(&transaction.id) {
+                continue;
+            }
+            self.runs.push(OrderRun {
+                order: id,
+                date,
+                status: RunStatus::Executed,
+                transaction_id: Some(transaction.id),
+                reason: None,
+                message: None,
+            });
+            if let Some(order) = self.orders.get_mut(&id) {
+                if order.next <= date {
+                    order.next = date;
+                    order.advance();
+                    self.modified_orders.insert(id);
+                }
+            }
+        }
+    }
+
+    /// Standing orders changed since the system was loaded or last marked as saved.
+    pub fn modified_orders(&self) -> impl Iterator<Item = &StandingOrder> + '_ {
+        self.modified_orders
+            .iter()
+            .filter_map(|id| self.orders.get(id))
+    }
+
+    pub fn idempotency_keys(&self) -> impl Iterator<Item = &IdempotencyKey> + '_ {
+        self.idempotency_keys.values()
+    }
+
+    /// Restore stored idempotency keys. Keys of requests whose transactions are missing from the
+    /// journal, as they were lost before being saved, are dropped so the requests can be retried.
+    pub fn set_idempotency_keys(&mut self, keys: Vec<IdempotencyKey>) {
+        let last = self.journal.last().map_or(0, |x| x.id);
+        self.idempotency_keys = keys
+            .into_iter()
+            .filter(|x| x.transaction_id.is_none_or(|id| id <= last))
+            .map(|x| (x.key.clone(), x))
+            .collect();
+    }
+
+    /// Idempotency keys used since the system was loaded or last marked as saved.
+    pub fn unsaved_idempotency_keys(&self) -> impl Iterator<Item = &IdempotencyKey> + '_ {
+        self.unsaved_keys
+            .iter()
+            .filter_map(|key| self.idempotency_keys.get(key))
+    }
+
+    /// Runs recorded since the system was loaded or last marked as saved.
+    pub fn unsaved_runs(&self) -> &[OrderRun] {
+        &self.runs[self.saved_runs..]
+    }
+
+    /// Transactions recorded since the system was loaded or last marked as saved.
+    pub fn unsaved_transactions(&self) -> &[Transaction] {
+        &self.journal[self.saved..]
+    }
+
+    /// Accounts changed since the system was loaded or last marked as saved, including renamed
+    /// accounts that no transaction was posted to.
+    pub fn modified_accounts(&self) -> impl Iterator<Item = &Account> + '_ {
+        self.modified.iter().filter_map(|&id| self.accounts.get(id))
+    }
+
+    /// Record the state to return to when the transactions posted after it are undone with
+    /// `rollback`.
+    pub(crate) fn savepoint(&self) -> Savepoint {
+        Savepoint {
+            journal: self.journal.len(),
+            accounts: self.accounts.len(),
+            last_id: self.accounts.last_id(),
+            modified: self.modified.clone(),
+            ledger: self.ledger.savepoint(),
+        }
+    }
+
+    /// Undo every transaction posted since the savepoint, restoring the balances of the accounts
+    /// they changed and removing the accounts they created. Only postings are undone, so nothing
+    /// else may be changed in between.
+    pub(crate) fn rollback(&mut self, savepoint: Savepoint) {
+        let undone = self.journal.split_off(savepoint.journal);
+        self.ledger.rollback(savepoint.ledger, &undone);
+        for transaction in undone.iter().rev() {
+            if let Some(id) = transaction.reverses {
+                self.reversals.remove(&id);
+            }
+            for entry in &transaction.entries {
+                let Some(balance) = entry.balance else {
+                    continue;
+                };
+                let id = entry
+                    .account
+                    .parse()
+                    .expect("entry with a balance should be posted to a customer account");
+                let account = self
+                    .accounts
+                    .get_mut(id)
+                    .expect("undone entry should be posted to an existing account");
+                account.balance = match entry.side {
+                    Side::Debit => balance.checked_add_cents(entry.amount),
+                    Side::Credit => balance.checked_sub_cents(entry.amount),
+                }
+                .expect("balance before an entry should be in range");
+            }
+        }
+        self.accounts
+            .truncate(savepoint.accounts, savepoint.last_id);
+        self.modified = savepoint.modified;
+    }
+
+    /// Mark every transaction in the journal and every account as persisted by storage.
+    pub fn mark_saved(&mut self) {
+        self.saved = self.journal.len();
+        self.modified.clear();
+        self.modified_holds.clear();
+        self.modified_orders.clear();
+        self.saved_runs = self.runs.len();
+        self.unsaved_keys.clear();
+    }
+
+    /// Transactions in the journal, optionally only those involving the account.
+    pub fn history(&self, id: Option<AccountId>) -> Result<Vec<&Transaction>> {
+        let code = id.map(|x| x.to_string());
+        if let Some(id) = id {
+            self.account_index(id)?;
+        }
+
+        Ok(self
+            .journal
+            .iter()
+            .filter(|x| code.as_deref().is_none_or(|code| x.involves(code)))
+            .collect())
+    }
+
+    /// Check that the ledger balances and every account balance matches its ledger balance.
+    pub fn trial_balance(&self) -> TrialBalance {
+        let mut trial_balance = self.ledger.trial_balance();
+        trial_balance.discrepancies = self
+            .accounts()
+            .iter()
+            .map(|x| (x.id.to_string(), x.currency, x.balance))
+            .filter(|(code, currency, balance)| {
+                self.ledger.balance(code, *currency) != balance.0 as i128
+            })
+            .map(|(code, _, _)| code)
+            .collect();
+        trial_balance
+    }
+
+    pub fn get_account(&self, id: AccountId) -> Result<&Account, BankingSystemError> {
+        self.accounts
+            .get(id)
+            .ok_or(BankingSystemError::AccountNotFound(id.to_string()))
+    }
+
+    /// Account with the name, compared after Unicode normalization.
+    pub fn find_account(&self, name: &str) -> Result<&Account, BankingSystemError> {
+        self.accounts
+            .find(name)
+            .ok_or(BankingSystemError::AccountNotFound(name.to_owned()))
+    }
+
+    /// Id of the account referred to by text, e.g. from the command line, which is either its id
+    /// or its name.
+    pub fn resolve(&self, account: &str) -> Result<AccountId, BankingSystemError> {
+        match account.parse::<AccountId>() {
+            Ok(id) if self.accounts.contains(id) => Ok(id),
+            _ => self.find_account(account).map(|x| x.id),
+        }
+    }
+
+    #[cfg(test)]
+    fn get_account_mut(&mut self, id: AccountId) -> Result<&mut Account, BankingSystemError> {
+        self.accounts
+            .get_mut(id)
+            .ok_or(BankingSystemError::AccountNotFound(id.to_string()))
+    }
+
+    fn account_index(&self, id: AccountId) -> Result<usize, BankingSystemError> {
+        self.accounts
+            .position(id)
+            .ok_or(BankingSystemError::AccountNotFound(id.to_string()))
+    }
+
+    /// Post balanced entries to the ledger and record them in the journal as one transaction.
+    ///
+    /// Entries are applied in order to copies of the affected accounts, so if any of them fails
+    /// the system is left untouched. Entries to system accounts only go to the ledger. Amounts
+    /// posted to an account must be in its currency.
+    fn post(
+        &mut self,
+        kind: TransactionKind,
+        postings: &[(Target, Side, Money)],
+    ) -> Result<Receipt> {
+        self.post_at(kind, postings, Utc::now())
+    }
+
+    /// Post a transaction like `post`, recorded with the time it takes effect instead of now.
+    fn post_at(
+        &mut self,
+        kind: TransactionKind,
+        postings: &[(Target, Side, Money)],
+        timestamp: DateTime<Utc>,
+    ) -> Result<Receipt> {
+        let (staged, entries) = self.stage(postings)?;
+        Ok(self.record(kind, staged, entries, timestamp, None))
+    }
+
+    /// Replace the accounts with their staged copies and record the entries as one transaction,
+    /// with the transaction it reverses if it is a reversal.
+    fn record(
+        &mut self,
+        kind: TransactionKind,
+        staged: StagedAccounts,
+        entries: Vec<Entry>,
+        timestamp: DateTime<Utc>,
+        reverses: Option<u64>,
+    ) -> Receipt {
+        debug_assert!(
+            entries.iter().all(|x| {
+                entries
+                    .iter()
+                    .filter(|y| y.currency == x.currency)
+                    .map(|y| match y.side {
+                        Side::Debit => y.amount.0 as i128,
+                        Side::Credit => -(y.amount.0 as i128),
+                    })
+                    .sum::<i128>()
+                    == 0
+            }),
+            "transaction should be balanced in every currency"
+        );
+
+        for (index, account) in staged {
+            self.modified.insert(account.id);
+            self.accounts.replace(index, account);
+        }
+
+        let transaction = Transaction {
+            id: self.journal.last().map_or(1, |x| x.id + 1),
+            timestamp,
+            kind,
+            entries,
+            reverses,
+            order: None,
+        };
+        if let Some(id) = reverses {
+            self.reversals.insert(id, transaction.id);
+        }
+        let receipt = Receipt::from(&transaction);
+        self.ledger.post(&transaction);
+        self.journal.push(transaction);
+
+        receipt
+    }
+
+    /// Apply postings to copies of the accounts they are made to, returning the updated accounts
+    /// with their positions and the entries to record, or the first error of any account.
+    fn stage(&self, postings: &[(Target, Side, Money)]) -> Result<(StagedAccounts, Vec<Entry>)> {
+        self.stage_with(postings, Account::withdraw)
+    }
+
+    /// Stage postings like `stage`, but check the overdraft limit of every account on the net
+    /// change of all its postings, so the result does not depend on their order.
+    fn stage_net(
+        &self,
+        postings: &[(Target, Side, Money)],
+    ) -> Result<(StagedAccounts, Vec<Entry>)> {
+        let (staged, entries) = self.stage_with(postings, Account::debit)?;
+        for (index, account) in &staged {
+            let original = &self.accounts.as_slice()[*index];
+            let net = original.balance.0 as i128 - account.balance.0 as i128;
+            if net > 0 {
+                let amount = Money::new(Cents(net as u64), original.currency);
+                original.clone().withdraw(amount)?;
+            }
+        }
+        Ok((staged, entries))
+    }
+
+    /// Stage postings with the function to debit an account by.
+    fn stage_with(
+        &self,
+        postings: &[(Target, Side, Money)],
+        debit: fn(&mut Account, Money) -> Result<&mut Account, AccountError>,
+    ) -> Result<(StagedAccounts, Vec<Entry>)> {
+        let mut staged = StagedAccounts::new();
+        let mut entries = Vec::with_capacity(postings.len());
+
+        for &(target, side, amount) in postings {
+            let id = match target {
+                Target::Account(id) => id,
+                Target::System(account) => {
+                    entries.push(Entry {
+                        account: account.to_owned(),
+                        side,
+                        amount: amount.amount,
+                        balance: None,
+                        currency: amount.currency,
+                    });
+                    continue;
+                },
+            };
+
+            let index = self.account_index(id)?;
+            let position = match staged.iter().position(|(i, _)| *i == index) {
+                Some(position) => position,
+                None => {
+                    staged.push((index, self.accounts.as_slice()[index].clone()));
+                    staged.len() - 1
+                },
+            };
+            let account = &mut staged[position].1;
+            match side {
+                Side::Debit => debit(account, amount)?,
+                Side::Credit => account.deposit(amount)?,
+            };
+
+            entries.push(Entry {
+                account: id.to_string(),
+                side,
+                amount: amount.amount,
+                balance: Some(account.balance),
+                currency: amount.currency,
+            });
+        }
+
+        Ok((staged, entries))
+    }
+
+    /// Create an account with the next id in the currency of its opening balance. The receipt
+    /// includes the id of the new account.
+    pub fn create(&mut self, name: &str, balance: Money) -> Result<Receipt> {
+        if self.accounts.contains_name(name) {
+            return Err(BankingSystemError::DuplicateAccountName(name.to_owned()).into());
+        }
+
+        let account = Account {
+            currency: balance.currency,
+            ..Account::new(
+                self.accounts
+                    .next_id()
+                    .ok_or(BankingSystemError::AccountIdsExhausted)?,
+                name,
+                SignedCents::ZERO,
+            )?
+        };
+        let id = account.id;
+        let (len, last_id) = (self.accounts.len(), self.accounts.last_id());
+        self.accounts
+            .insert(account)
+            .expect("next id should not be in use");
+        let receipt = self.post(
+            TransactionKind::Create,
+            &[
+                (EXTERNAL, Side::Debit, balance),
+                (Target::Account(id), Side::Credit, balance),
+            ],
+        );
+        // The account only exists once its opening balance is posted
+        if receipt.is_err() {
+            self.accounts.truncate(len, last_id);
+        }
+        receipt
+    }
+
+    pub fn deposit(&mut self, id: AccountId, amount: Money) -> Result<Receipt> {
+        self.post(
+            TransactionKind::Deposit,
+            &[
+                (EXTERNAL, Side::Debit, amount),
+                (Target::Account(id), Side::Credit, amount),
+            ],
+        )
+    }
+
+    /// Withdraw from an account, charging the withdrawal fee of its product if it has one.
+    pub fn withdraw(&mut self, id: AccountId, amount: Money) -> Result<Receipt> {
+        let fee = self.fee(id, amount, |x| x.withdraw.as_ref())?;
+        self.post_with_fee(
+            TransactionKind::Withdraw,
+            Vec::from([
+                (Target::Account(id), Side::Debit, amount),
+                (EXTERNAL, Side::Credit, amount),
+            ]),
+            fee,
+        )
+    }
+
+    /// Transfer between accounts, charging the transfer fee of the product of the account
+    /// transferred from if it has one. An amount transferred to an account in another currency is
+    /// converted by the foreign exchange at the rate in effect today, and the receipt includes
+    /// the conversion.
+    pub fn transfer(&mut self, from: AccountId, to: AccountId, amount: Money) -> Result<Receipt> {
+        let to_currency = self.get_account(to)?.currency;
+        let fee = self.fee(from, amount, |x| x.transfer.as_ref())?;
+        if self.get_account(from)?.currency != to_currency {
+            if let Some(exchange) = &self.exchange {
+                let conversion = exchange.convert(amount, to_currency, Utc::now().date_naive())?;
+                return self.exchange_transfer(from, to, conversion, fee);
+            }
+            self.check_same_currency(from, to)?;
+        }
+
+        self.post_with_fee(
+            TransactionKind::Transfer,
+            Vec::from([
+                (Target::Account(from), Side::Debit, amount),
+                (Target::Account(to), Side::Credit, amount),
+            ]),
+            fee,
+        )
+    }
+
+    /// Id of the reversal of a transaction, if it was reversed.
+    pub fn reversed_by(&self, id: u64) -> Option<u64> {
+        self.reversals.get(&id).copied()
+    }
+
+    /// Undo a transaction by posting a reversal linked to it, with every debit and credit of the
+    /// original swapped. A transaction can only be reversed once, and reversals, opening balances
+    /// and transactions reversing which would overdraft an account or post to a closed one are
+    /// refused.
+    pub fn reverse(&mut self, id: u64) -> Result<Receipt> {
+        let transaction = self
+            .journal
+            .iter()
+            .find(|x| x.id == id)
+            .ok_or(BankingSystemError::TransactionNotFound(id))?;
+        if let Some(by) = self.reversed_by(id) {
+            return Err(BankingSystemError::AlreadyReversed { id, by }.into());
+        }
+        let not_reversible = || BankingSystemError::NotReversible {
+            id,
+            kind: transaction.kind,
+        };
+        if matches!(
+            transaction.kind,
+            TransactionKind::Opening | TransactionKind::Reversal
+        ) {
+            return Err(not_reversible().into());
+        }
+
+        let postings = transaction
+            .entries
+            .iter()
+            .map(|entry| {
+                let target = match SYSTEM_ACCOUNTS.iter().find(|x| **x == entry.account) {
+                    Some(account) => Target::System(account),
+                    None => Target::Account(entry.account.parse().map_err(|_| not_reversible())?),
+                };
+                let side = match entry.side {
+                    Side::Debit => Side::Credit,
+                    Side::Credit => Side::Debit,
+                };
+                Ok((target, side, Money::new(entry.amount, entry.currency)))
+            })
+            .collect::<Result<Vec<_>, BankingSystemError>>()?;
+        // Like the transaction builder, a settlement may debit an account before crediting it
+        let (staged, entries) = self.stage_net(&postings)?;
+        Ok(self.record(
+            TransactionKind::Reversal,
+            staged,
+            entries,
+            Utc::now(),
+            Some(id),
+        ))
+    }
+
+    /// Run an operation at most once for an idempotency key, e.g. for a request a client may retry
+    /// after a timeout. Without a key the operation is simply run.
+    ///
+    /// The first time the operation succeeds, the key is recorded with the request and the
+    /// outcome. Running the same request with the key again returns that outcome without applying
+    /// anything, and running a different one is refused. Failures are not recorded, so a request
+    /// that failed can be retried with its key. The request names the operation and its
+    /// parameters, e.g. `deposit 00000018 $10.00`.
+    pub fn idempotent<T: Serialize + DeserializeOwned>(
+        &mut self,
+        key: Option<&str>,
+        request: &str,
+        op: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        let Some(key) = key else {
+            return op(self);
+        };
+        if let Some(seen) = self.idempotency_keys.get(key) {
+            if seen.request != request {
+                return Err(BankingSystemError::IdempotencyKeyReused {
+                    key: key.to_owned(),
+                    request: seen.request.clone(),
+                }
+                .into());
+            }
+            return Ok(serde_json::from_str(&seen.response)?);
+        }
+
+        let start = self.journal.len();
+        let outcome = op(self)?;
+        self.idempotency_keys.insert(
+            key.to_owned(),
+            IdempotencyKey {
+                key: key.to_owned(),
+                request: request.to_owned(),
+                transaction_id: self.journal[start..].last().map(|x| x.id),
+                response: serde_json::to_string(&outcome)?,
+                used: Utc::now(),
+            },
+        );
+        self.unsaved_keys.insert(key.to_owned());
+        Ok(outcome)
+    }
+
+    /// Start a transaction of any number of debits and credits, which are staged on the builder and
+    /// posted together as a single `settlement` transaction when it is committed.
+    pub fn transaction(&mut self) -> TransactionBuilder<'_> {
+        TransactionBuilder {
+            bs: self,
+            postings: Vec::new(),
+        }
+    }
+
+    /// Fee charged to an account for an operation on an amount, according to the fee schedule of
+    /// its product. None if the account has no product, or its product charges nothing.
+    fn fee(
+        &self,
+        id: AccountId,
+        amount: Money,
+        fee: impl Fn(&FeeSchedule) -> Option<&Fee>,
+    ) -> Result<Option<Money>> {
+        let Some(product) = &self.get_account(id)?.product else {
+            return Ok(None);
+        };
+        let product = self
+            .products
+            .get(product)
+            .ok_or_else(|| BankingSystemError::UnknownProduct(product.clone()))?;
+        match fee(&product.fees) {
+            Some(fee) => Ok(Some(fee.charge(amount)?).filter(|x| x.amount != Cents::ZERO)),
+            None => Ok(None),
+        }
+    }
+
+    /// Post a transaction whose first posting debits the account charged a fee. The fee is added
+    /// to that debit and credited to the fee account, so it is charged in the same transaction as
+    /// the operation. The receipt shows the amount of the operation and the fee separately.
+    fn post_with_fee(
+        &mut self,
+        kind: TransactionKind,
+        mut postings: Vec<(Target, Side, Money)>,
+        fee: Option<Money>,
+    ) -> Result<Receipt> {
+        let amount = postings[0].2;
+        if let Some(fee) = fee {
+            let charged = amount
+                .amount
+                .checked_add(fee.amount)
+                .ok_or_else(|| BankingSystemError::AmountOverflow(amount.to_string()))?;
+            postings[0].2 = Money::new(charged, amount.currency);
+            postings.push((Target::System(FEE_ACCOUNT), Side::Credit, fee));
+        }
+
+        let mut receipt = self.post(kind, &postings)?;
+        receipt.amount = amount;
+        receipt.fee = fee;
+        Ok(receipt)
+    }
+
+    /// Correct the balance of an account by a signed amount, e.g. to reverse a fee or a credit
+    /// made in error. A negative adjustment is a debit and is subject to the overdraft limit.
+    pub fn adjust(&mut self, id: AccountId, amount: Money<SignedCents>) -> Result<Receipt> {
+        let (external, side) = if amount.amount.is_negative() {
+            (Side::Credit, Side::Debit)
+        } else {
+            (Side::Debit, Side::Credit)
+        };
+        let amount = Money::new(amount.amount.unsigned_abs(), amount.currency);
+        self.post(
+            TransactionKind::Adjustment,
+            &[
+                (EXTERNAL, external, amount),
+                (Target::Account(id), side, amount),
+            ],
+        )
+    }
+
+    /// Post a transfer converted between currencies. The exchange account takes the source amount
+    /// and pays out the converted amount, of which the spread goes to the house account. A fee is
+    /// charged in the source currency.
+    fn exchange_transfer(
+        &mut self,
+        from: AccountId,
+        to: AccountId,
+        conversion: Conversion,
+        fee: Option<Money>,
+    ) -> Result<Receipt> {
+        let exchange = Target::System(EXCHANGE_ACCOUNT);
+        let converted = Money::new(
+            conversion
+                .destination
+                .amount
+                .checked_add(conversion.spread.amount)
+                .ok_or_else(|| BankingSystemError::AmountOverflow(conversion.to_string()))?,
+            conversion.destination.currency,
+        );
+        let mut postings = Vec::from([
+            (Target::Account(from), Side::Debit, conversion.source),
+            (exchange, Side::Credit, conversion.source),
+            (exchange, Side::Debit, converted),
+            (Target::Account(to), Side::Credit, conversion.destination),
+        ]);
+        if conversion.spread.amount != Cents::ZERO {
+            postings.push((
+                Target::System(HOUSE_ACCOUNT),
+                Side::Credit,
+                conversion.spread,
+            ));
+        }
+
+        let mut receipt = self.post_with_fee(TransactionKind::Transfer, postings, fee)?;
+        receipt.conversion = Some(conversion);
+        Ok(receipt)
+    }
+
+    /// Check that money can be moved between two accounts without conversion.
+    fn check_same_currency(&self, from: AccountId, to: AccountId) -> Result<Currency> {
+        let from = self.get_account(from)?;
+        let to = self.get_account(to)?;
+        if from.currency != to.currency {
+            return Err(BankingSystemError::CurrencyMismatch {
+                from: from.name.clone(),
+                from_currency: from.currency,
+                to: to.name.clone(),
+                to_currency: to.currency,
+            }
+            .into());
+        }
+        Ok(from.currency)
+    }
+
+    /// Change the name of an account. Its id and history are unaffected.
+    pub fn rename(&mut self, id: AccountId, name: &str) -> Result<&Account> {
+        self.account_index(id)?;
+        let name = Account::validate_name(name)?;
+        if self.accounts.find(&name).is_some_and(|x| x.id != id) {
+            return Err(BankingSystemError::DuplicateAccountName(name).into());
+        }
+
+        self.accounts
+            .rename(id, name)
+            .expect("account should exist");
+        self.modified.insert(id);
+        Ok(self.get_account(id)?)
+    }
+
+    /// Change the status of an account that is not closed.
+    fn set_status(&mut self, id: AccountId, status: AccountStatus) -> Result<&Account> {
+        let index = self.account_index(id)?;
+        let account = &self.accounts.as_slice()[index];
+        if account.status == AccountStatus::Closed {
+            return Err(AccountError::AccountClosed {
+                name: account.name.clone(),
+            }
+            .into());
+        }
+
+        self.accounts
+            .get_mut(id)
+            .expect("account should exist")
+            .status = status;
+        self.modified.insert(id);
+        Ok(self.get_account(id)?)
+    }
+
+    /// Block debits from an account, e.g. because it was compromised.
+    pub fn freeze(&mut self, id: AccountId) -> Result<&Account> {
+        self.set_status(id, AccountStatus::Frozen)
+    }
+
+    /// Reopen a frozen or dormant account.
+    pub fn unfreeze(&mut self, id: AccountId) -> Result<&Account> {
+        self.set_status(id, AccountStatus::Open)
+    }
+
+    /// Block debits from an account that has been inactive, until it is reopened with `unfreeze`.
+    pub fn mark_dormant(&mut self, id: AccountId) -> Result<&Account> {
+        self.set_status(id, AccountStatus::Dormant)
+    }
+
+    /// Set how far the balance of an account may go below zero, in the currency of the account.
+    ///
+    /// Lowering the limit below an existing overdraft is allowed, the account then cannot be
+    /// debited until the balance is back within the limit. Like balances, the limit cannot be
+    /// more than `i64::MAX` cents.
+    pub fn set_overdraft_limit(&mut self, id: AccountId, limit: Cents) -> Result<&Account> {
+        let account = self.get_account(id)?;
+        if account.status == AccountStatus::Closed {
+            return Err(AccountError::AccountClosed {
+                name: account.name.clone(),
+            }
+            .into());
+        }
+        if i64::try_from(limit.0).is_err() {
+            return Err(
+                BankingSystemError::AmountOverflow(account.money(limit).to_string()).into(),
+            );
+        }
+
+        self.accounts
+            .get_mut(id)
+            .expect("account should exist")
+            .overdraft_limit = limit;
+        self.modified.insert(id);
+        Ok(self.get_account(id)?)
+    }
+
+    /// Reserve funds of an account until a time, reducing its available balance but not its
+    /// ledger balance. The funds must be available and the account must be allowed to be debited.
+    pub fn hold(&mut self, id: AccountId, amount: Money, expires: DateTime<Utc>) -> Result<&Hold> {
+        self.account_index(id)?;
+        self.accounts
+            .get_mut(id)
+            .expect("account should exist")
+            .hold(amount)?;
+        self.modified.insert(id);
+
+        let hold_id = self.holds.last_key_value().map_or(1, |(id, _)| id + 1);
+        self.holds.insert(
+            hold_id,
+            Hold {
+                id: hold_id,
+                account: id,
+                amount: amount.amount,
+                captured: Cents::ZERO,
+                currency: amount.currency,
+                status: HoldStatus::Open,
+                placed: Utc::now(),
+                expires,
+            },
+        );
+        self.modified_holds.insert(hold_id);
+        Ok(self.get_hold(hold_id)?)
+    }
+
+    /// Hold that can still be captured or released.
+    fn open_hold(&self, id: u64) -> Result<Hold> {
+        let hold = self.get_hold(id)?;
+        let status = if hold.is_expired(Utc::now()) {
+            HoldStatus::Expired
+        } else {
+            hold.status
+        };
+        if status != HoldStatus::Open {
+            return Err(BankingSystemError::HoldNotOpen { id, status }.into());
+        }
+        Ok(hold.clone())
+    }
+
+    /// Close an open hold, returning the funds it reserved to the available balance.
+    fn close_hold(&mut self, id: u64, status: HoldStatus, captured: Cents) {
+        let hold = self.holds.get_mut(&id).expect("hold should exist");
+        if let Some(account) = self.accounts.get_mut(hold.account) {
+            account.held = account
+                .held
+                .checked_sub(hold.reserved())
+                .unwrap_or_default();
+            self.modified.insert(hold.account);
+        }
+        hold.status = status;
+        hold.captured = captured;
+        self.modified_holds.insert(id);
+    }
+
+    /// Pay out the funds reserved by a hold, in full or only the given amount, with the rest
+    /// released. The amount is posted like a withdrawal, and the hold cannot be used again.
+    pub fn capture(&mut self, id: u64, amount: Option<Money>) -> Result<Receipt> {
+        let hold = self.open_hold(id)?;
+        let amount = amount.unwrap_or(hold.amount());
+        if amount.currency == hold.currency && amount.amount > hold.amount {
+            return Err(BankingSystemError::CaptureExceedsHold {
+                id,
+                amount,
+                held: hold.amount(),
+            }
+            .into());
+        }
+
+        // The funds are released before posting, so they count as available for the capture
+        let account =
+            self.accounts
+                .get_mut(hold.account)
+                .ok_or(BankingSystemError::AccountNotFound(
+                    hold.account.to_string(),
+                ))?;
+        let held = account.held;
+        account.held = held.checked_sub(hold.amount).unwrap_or_default();
+        let receipt = self.post(
+            TransactionKind::Capture,
+            &[
+                (Target::Account(hold.account), Side::Debit, amount),
+                (EXTERNAL, Side::Credit, amount),
+            ],
+        );
+        let account = self
+            .accounts
+            .get_mut(hold.account)
+            .expect("account should exist");
+        account.held = held;
+        let receipt = receipt?;
+
+        self.close_hold(id, HoldStatus::Captured, amount.amount);
+        Ok(receipt)
+    }
+
+    /// Cancel a hold, returning the funds it reserved to the available balance.
+    pub fn release(&mut self, id: u64) -> Result<&Hold> {
+        self.open_hold(id)?;
+        self.close_hold(id, HoldStatus::Released, Cents::ZERO);
+        Ok(self.get_hold(id)?)
+    }
+
+    /// Release every open hold that expired by a time, returning their ids.
+    pub fn expire_holds(&mut self, now: DateTime<Utc>) -> Vec<u64> {
+        let expired = self
+            .holds
+            .values()
+            .filter(|x| x.is_expired(now))
+            .map(|x| x.id)
+            .collect::<Vec<_>>();
+        for &id in &expired {
+            self.close_hold(id, HoldStatus::Expired, Cents::ZERO);
+        }
+        expired
+    }
+
+    /// Set up a standing order transferring an amount from one account to another on a schedule,
+    /// with the first instance due on or after the start date and the last one on or before the
+    /// end date if one is given.
+    pub fn create_order(
+        &mut self,
+        from: AccountId,
+        to: AccountId,
+        amount: Money,
+        schedule: Schedule,
+        start: NaiveDate,
+        end: Option<NaiveDate>,
+    ) -> Result<&StandingOrder> {
+        if from == to {
+            return Err(BankingSystemError::OrderToSelf(from.to_string()).into());
+        }
+        if amount.amount == Cents::ZERO {
+            return Err(BankingSystemError::ZeroOrderAmount.into());
+        }
+        self.get_account(from)?.check_currency(amount)?;
+        for id in [from, to] {
+            let account = self.get_account(id)?;
+            if account.status == AccountStatus::Closed {
+                return Err(AccountError::AccountClosed {
+                    name: account.name.clone(),
+                }
+                .into());
+            }
+        }
+
+        let id = self.orders.last_key_value().map_or(1, |(id, _)| id + 1);
+        self.orders.insert(
+            id,
+            StandingOrder {
+                id,
+                from,
+                to,
+                amount: amount.amount,
+                currency: amount.currency,
+                schedule,
+                next: schedule.next_due(start).unwrap_or(NaiveDate::MAX),
+                end,
+            },
+        );
+        self.modified_orders.insert(id);
+        Ok(self.get_order(id)?)
+    }
+
+    /// Run every instance of the standing orders due on or before a date that has not been run
+    /// yet, oldest first, transferring each like [`BankingSystem::transfer`]. Instances that cannot
+    /// be transferred are recorded as skipped or failed with the reason and not tried again.
+    /// Instances can only be run up to today, as instances due later must not be paid early.
+    pub fn run_due(&mut self, as_of: NaiveDate) -> Result<Vec<OrderRun>> {
+        if as_of > Utc::now().date_naive() {
+            return Err(BankingSystemError::FutureDate(as_of).into());
+        }
+        let start = self.runs.len();
+        while let Some((id, date)) = self
+            .orders
+            .values()
+            .filter_map(|x| x.due(as_of).map(|date| (x.id, date)))
+            .min_by_key(|(id, date)| (*date, *id))
+        {
+            let order = self.orders[&id].clone();
+            let run = match self.transfer(order.from, order.to, order.amount()) {
+                Ok(receipt) => {
+                    // Recorded with the transfer, so the instance is known to have run even if the
+                    // order and its runs are not saved after it
+                    self.journal
+                        .last_mut()
+                        .expect("transfer should be in the journal")
+                        .order = Some((id, date));
+                    OrderRun {
+                        order: id,
+                        date,
+                        status: RunStatus::Executed,
+                        transaction_id: Some(receipt.transaction_id()),
+                        reason: None,
+                        message: None,
+                    }
+                },
+                Err(error) => {
+                    let status = match error.downcast_ref::<AccountError>() {
+                        Some(
+                            AccountError::AccountFrozen { .. }
+                            | AccountError::AccountDormant { .. }
+                            | AccountError::AccountClosed { .. },
+                        ) => RunStatus::Skipped,
+                        _ => RunStatus::Failed,
+                    };
+                    OrderRun {
+                        order: id,
+                        date,
+                        status,
+                        transaction_id: None,
+                        reason: Some(error_code(&error).to_owned()),
+                        message: Some(error.to_string()),
+                    }
+                },
+            };
+
+            self.runs.push(run);
+            self.orders
+                .get_mut(&id)
+                .expect("order should exist")
+                .advance();
+            self.modified_orders.insert(id);
+        }
+        Ok(self.runs[start..].to_vec())
+    }
+
+    /// Have an account earn interest under a savings product from a date on, or stop earning
+    /// interest if no product is given.
+    ///
+    /// Days not accrued yet when the product of an account is changed earn interest under the new
+    /// product. Interest accrued but not yet posted when the product is removed is kept, and
+    /// posted once the account has a product again.
+    pub fn set_product(
+        &mut self,
+        id: AccountId,
+        product: Option<&str>,
+        from: NaiveDate,
+    ) -> Result<&Account> {
+        let account = self.get_account(id)?;
+        if account.status == AccountStatus::Closed {
+            return Err(AccountError::AccountClosed {
+                name: account.name.clone(),
+            }
+            .into());
+        }
+        if let Some(name) = product {
+            let product = self
+                .products
+                .get(name)
+                .ok_or_else(|| BankingSystemError::UnknownProduct(name.to_owned()))?;
+            validate_fees(name, product, account.currency)?;
+        }
+
+        let account = self.accounts.get_mut(id).expect("account should exist");
+        account.accrued_to = match (&account.product, product) {
+            (_, None) => None,
+            (Some(_), Some(_)) if account.accrued_to.is_some() => account.accrued_to,
+            (_, Some(_)) => from.pred_opt(),
+        };
+        account.product = product.map(str::to_owned);
+        self.modified.insert(id);
+        Ok(self.get_account(id)?)
+    }
+
+    /// Accrue interest on every account with a savings product for each day up to and including
+    /// a date, and post the interest of every month that ended. Returns the receipts of the
+    /// interest posted.
+    ///
+    /// Days already accrued are skipped, so accruing up to the same date again changes nothing.
+    /// Interest can only be accrued up to today, as it is not earned before the day has passed.
+    ///
+    /// Interest is recorded in the journal with the end of the day it was posted for as its
+    /// timestamp, which is earlier than the transactions before it when interest is accrued late.
+    /// The journal stays in the order transactions were posted, by id.
+    pub fn accrue(&mut self, as_of: NaiveDate) -> Result<Vec<Receipt>> {
+        if as_of > Utc::now().date_naive() {
+            return Err(BankingSystemError::FutureDate(as_of).into());
+        }
+        let ids = self
+            .accounts()
+            .iter()
+            .filter(|x| x.product.is_some())
+            .map(|x| x.id)
+            .collect::<Vec<_>>();
+
+        // Changes of the balances of those accounts, gathered in a single pass over the journal
+        let mut changes = ids
+            .iter()
+            .map(|id| (id.to_string(), Vec::new()))
+            .collect::<HashMap<_, _>>();
+        for transaction in &self.journal {
+            let date = transaction.timestamp.date_naive();
+            for entry in &transaction.entries {
+                if let Some(changes) = changes.get_mut(&entry.account) {
+                    changes.push(match entry.side {
+                        Side::Debit => (date, -(entry.amount.0 as i128)),
+                        Side::Credit => (date, entry.amount.0 as i128),
+                    });
+                }
+            }
+        }
+
+        let mut receipts = Vec::new();
+        for id in ids {
+            let changes = changes.remove(&id.to_string()).unwrap_or_default();
+            receipts.extend(self.accrue_account(id, as_of, changes)?);
+        }
+        Ok(receipts)
+    }
+
+    /// Accrue interest on an account from the day after it was last accrued, given the changes of
+    /// its balance in the journal with the day they were recorded.
+    ///
+    /// Interest is accrued on the balance at the end of each day, going by the time transactions
+    /// were recorded in the journal. The interest of a month is posted as of the end of its last
+    /// day, so it earns interest from the next day on, and the fraction of a minor unit left over
+    /// is carried into the next month.
+    fn accrue_account(
+        &mut self,
+        id: AccountId,
+        as_of: NaiveDate,
+        mut changes: Vec<(NaiveDate, i128)>,
+    ) -> Result<Vec<Receipt>> {
+        let account = self.get_account(id)?;
+        let (Some(product), Some(accrued_to)) = (&account.product, account.accrued_to) else {
+            return Ok(Vec::new());
+        };
+        if accrued_to >= as_of || account.status == AccountStatus::Closed {
+            return Ok(Vec::new());
+        }
+        let product = self
+            .products
+            .get(product)
+            .cloned()
+            .ok_or_else(|| BankingSystemError::UnknownProduct(product.clone()))?;
+        let currency = account.currency;
+        let mut accrued = u128::from(account.accrued_interest);
+
+        // Interest accrued late is recorded out of order, so the changes are sorted by day
+        changes.sort_by_key(|(date, _)| *date);
+        let mut changes = changes.into_iter().peekable();
+
+        let mut receipts = Vec::new();
+        let mut balance = 0i128;
+        let mut date = accrued_to;
+        while date < as_of {
+            let day = date
+                .succ_opt()
+                .expect("day before another should have a successor");
+            while let Some((_, change)) = changes.next_if(|(recorded, _)| *recorded <= day) {
+                balance += change;
+            }
+            let end_of_day = balance.clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+            accrued += product.interest(end_of_day, date, day);
+            date = day;
+
+            let posted = accrued / ACCRUAL_SCALE;
+            if !is_posting_date(day) || posted == 0 {
+                continue;
+            }
+            let amount = Money::new(
+                Cents(
+                    u64::try_from(posted)
+                        .map_err(|_| BankingSystemError::AmountOverflow(posted.to_string()))?,
+                ),
+                currency,
+            );
+            let timestamp = day
+                .and_hms_opt(23, 59, 59)
+                .expect("end of day should be a valid time")
+                .and_utc();
+            receipts.push(self.post_at(
+                TransactionKind::Interest,
+                &[
+                    (Target::System(INTEREST_ACCOUNT), Side::Debit, amount),
+                    (Target::Account(id), Side::Credit, amount),
+                ],
+                timestamp,
+            )?);
+            balance += posted as i128;
+            accrued -= posted * ACCRUAL_SCALE;
+            // Recorded after every posting, so a later failure cannot post the interest twice
+            self.set_accrued(id, day, accrued)?;
+        }
+
+        self.set_accrued(id, as_of, accrued)?;
+        Ok(receipts)
+    }
+
+    fn set_accrued(&mut self, id: AccountId, accrued_to: NaiveDate, accrued: u128) -> Result<()> {
+        let accrued = u64::try_from(accrued)
+            .map_err(|_| BankingSystemError::AmountOverflow(accrued.to_string()))?;
+        let account = self.accounts.get_mut(id).expect("account should exist");
+        account.accrued_to = Some(accrued_to);
+        account.accrued_interest = accrued;
+        self.modified.insert(id);
+        Ok(())
+    }
+
+    /// Close an account, after which nothing can be posted to it.
+    ///
+    /// The balance must be zero unless an account to sweep it into is given, in which case the
+    /// balance is transferred there first and the receipt of the transfer returned.
+    pub fn close(&mut self, id: AccountId, sweep_to: Option<AccountId>) -> Result<Option<Receipt>> {
+        let account = self.get_account(id)?;
+        if account.status == AccountStatus::Closed {
+            return Err(AccountError::AccountClosed {
+                name: account.name.clone(),
+            }
+            .into());
+        }
+        let balance = account.balance;
+        let amount = account.money(balance.unsigned_abs());
+
+        if let Some(to) = sweep_to {
+            if to == id {
+                return Err(BankingSystemError::SweepToSelf(id.to_string()).into());
+            }
+            self.check_same_currency(id, to)?;
+        }
+
+        let receipt = match sweep_to {
+            // An overdrawn balance is settled from the account swept into
+            Some(to) if balance.is_negative() => Some(self.post(
+                TransactionKind::Close,
+                &[
+                    (Target::Account(to), Side::Debit, amount),
+                    (Target::Account(id), Side::Credit, amount),
+                ],
+            )?),
+            Some(to) if balance != SignedCents::ZERO => Some(self.post(
+                TransactionKind::Close,
+                &[
+                    (Target::Account(id), Side::Debit, amount),
+                    (Target::Account(to), Side::Credit, amount),
+                ],
+            )?),
+            Some(_) => None,
+            None if balance != SignedCents::ZERO => {
+                return Err(AccountError::NonZeroBalance {
+                    name: account.name.clone(),
+                    balance: account.money(balance),
+                }
+                .into());
+            },
+            None => None,
+        };
+
+        self.set_status(id, AccountStatus::Closed)?;
+        Ok(receipt)
+    }
+
+    /// Create an account with a balance given as text, e.g. from the command line. The balance
+    /// may name the currency of the account, as in "10.50 EUR", which is otherwise USD.
+    pub fn create_str(&mut self, name: &str, balance: &str) -> Result<Receipt> {
+        if self.accounts.contains_name(name) {
+            return Err(BankingSystemError::DuplicateAccountName(name.to_owned()).into());
+        }
+        self.create(name, Money::parse(balance, Currency::USD)?)
+    }
+
+    /// Parse an amount given as text in the currency of an account, unless it names another.
+    fn parse_money(&self, id: AccountId, amount: &str) -> Result<Money> {
+        Ok(Money::parse(amount, self.get_account(id)?.currency)?)
+    }
+
+    /// Deposit an amount given as text to an account given by id or name, e.g. from the command
+    /// line.
+    pub fn deposit_str(&mut self, account: &str, amount: &str) -> Result<Receipt> {
+        let id = self.resolve(account)?;
+        self.deposit(id, self.parse_money(id, amount)?)
+    }
+
+    /// Withdraw an amount given as text from an account given by id or name, e.g. from the
+    /// command line.
+    pub fn withdraw_str(&mut self, account: &str, amount: &str) -> Result<Receipt> {
+        let id = self.resolve(account)?;
+        self.withdraw(id, self.parse_money(id, amount)?)
+    }
+
+    /// Adjust an account given by id or name by a signed amount given as text, e.g. from the
+    /// command line.
+    pub fn adjust_str(&mut self, account: &str, amount: &str) -> Result<Receipt> {
+        let id = self.resolve(account)?;
+        let currency = self.get_account(id)?.currency;
+        self.adjust(id, Money::parse_signed(amount, currency)?)
+    }
+
+    /// Transfer an amount given as text between accounts given by id or name, e.g. from the
+    /// command line.
+    pub fn transfer_str(&mut self, from: &str, to: &str, amount: &str) -> Result<Receipt> {
+        let from = self.resolve(from)?;
+        let to = self.resolve(to)?;
+        self.transfer(from, to, self.parse_money(from, amount)?)
+    }
+}
+
+/// Debits and credits staged on a system, e.g. for a payout to many accounts or a settlement
+/// between several parties, that are posted atomically: either all of them or none.
+///
+/// Nothing is changed until the transaction is committed, and dropping the builder or calling
+/// [`TransactionBuilder::abort`] discards what was staged.
+#[derive(Debug)]
+pub struct TransactionBuilder<'a> {
+    bs: &'a mut BankingSystem,
+    postings: Vec<(Target, Side, Money)>,
+}
+
+impl TransactionBuilder<'_> {
+    /// Stage a debit of an account, which reduces its balance.
+    pub fn debit(&mut self, id: AccountId, amount: Money) -> &mut Self {
+        self.postings
+            .push((Target::Account(id), Side::Debit, amount));
+        self
+    }
+
+    /// Stage a credit of an account, which increases its balance.
+    pub fn credit(&mut self, id: AccountId, amount: Money) -> &mut Self {
+        self.postings
+            .push((Target::Account(id), Side::Credit, amount));
+        self
+    }
+
+    /// Stage a debit of the external account, for money coming into the system.
+    pub fn debit_external(&mut self, amount: Money) -> &mut Self {
+        self.postings.push((EXTERNAL, Side::Debit, amount));
+        self
+    }
+
+    /// Stage a credit of the external account, for money leaving the system.
+    pub fn credit_external(&mut self, amount: Money) -> &mut Self {
+        self.postings.push((EXTERNAL, Side::Credit, amount));
+        self
+    }
+
+    /// Check that the staged debits and credits balance in every currency and can all be posted,
+    /// without posting them. Accounts must exist and allow the postings, without their net change
+    /// going over their overdraft limit or overflowing their balance.
+    pub fn validate(&self) -> Result<()> {
+        self.stage().map(|_| ())
+    }
+
+    /// Post everything staged as a single transaction if it is valid, leaving the system
+    /// unchanged otherwise.
+    pub fn commit(self) -> Result<Receipt> {
+        let (staged, entries) = self.stage()?;
+        Ok(self.bs.record(
+            TransactionKind::Settlement,
+            staged,
+            entries,
+            Utc::now(),
+            None,
+        ))
+    }
+
+    /// Discard everything staged.
+    pub fn abort(self) {}
+
+    /// Validate the postings and apply them to copies of the accounts.
+    fn stage(&self) -> Result<(StagedAccounts, Vec<Entry>)> {
+        if self.postings.is_empty() {
+            return Err(BankingSystemError::EmptyTransaction.into());
+        }
+
+        let mut totals: BTreeMap<Currency, (u128, u128)> = BTreeMap::new();
+        for (_, side, amount) in &self.postings {
+            let total = totals.entry(amount.currency).or_default();
+            match side {
+                Side::Debit => total.0 += amount.amount.0 as u128,
+                Side::Credit => total.1 += amount.amount.0 as u128,
+            }
+        }
+        for (currency, (debits, credits)) in totals {
+            if debits != credits {
+                let money = |x: u128| {
+                    u64::try_from(x)
+                        .map(|x| Money::new(Cents(x), currency))
+                        .map_err(|_| BankingSystemError::AmountOverflow(x.to_string()))
+                };
+                return Err(BankingSystemError::UnbalancedTransaction {
+                    debits: money(debits)?,
+                    credits: money(credits)?,
+                }
+                .into());
+            }
+        }
+
+        self.bs.stage_net(&self.postings)
+    }
+}
+
+/// Outcome of a successful operation.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Receipt {
+    pub(crate) transaction_id: u64,
+    pub(crate) kind: TransactionKind,
+    pub(crate) amount: Money,
+    /// Balances of the accounts involved after the operation, in the order they were posted to.
+    pub(crate) balances: Vec<(AccountId, SignedCents)>,
+    /// Conversion of a transfer between currencies.
+    pub(crate) conversion: Option<Conversion>,
+    /// Fee charged to the account debited, on top of the amount.
+    pub(crate) fee: Option<Money>,
+}
+
+impl From<&Transaction> for Receipt {
+    fn from(transaction: &Transaction) -> Self {
+        Self {
+            transaction_id: transaction.id,
+            kind: transaction.kind,
+            amount: transaction.amount(),
+            balances: transaction
+                .entries
+                .iter()
+                .filter_map(|x| Some((x.account.parse().ok()?, x.balance?)))
+                .collect(),
+            conversion: None,
+            fee: None,
+        }
+    }
+}
+
+impl Receipt {
+    pub fn transaction_id(&self) -> u64 {
+        self.transaction_id
+    }
+
+    pub fn kind(&self) -> TransactionKind {
+        self.kind
+    }
+
+    pub fn amount(&self) -> Money {
+        self.amount
+    }
+
+    pub fn balances(&self) -> &[(AccountId, SignedCents)] {
+        &self.balances
+    }
+
+    /// Source amount, rate and destination amount of a transfer between currencies.
+    pub fn conversion(&self) -> Option<&Conversion> {
+        self.conversion.as_ref()
+    }
+
+    /// Fee charged for the operation, in the currency of the account charged.
+    pub fn fee(&self) -> Option<Money> {
+        self.fee
+    }
+
+    /// Balance of the account after the operation, if it was involved.
+    pub fn balance(&self, id: AccountId) -> Option<SignedCents> {
+        self.balances
+            .iter()
+            .rev()
+            .find(|(account, _)| *account == id)
+            .map(|(_, balance)| *balance)
+    }
+
+    /// Id of the account created, deposited to or withdrawn from, or the first account of a
+    /// transfer.
+    pub fn account_id(&self) -> Option<AccountId> {
+        self.balances.first().map(|(id, _)| *id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::AccountError;
+    use crate::exchange::{RateRecord, RateTable, RoundingMode};
+    use crate::interest::DayCount;
+
+    const DEFAULT_NAME: &str = "user";
+
+    fn account(id: u64, name: &str, balance: i64) -> Account {
+        Account {
+            id: AccountId(id),
+            name: name.to_owned(),
+            balance: SignedCents(balance),
+            status: AccountStatus::Open,
+            overdraft_limit: Cents::ZERO,
+            currency: Currency::USD,
+            product: None,
+            accrued_to: None,
+            accrued_interest: 0,
+            held: Cents::ZERO,
+        }
+    }
+
+    /// Id of the account created by the receipt.
+    fn created(receipt: Receipt) -> AccountId {
+        receipt.account_id().unwrap()
+    }
+
+    #[test]
+    fn test_find_account() {
+        let bs = BankingSystem::new(Vec::from([account(1, DEFAULT_NAME, 20)]), Vec::new()).unwrap();
+
+        assert_eq!(bs.find_account(DEFAULT_NAME).unwrap().id(), AccountId(1));
+        assert_eq!(
+            bs.find_account("user1"),
+            Err(BankingSystemError::AccountNotFound(String::from("user1")))
+        );
+    }
+
+    #[test]
+    fn test_get_account_mut() {
+        let mut bs =
+            BankingSystem::new(Vec::from([account(1, DEFAULT_NAME, 20)]), Vec::new()).unwrap();
+
+        assert_eq!(bs.get_account_mut(AccountId(1)).unwrap().name, DEFAULT_NAME);
+        assert_eq!(
+            bs.get_account_mut(AccountId(2)),
+            Err(BankingSystemError::AccountNotFound(String::from(
+                "00000026"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_get_account() {
+        let bs = BankingSystem::new(Vec::from([account(1, DEFAULT_NAME, 20)]), Vec::new()).unwrap();
+
+        assert_eq!(
+            bs.get_account(AccountId(1)).unwrap().balance(),
+            SignedCents(20)
+        );
+        assert_eq!(
+            bs.get_account(AccountId(2)),
+            Err(BankingSystemError::AccountNotFound(String::from(
+                "00000026"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let bs = BankingSystem::new(
+            Vec::from([account(1, DEFAULT_NAME, 20), account(2, "Andr\u{e9}", 0)]),
+            Vec::new(),
+        )
+        .unwrap();
+
+        assert_eq!(bs.resolve("00000018"), Ok(AccountId(1)));
+        assert_eq!(bs.resolve(DEFAULT_NAME), Ok(AccountId(1)));
+        // Names are compared after normalization
+        assert_eq!(bs.resolve("Andre\u{301}"), Ok(AccountId(2)));
+        assert_eq!(
+            bs.resolve("00000034"),
+            Err(BankingSystemError::AccountNotFound(String::from(
+                "00000034"
+            )))
+        );
+        assert_eq!(
+            bs.resolve("user1"),
+            Err(BankingSystemError::AccountNotFound(String::from("user1")))
+        );
+    }
+
+    #[test]
+    fn test_create_duplicate_account_name() {
+        let mut bs =
+            BankingSystem::new(Vec::from([account(1, DEFAULT_NAME, 20)]), Vec::new()).unwrap();
+
+        assert_eq!(
+            bs.create(DEFAULT_NAME, Money::usd(Cents(100000)))
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap(),
+            BankingSystemError::DuplicateAccountName(DEFAULT_NAME.to_owned())
+        );
+        // Names that only differ in normalization are the same name
+        bs.create("Andr\u{e9}", Money::usd(Cents(0))).unwrap();
+        assert!(bs.create("Andre\u{301}", Money::usd(Cents(0))).is_err());
+    }
+
+    #[test]
+    fn test_create_account_success() {
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+        let id = created(bs.create(DEFAULT_NAME, Money::usd(Cents(2000))).unwrap());
+
+        assert_eq!(id, AccountId(1));
+        assert_eq!(bs.get_account(id).unwrap().name(), DEFAULT_NAME);
+        assert_eq!(
+            created(bs.create("user1", Money::usd(Cents(0))).unwrap()),
+            AccountId(2)
+        );
+    }
+
+    #[test]
+    fn test_create_continues_ids() {
+        let mut bs =
+            BankingSystem::new(Vec::from([account(7, DEFAULT_NAME, 20)]), Vec::new()).unwrap();
+
+        assert_eq!(
+            created(bs.create("user1", Money::usd(Cents(0))).unwrap()),
+            AccountId(8)
+        );
+
+        // Ids are never reused, so none are left after the last one
+        let mut bs =
+            BankingSystem::new(Vec::from([account(u64::MAX, DEFAULT_NAME, 20)]), Vec::new())
+                .unwrap();
+        assert_eq!(
+            bs.create("user1", Money::usd(Cents(0)))
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap(),
+            BankingSystemError::AccountIdsExhausted
+        );
+        assert_eq!(bs.accounts().len(), 1);
+    }
+
+    #[test]
+    fn test_create_account_failure() {
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+
+        assert_eq!(
+            bs.create("", Money::usd(Cents(2000)))
+                .unwrap_err()
+                .downcast::<AccountError>()
+                .unwrap(),
+            AccountError::EmptyAccountName
+        );
+        assert_eq!(
+            bs.create("00000018", Money::usd(Cents(2000)))
+                .unwrap_err()
+                .downcast::<AccountError>()
+                .unwrap(),
+            AccountError::AccountIdName(String::from("00000018"))
+        );
+
+        // An account whose opening balance cannot be posted is not kept
+        assert_eq!(
+            bs.create_str("big", "100000000000000000")
+                .unwrap_err()
+                .downcast::<AccountError>()
+                .unwrap()
+                .code(),
+            "balance_overflow"
+        );
+        assert!(bs.accounts().is_empty());
+        assert!(bs.find_account("big").is_err());
+        assert_eq!(created(bs.create_str("big", "100").unwrap()), AccountId(1));
+        assert_eq!(bs.journal().len(), 1);
+    }
+
+    #[test]
+    fn test_deposit_success() {
+        let mut bs =
+            BankingSystem::new(Vec::from([account(1, DEFAULT_NAME, 20)]), Vec::new()).unwrap();
+        bs.deposit(AccountId(1), Money::usd(Cents(2000))).unwrap();
+
+        assert_eq!(
+            bs.get_account_mut(AccountId(1)).unwrap().balance,
+            SignedCents(2020)
+        );
+    }
+
+    #[test]
+    fn test_deposit_failure() {
+        let mut bs =
+            BankingSystem::new(Vec::from([account(1, DEFAULT_NAME, i64::MAX)]), Vec::new())
+                .unwrap();
+
+        assert_eq!(
+            bs.deposit(AccountId(1), Money::usd(Cents(200)))
+                .unwrap_err()
+                .downcast::<AccountError>()
+                .unwrap(),
+            AccountError::BalanceOverflow {
+                name: DEFAULT_NAME.to_owned(),
+                deposit_amount: Money::usd(Cents(200))
+            }
+        );
+    }
+
+    #[test]
+    fn test_withdraw_success() {
+        let mut bs =
+            BankingSystem::new(Vec::from([account(1, DEFAULT_NAME, 2000)]), Vec::new()).unwrap();
+        bs.withdraw(AccountId(1), Money::usd(Cents(2000))).unwrap();
+
+        assert_eq!(
+            bs.get_account_mut(AccountId(1)).unwrap().balance,
+            SignedCents(0)
+        );
+    }
+
+    #[test]
+    fn test_withdraw_failure() {
+        let mut bs =
+            BankingSystem::new(Vec::from([account(1, DEFAULT_NAME, 2)]), Vec::new()).unwrap();
+
+        assert_eq!(
+            bs.withdraw(AccountId(1), Money::usd(Cents(200)))
+                .unwrap_err()
+                .downcast::<AccountError>()
+                .unwrap(),
+            AccountError::AccountOverdraft {
+                name: DEFAULT_NAME.to_owned(),
+                balance: Money::new(SignedCents(2), Currency::USD),
+                available: Money::usd(Cents(2)),
+                withdraw_amount: Money::usd(Cents(200))
+            }
+        );
+    }
+
+    #[test]
+    fn test_transfer_success() {
+        let mut bs = BankingSystem::new(
+            Vec::from([account(1, "user1", 2000), account(2, "user2", 1000)]),
+            Vec::new(),
+        )
+        .unwrap();
+        bs.transfer(AccountId(1), AccountId(2), Money::usd(Cents(1000)))
+            .unwrap();
+
+        assert_eq!(
+            bs.get_account_mut(AccountId(1)).unwrap().balance,
+            SignedCents(1000)
+        );
+        assert_eq!(
+            bs.get_account_mut(AccountId(2)).unwrap().balance,
+            SignedCents(2000)
+        );
+    }
+
+    #[test]
+    fn test_transfer_failure() {
+        let mut bs = BankingSystem::new(
+            Vec::from([account(1, "user1", 2000), account(2, "user2", i64::MAX)]),
+            Vec::new(),
+        )
+        .unwrap();
+
+        // test failed withdrawal
+        assert_eq!(
+            bs.transfer(AccountId(1), AccountId(2), Money::usd(Cents(3000)))
+                .unwrap_err()
+                .downcast::<AccountError>()
+                .unwrap(),
+            AccountError::AccountOverdraft {
+                name: String::from("user1"),
+                balance: Money::new(SignedCents(2000), Currency::USD),
+                available: Money::usd(Cents(2000)),
+                withdraw_amount: Money::usd(Cents(3000))
+            }
+        );
+        assert_eq!(
+            bs.get_account_mut(AccountId(1)).unwrap().balance,
+            SignedCents(2000)
+        );
+        assert_eq!(
+            bs.get_account_mut(AccountId(2)).unwrap().balance,
+            SignedCents(i64::MAX)
+        );
+
+        // test failed deposit
+        assert_eq!(
+            bs.transfer(AccountId(1), AccountId(2), Money::usd(Cents(1000)))
+                .unwrap_err()
+                .downcast::<AccountError>()
+                .unwrap(),
+            AccountError::BalanceOverflow {
+                name: String::from("user2"),
+                deposit_amount: Money::usd(Cents(1000))
+            }
+        );
+        assert_eq!(
+            bs.get_account_mut(AccountId(1)).unwrap().balance,
+            SignedCents(2000)
+        );
+        assert_eq!(
+            bs.get_account_mut(AccountId(2)).unwrap().balance,
+            SignedCents(i64::MAX)
+        );
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut bs = BankingSystem::new(
+            Vec::from([account(1, "user1", 2000), account(2, "user2", 0)]),
+            Vec::new(),
+        )
+        .unwrap();
+        bs.mark_saved();
+        bs.transfer(AccountId(1), AccountId(2), Money::usd(Cents(500)))
+            .unwrap();
+
+        let renamed = bs.rename(AccountId(1), "Andre\u{301}").unwrap();
+        assert_eq!(renamed.name(), "Andr\u{e9}");
+        assert_eq!(bs.resolve("Andr\u{e9}"), Ok(AccountId(1)));
+        assert!(bs.find_account("user1").is_err());
+        // The history still refers to the account by id
+        assert_eq!(bs.history(Some(AccountId(1))).unwrap().len(), 2);
+
+        assert_eq!(
+            bs.rename(AccountId(1), "user2")
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap(),
+            BankingSystemError::DuplicateAccountName(String::from("user2"))
+        );
+        assert_eq!(
+            bs.rename(AccountId(1), "@house")
+                .unwrap_err()
+                .downcast::<AccountError>()
+                .unwrap(),
+            AccountError::ReservedAccountName(String::from("@house"))
+        );
+        assert!(bs.rename(AccountId(3), "user3").is_err());
+    }
+
+    #[test]
+    fn test_modified_accounts() {
+        let mut bs = BankingSystem::new(
+            Vec::from([
+                account(1, "user1", 2000),
+                account(2, "user2", 0),
+                account(3, "user3", 0),
+            ]),
+            Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(bs.modified_accounts().count(), 3);
+        bs.mark_saved();
+        assert_eq!(bs.modified_accounts().count(), 0);
+
+        bs.deposit(AccountId(1), Money::usd(Cents(100))).unwrap();
+        bs.rename(AccountId(3), "user4").unwrap();
+        assert_eq!(
+            bs.modified_accounts().map(|x| x.id).collect::<Vec<_>>(),
+            Vec::from([AccountId(1), AccountId(3)])
+        );
+    }
+
+    #[test]
+    fn test_journal_records_operations() {
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+        let user1 = created(bs.create("user1", Money::usd(Cents(2000))).unwrap());
+        let user2 = created(bs.create("user2", Money::usd(Cents(0))).unwrap());
+        bs.deposit(user1, Money::usd(Cents(500))).unwrap();
+        bs.withdraw(user1, Money::usd(Cents(1000))).unwrap();
+        bs.transfer(user1, user2, Money::usd(Cents(1500))).unwrap();
+
+        let journal = bs.journal();
+        assert_eq!(
+            journal.iter().map(|x| x.id).collect::<Vec<_>>(),
+            Vec::from([1, 2, 3, 4, 5])
+        );
+        assert_eq!(
+            journal.iter().map(|x| x.kind).collect::<Vec<_>>(),
+            Vec::from([
+                TransactionKind::Create,
+                TransactionKind::Create,
+                TransactionKind::Deposit,
+                TransactionKind::Withdraw,
+                TransactionKind::Transfer,
+            ])
+        );
+
+        let transfer = &journal[4];
+        assert_eq!(transfer.amount(), Money::usd(Cents(1500)));
+        assert_eq!(
+            transfer.entries(),
+            &[
+                Entry {
+                    account: String::from("00000018"),
+                    side: Side::Debit,
+                    amount: Cents(1500),
+                    balance: Some(SignedCents(0)),
+                    currency: Currency::USD,
+                },
+                Entry {
+                    account: String::from("00000026"),
+                    side: Side::Credit,
+                    amount: Cents(1500),
+                    balance: Some(SignedCents(1500)),
+                    currency: Currency::USD,
+                },
+            ]
+        );
+
+        let deposit = &journal[2];
+        assert_eq!(deposit.entries()[0].account(), EXTERNAL_ACCOUNT);
+        assert_eq!(deposit.entries()[0].side(), Side::Debit);
+        assert_eq!(deposit.entries()[1].account(), "00000018");
+        assert_eq!(deposit.entries()[1].side(), Side::Credit);
+    }
+
+    #[test]
+    fn test_journal_ignores_failed_operations() {
+        let mut bs =
+            BankingSystem::new(Vec::from([account(1, DEFAULT_NAME, 2)]), Vec::new()).unwrap();
+
+        assert!(bs.withdraw(AccountId(1), Money::usd(Cents(200))).is_err());
+        assert!(bs
+            .transfer(AccountId(1), AccountId(2), Money::usd(Cents(1)))
+            .is_err());
+        assert!(bs.create(DEFAULT_NAME, Money::usd(Cents(100))).is_err());
+        assert!(bs.create("", Money::usd(Cents(100))).is_err());
+        assert!(bs.rename(AccountId(1), "user1").is_ok());
+        assert_eq!(bs.journal().len(), 1);
+        assert_eq!(bs.journal()[0].kind(), TransactionKind::Opening);
+    }
+
+    #[test]
+    fn test_journal_continues_ids() {
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+        let id = created(bs.create(DEFAULT_NAME, Money::usd(Cents(100))).unwrap());
+        let journal = bs.journal().to_vec();
+
+        let mut bs = BankingSystem::new(bs.accounts().to_vec(), journal).unwrap();
+        bs.deposit(id, Money::usd(Cents(100))).unwrap();
+
+        assert_eq!(bs.journal().last().unwrap().id, 2);
+    }
+
+    #[test]
+    fn test_str_operations() {
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+        bs.create_str("user1", "20").unwrap();
+        bs.create_str("user2", "0.5").unwrap();
+        bs.deposit_str("user1", "1.25").unwrap();
+        bs.withdraw_str("00000018", "0.25").unwrap();
+        let receipt = bs.transfer_str("user1", "00000026", "10").unwrap();
+
+        assert_eq!(receipt.balance(AccountId(1)), Some(SignedCents(1100)));
+        assert_eq!(receipt.balance(AccountId(2)), Some(SignedCents(1050)));
+    }
+
+    #[test]
+    fn test_str_operations_failure() {
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+        bs.create_str(DEFAULT_NAME, "20").unwrap();
+
+        assert_eq!(
+            bs.deposit_str(DEFAULT_NAME, "2a")
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap(),
+            BankingSystemError::InvalidAmount(String::from("2a"))
+        );
+        // Unknown accounts are reported before invalid amounts
+        assert_eq!(
+            bs.withdraw_str("user1", "2a")
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap(),
+            BankingSystemError::AccountNotFound(String::from("user1"))
+        );
+        assert_eq!(
+            bs.create_str(DEFAULT_NAME, "2a")
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap(),
+            BankingSystemError::DuplicateAccountName(DEFAULT_NAME.to_owned())
+        );
+        assert_eq!(bs.journal().len(), 1);
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+        let id = created(bs.create(DEFAULT_NAME, Money::usd(Cents(100))).unwrap());
+
+        let error = bs
+            .withdraw(id.next().unwrap(), Money::usd(Cents(1)))
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<BankingSystemError>().unwrap().code(),
+            "account_not_found"
+        );
+        let error = bs.withdraw(id, Money::usd(Cents(200))).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<AccountError>().unwrap().code(),
+            "account_overdraft"
+        );
+    }
+
+    #[test]
+    fn test_receipts() {
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+
+        let receipt = bs.create("user1", Money::usd(Cents(2000))).unwrap();
+        let user1 = receipt.account_id().unwrap();
+        assert_eq!(receipt.transaction_id(), 1);
+        assert_eq!(receipt.kind(), TransactionKind::Create);
+        assert_eq!(receipt.amount(), Money::usd(Cents(2000)));
+        assert_eq!(receipt.balances(), &[(user1, SignedCents(2000))]);
+
+        let user2 = created(bs.create("user2", Money::usd(Cents(0))).unwrap());
+        let receipt = bs.deposit(user2, Money::usd(Cents(100))).unwrap();
+        assert_eq!(receipt.transaction_id(), 3);
+        assert_eq!(receipt.balance(user2), Some(SignedCents(100)));
+
+        let receipt = bs.withdraw(user1, Money::usd(Cents(500))).unwrap();
+        assert_eq!(receipt.balance(user1), Some(SignedCents(1500)));
+        assert_eq!(receipt.balance(user2), None);
+
+        let receipt = bs.transfer(user1, user2, Money::usd(Cents(1000))).unwrap();
+        assert_eq!(receipt.kind(), TransactionKind::Transfer);
+        assert_eq!(receipt.amount(), Money::usd(Cents(1000)));
+        assert_eq!(
+            receipt.balances(),
+            &[(user1, SignedCents(500)), (user2, SignedCents(1100))]
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+        let user1 = created(bs.create("user1", Money::usd(Cents(2000))).unwrap());
+        let user2 = created(bs.create("user2", Money::usd(Cents(0))).unwrap());
+        bs.deposit(user1, Money::usd(Cents(100))).unwrap();
+        bs.transfer(user1, user2, Money::usd(Cents(100))).unwrap();
+
+        assert_eq!(bs.history(None).unwrap().len(), 4);
+        assert_eq!(
+            bs.history(Some(user2))
+                .unwrap()
+                .iter()
+                .map(|x| x.id())
+                .collect::<Vec<_>>(),
+            Vec::from([2, 4])
+        );
+    }
+
+    #[test]
+    fn test_history_unknown_account() {
+        let bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+
+        assert_eq!(
+            bs.history(Some(AccountId(1)))
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap(),
+            BankingSystemError::AccountNotFound(String::from("00000018"))
+        );
+    }
+
+    #[test]
+    fn test_opening_balances() {
+        let bs = BankingSystem::new(
+            Vec::from([account(1, "user1", 2000), account(2, "user2", 0)]),
+            Vec::new(),
+        )
+        .unwrap();
+
+        assert_eq!(bs.journal().len(), 2);
+        assert!(bs
+            .journal()
+            .iter()
+            .all(|x| x.kind() == TransactionKind::Opening));
+        assert_eq!(bs.accounts()[0].balance, SignedCents(2000));
+        assert_eq!(bs.ledger().balance("00000018", Currency::USD), 2000);
+        assert_eq!(bs.ledger().balance(EXTERNAL_ACCOUNT, Currency::USD), -2000);
+
+        // Accounts already in the journal are not opened again
+        let reloaded = BankingSystem::new(bs.accounts().to_vec(), bs.journal().to_vec()).unwrap();
+        assert_eq!(reloaded.journal().len(), 2);
+        assert_eq!(reloaded.accounts(), bs.accounts());
+    }
+
+    #[test]
+    fn test_trial_balance() {
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+        let user1 = created(bs.create("user1", Money::usd(Cents(2000))).unwrap());
+        let user2 = created(bs.create("user2", Money::usd(Cents(500))).unwrap());
+        bs.withdraw(user1, Money::usd(Cents(250))).unwrap();
+        bs.transfer(user1, user2, Money::usd(Cents(750))).unwrap();
+        bs.deposit(user2, Money::usd(Cents(100))).unwrap();
+
+        let trial_balance = bs.trial_balance();
+        assert!(trial_balance.is_balanced());
+        assert_eq!(bs.ledger().balance(&user1.to_string(), Currency::USD), 1000);
+        assert_eq!(bs.ledger().balance(&user2.to_string(), Currency::USD), 1350);
+        assert_eq!(bs.ledger().balance(EXTERNAL_ACCOUNT, Currency::USD), -2350);
+        assert!(trial_balance
+            .totals
+            .iter()
+            .all(|(_, x)| x.debits == x.credits));
+    }
+
+    #[test]
+    fn test_trial_balance_discrepancy() {
+        let bs =
+            BankingSystem::new(Vec::from([account(1, DEFAULT_NAME, 2000)]), Vec::new()).unwrap();
+        let mut accounts = bs.accounts().to_vec();
+        accounts[0].balance = SignedCents(3000);
+
+        let tampered = BankingSystem::new(accounts, bs.journal().to_vec()).unwrap();
+        let trial_balance = tampered.trial_balance();
+
+        assert!(!trial_balance.is_balanced());
+        assert_eq!(
+            trial_balance.discrepancies,
+            Vec::from([String::from("00000018")])
+        );
+    }
+
+    #[test]
+    fn test_recover() {
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+        let user1 = created(bs.create("user1", Money::usd(Cents(2000))).unwrap());
+        let backup = bs.accounts().to_vec();
+        let user2 = created(bs.create("user2", Money::usd(Cents(500))).unwrap());
+        bs.transfer(user1, user2, Money::usd(Cents(250))).unwrap();
+
+        let recovered = BankingSystem::recover(backup, bs.journal().to_vec()).unwrap();
+
+        // Names are not journaled, the account missing from the backup is named after its id
+        assert_eq!(recovered.accounts()[0], bs.accounts()[0]);
+        assert_eq!(recovered.accounts()[1], account(2, "00000026", 750));
+        assert_eq!(recovered.journal(), bs.journal());
+        assert!(recovered.trial_balance().is_balanced());
+    }
+
+    #[test]
+    fn test_from_named_accounts() {
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+        let user1 = created(bs.create("user1", Money::usd(Cents(2000))).unwrap());
+        let user2 = created(bs.create("user2", Money::usd(Cents(500))).unwrap());
+        bs.transfer(user1, user2, Money::usd(Cents(250))).unwrap();
+
+        // The same journal as written before accounts had ids
+        let mut journal = bs.journal().to_vec();
+        for entry in journal.iter_mut().flat_map(|x| x.entries.iter_mut()) {
+            if let Ok(id) = entry.account.parse() {
+                entry.account = bs.get_account(id).unwrap().name.clone();
+            }
+        }
+        let accounts = bs
+            .accounts()
+            .iter()
+            .map(|x| (x.name.clone(), x.balance))
+            .collect();
+
+        let migrated = BankingSystem::from_named_accounts(accounts, journal).unwrap();
+        assert_eq!(migrated.accounts(), bs.accounts());
+        assert_eq!(migrated.journal(), bs.journal());
+        assert!(migrated.unsaved_transactions().is_empty());
+    }
+
+    #[test]
+    fn test_freeze() {
+        let mut bs = BankingSystem::new(
+            Vec::from([account(1, "user1", 2000), account(2, "user2", 0)]),
+            Vec::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            bs.freeze(AccountId(1)).unwrap().status(),
+            AccountStatus::Frozen
+        );
+        assert_eq!(
+            bs.transfer(AccountId(1), AccountId(2), Money::usd(Cents(100)))
+                .unwrap_err()
+                .downcast::<AccountError>()
+                .unwrap(),
+            AccountError::AccountFrozen {
+                name: String::from("user1")
+            }
+        );
+        assert!(bs.withdraw(AccountId(1), Money::usd(Cents(100))).is_err());
+        // Frozen accounts can still be credited
+        bs.transfer(AccountId(2), AccountId(1), Money::usd(Cents(0)))
+            .unwrap();
+        bs.deposit(AccountId(1), Money::usd(Cents(100))).unwrap();
+
+        assert_eq!(
+            bs.unfreeze(AccountId(1)).unwrap().status(),
+            AccountStatus::Open
+        );
+        bs.transfer(AccountId(1), AccountId(2), Money::usd(Cents(100)))
+            .unwrap();
+        assert!(bs.modified_accounts().any(|x| x.id == AccountId(1)));
+    }
+
+    #[test]
+    fn test_dormant() {
+        let mut bs =
+            BankingSystem::new(Vec::from([account(1, DEFAULT_NAME, 2000)]), Vec::new()).unwrap();
+
+        bs.mark_dormant(AccountId(1)).unwrap();
+        assert_eq!(
+            bs.withdraw(AccountId(1), Money::usd(Cents(100)))
+                .unwrap_err()
+                .downcast::<AccountError>()
+                .unwrap(),
+            AccountError::AccountDormant {
+                name: DEFAULT_NAME.to_owned()
+            }
+        );
+        bs.deposit(AccountId(1), Money::usd(Cents(100))).unwrap();
+
+        bs.unfreeze(AccountId(1)).unwrap();
+        bs.withdraw(AccountId(1), Money::usd(Cents(100))).unwrap();
+    }
+
+    #[test]
+    fn test_close() {
+        let mut bs = BankingSystem::new(
+            Vec::from([account(1, "user1", 2000), account(2, "user2", 0)]),
+            Vec::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            bs.close(AccountId(1), None)
+                .unwrap_err()
+                .downcast::<AccountError>()
+                .unwrap(),
+            AccountError::NonZeroBalance {
+                name: String::from("user1"),
+                balance: Money::new(SignedCents(2000), Currency::USD)
+            }
+        );
+        assert_eq!(
+            bs.close(AccountId(1), Some(AccountId(1)))
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap(),
+            BankingSystemError::SweepToSelf(String::from("00000018"))
+        );
+        assert_eq!(bs.close(AccountId(2), None).unwrap(), None);
+        assert_eq!(
+            bs.get_account(AccountId(2)).unwrap().status(),
+            AccountStatus::Closed
+        );
+
+        // Closed accounts cannot be posted to or reopened
+        for error in [
+            bs.deposit(AccountId(2), Money::usd(Cents(100)))
+                .unwrap_err(),
+            bs.transfer(AccountId(1), AccountId(2), Money::usd(Cents(100)))
+                .unwrap_err(),
+            bs.close(AccountId(1), Some(AccountId(2))).unwrap_err(),
+            bs.unfreeze(AccountId(2)).unwrap_err(),
+            bs.close(AccountId(2), None).unwrap_err(),
+        ] {
+            assert_eq!(
+                error.downcast::<AccountError>().unwrap(),
+                AccountError::AccountClosed {
+                    name: String::from("user2")
+                }
+            );
+        }
+        assert_eq!(
+            bs.get_account(AccountId(1)).unwrap().balance(),
+            SignedCents(2000)
+        );
+        assert_eq!(
+            bs.get_account(AccountId(1)).unwrap().status(),
+            AccountStatus::Open
+        );
+    }
+
+    #[test]
+    fn test_close_sweep() {
+        let mut bs = BankingSystem::new(
+            Vec::from([account(1, "user1", 2000), account(2, "user2", 500)]),
+            Vec::new(),
+        )
+        .unwrap();
+
+        let receipt = bs.close(AccountId(1), Some(AccountId(2))).unwrap().unwrap();
+        assert_eq!(receipt.kind(), TransactionKind::Close);
+        assert_eq!(receipt.amount(), Money::usd(Cents(2000)));
+        assert_eq!(
+            receipt.balances(),
+            &[
+                (AccountId(1), SignedCents(0)),
+                (AccountId(2), SignedCents(2500))
+            ]
+        );
+        assert_eq!(
+            bs.get_account(AccountId(1)).unwrap().status(),
+            AccountStatus::Closed
+        );
+        assert!(bs.trial_balance().is_balanced());
+
+        // A frozen account cannot be swept
+        bs.freeze(AccountId(2)).unwrap();
+        assert!(bs.close(AccountId(2), Some(AccountId(1))).is_err());
+    }
+
+    #[test]
+    fn test_overdraft_limit() {
+        let mut bs = BankingSystem::new(
+            Vec::from([account(1, "user1", 2000), account(2, "user2", 0)]),
+            Vec::new(),
+        )
+        .unwrap();
+
+        let account = bs.set_overdraft_limit(AccountId(1), Cents(1000)).unwrap();
+        assert_eq!(account.available(), Cents(3000));
+        let receipt = bs
+            .transfer(AccountId(1), AccountId(2), Money::usd(Cents(2500)))
+            .unwrap();
+        assert_eq!(receipt.balance(AccountId(1)), Some(SignedCents(-500)));
+        assert_eq!(
+            bs.withdraw(AccountId(1), Money::usd(Cents(600)))
+                .unwrap_err()
+                .downcast::<AccountError>()
+                .unwrap(),
+            AccountError::AccountOverdraft {
+                name: String::from("user1"),
+                balance: Money::new(SignedCents(-500), Currency::USD),
+                available: Money::usd(Cents(500)),
+                withdraw_amount: Money::usd(Cents(600))
+            }
+        );
+        assert!(bs.trial_balance().is_balanced());
+
+        // Removing the limit leaves nothing available until the overdraft is repaid
+        bs.set_overdraft_limit(AccountId(1), Cents::ZERO).unwrap();
+        assert!(bs.withdraw(AccountId(1), Money::usd(Cents(1))).is_err());
+        assert_eq!(
+            bs.set_overdraft_limit(AccountId(1), Cents(i64::MAX as u64 + 1))
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap()
+                .code(),
+            "amount_overflow"
+        );
+        bs.set_overdraft_limit(AccountId(1), Cents(i64::MAX as u64))
+            .unwrap();
+        bs.set_overdraft_limit(AccountId(1), Cents::ZERO).unwrap();
+        bs.deposit(AccountId(1), Money::usd(Cents(500))).unwrap();
+        bs.close(AccountId(1), None).unwrap();
+        assert!(bs.set_overdraft_limit(AccountId(1), Cents(100)).is_err());
+    }
+
+    #[test]
+    fn test_close_overdrawn_sweep() {
+        let mut overdrawn = account(1, "user1", -500);
+        overdrawn.overdraft_limit = Cents(1000);
+        let mut bs = BankingSystem::new(
+            Vec::from([overdrawn, account(2, "user2", 2000)]),
+            Vec::new(),
+        )
+        .unwrap();
+
+        // The overdraft is settled from the account swept into
+        let receipt = bs.close(AccountId(1), Some(AccountId(2))).unwrap().unwrap();
+        assert_eq!(receipt.amount(), Money::usd(Cents(500)));
+        assert_eq!(
+            receipt.balances(),
+            &[
+                (AccountId(2), SignedCents(1500)),
+                (AccountId(1), SignedCents(0))
+            ]
+        );
+        assert!(bs.trial_balance().is_balanced());
+    }
+
+    #[test]
+    fn test_load_overdrawn_account() {
+        let mut overdrawn = account(1, DEFAULT_NAME, -500);
+        overdrawn.overdraft_limit = Cents(100);
+        overdrawn.status = AccountStatus::Frozen;
+
+        // The opening balance is posted even though it is beyond the limit of a frozen account
+        let bs = BankingSystem::new(Vec::from([overdrawn.clone()]), Vec::new()).unwrap();
+        assert_eq!(bs.accounts(), &[overdrawn]);
+        assert_eq!(bs.ledger().balance("00000018", Currency::USD), -500);
+        assert_eq!(bs.ledger().balance(EXTERNAL_ACCOUNT, Currency::USD), 500);
+        assert!(bs.trial_balance().is_balanced());
+    }
+
+    #[test]
+    fn test_adjust() {
+        let mut bs =
+            BankingSystem::new(Vec::from([account(1, DEFAULT_NAME, 2000)]), Vec::new()).unwrap();
+
+        let receipt = bs
+            .adjust(AccountId(1), Money::new(SignedCents(-500), Currency::USD))
+            .unwrap();
+        assert_eq!(receipt.kind(), TransactionKind::Adjustment);
+        assert_eq!(receipt.amount(), Money::usd(Cents(500)));
+        assert_eq!(receipt.balance(AccountId(1)), Some(SignedCents(1500)));
+        let receipt = bs.adjust_str(DEFAULT_NAME, "+2.50").unwrap();
+        assert_eq!(receipt.balance(AccountId(1)), Some(SignedCents(1750)));
+        assert_eq!(bs.journal()[2].entries()[0].side(), Side::Debit);
+        assert_eq!(bs.journal()[2].entries()[0].account(), EXTERNAL_ACCOUNT);
+
+        // Negative adjustments are debits and cannot overdraw the account
+        assert_eq!(
+            bs.adjust_str(DEFAULT_NAME, "-20")
+                .unwrap_err()
+                .downcast::<AccountError>()
+                .unwrap(),
+            AccountError::AccountOverdraft {
+                name: DEFAULT_NAME.to_owned(),
+                balance: Money::new(SignedCents(1750), Currency::USD),
+                available: Money::usd(Cents(1750)),
+                withdraw_amount: Money::usd(Cents(2000))
+            }
+        );
+        assert_eq!(
+            bs.adjust_str(DEFAULT_NAME, "--20")
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap(),
+            BankingSystemError::InvalidSignedAmount(String::from("--20"))
+        );
+        assert!(bs.trial_balance().is_balanced());
+    }
+
+    #[test]
+    fn test_currencies() {
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+        let euros = created(bs.create_str("euros", "10.50 EUR").unwrap());
+        let yen = created(bs.create_str("yen", "JPY 1050").unwrap());
+        let dollars = created(bs.create_str("dollars", "10").unwrap());
+        assert_eq!(bs.get_account(euros).unwrap().currency(), Currency::EUR);
+        assert_eq!(bs.get_account(yen).unwrap().currency(), Currency::JPY);
+        assert_eq!(bs.get_account(dollars).unwrap().currency(), Currency::USD);
+
+        // Amounts are in the currency of the account unless they name another
+        let receipt = bs.deposit_str("euros", "1.25").unwrap();
+        assert_eq!(receipt.amount(), Money::new(Cents(125), Currency::EUR));
+        assert_eq!(receipt.balance(euros), Some(SignedCents(1175)));
+        let receipt = bs.withdraw_str("yen", "50").unwrap();
+        assert_eq!(receipt.balance(yen), Some(SignedCents(1000)));
+        assert_eq!(
+            bs.deposit_str("yen", "0.5")
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap(),
+            BankingSystemError::InvalidAmount(String::from("0.5"))
+        );
+        assert_eq!(
+            bs.deposit_str("euros", "5 USD")
+                .unwrap_err()
+                .downcast::<AccountError>()
+                .unwrap(),
+            AccountError::CurrencyMismatch {
+                name: String::from("euros"),
+                currency: Currency::EUR,
+                amount_currency: Currency::USD,
+            }
+        );
+
+        // Money is never moved between currencies
+        assert_eq!(
+            bs.transfer_str("euros", "dollars", "1")
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap(),
+            BankingSystemError::CurrencyMismatch {
+                from: String::from("euros"),
+                from_currency: Currency::EUR,
+                to: String::from("dollars"),
+                to_currency: Currency::USD,
+            }
+        );
+        assert_eq!(
+            bs.close(euros, Some(dollars))
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap()
+                .code(),
+            "currency_mismatch"
+        );
+        assert_eq!(bs.get_account(euros).unwrap().status(), AccountStatus::Open);
+
+        let trial_balance = bs.trial_balance();
+        assert!(trial_balance.is_balanced());
+        assert_eq!(trial_balance.totals().len(), 3);
+    }
+
+    #[test]
+    fn test_exchange_transfer() {
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+        let euros = created(bs.create_str("euros", "100 EUR").unwrap());
+        let dollars = created(bs.create_str("dollars", "0").unwrap());
+        let rates = RateTable::from_iter([RateRecord {
+            from: Currency::EUR,
+            to: Currency::USD,
+            rate: "1.0845".parse().unwrap(),
+            effective: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+        }]);
+        bs.set_exchange(ForeignExchange::new(rates, RoundingMode::HalfEven, 100).unwrap());
+
+        // €10.00 is $10.845, rounded to $10.84 of which 1% goes to the house
+        let receipt = bs.transfer_str("euros", "dollars", "10").unwrap();
+        let conversion = receipt.conversion().unwrap();
+        assert_eq!(conversion.source(), Money::new(Cents(1000), Currency::EUR));
+        assert_eq!(conversion.rate().to_string(), "1.0845");
+        assert_eq!(conversion.destination(), Money::usd(Cents(1073)));
+        assert_eq!(conversion.spread(), Money::usd(Cents(11)));
+        assert_eq!(receipt.amount(), Money::new(Cents(1000), Currency::EUR));
+        assert_eq!(receipt.balance(euros), Some(SignedCents(9000)));
+        assert_eq!(receipt.balance(dollars), Some(SignedCents(1073)));
+
+        let ledger = bs.ledger();
+        assert_eq!(ledger.balance(EXCHANGE_ACCOUNT, Currency::EUR), 1000);
+        assert_eq!(ledger.balance(EXCHANGE_ACCOUNT, Currency::USD), -1084);
+        assert_eq!(ledger.balance(HOUSE_ACCOUNT, Currency::USD), 11);
+        let trial_balance = bs.trial_balance();
+        assert!(trial_balance.is_balanced());
+        assert!(trial_balance.discrepancies().is_empty());
+
+        // The reverse direction uses the inverse rate, other pairs have none
+        let receipt = bs.transfer(dollars, euros, Money::usd(Cents(100))).unwrap();
+        assert_eq!(
+            receipt.conversion().unwrap().destination(),
+            Money::new(Cents(91), Currency::EUR)
+        );
+        let pounds = created(bs.create_str("pounds", "10 GBP").unwrap());
+        assert_eq!(
+            bs.transfer_str("pounds", "euros", "1")
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap()
+                .code(),
+            "no_exchange_rate"
+        );
+        assert_eq!(bs.get_account(pounds).unwrap().balance(), SignedCents(1000));
+        assert!(bs.trial_balance().is_balanced());
+
+        // A retried transfer returns the conversion it was made at
+        let transfer = |bs: &mut BankingSystem| {
+            bs.idempotent(Some("key1"), "transfer 00000018 00000026 €10.00", |bs| {
+                bs.transfer_str("euros", "dollars", "10")
+            })
+            .unwrap()
+        };
+        let receipt = transfer(&mut bs);
+        assert_eq!(transfer(&mut bs), receipt);
+        assert_eq!(
+            receipt.conversion().unwrap().destination(),
+            Money::usd(Cents(1073))
+        );
+    }
+
+    #[test]
+    fn test_accrue_interest() {
+        let date = |s: &str| s.parse::<NaiveDate>().unwrap();
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+        bs.set_products(BTreeMap::from([(
+            String::from("savings"),
+            Product {
+                rate: Some("3.65".parse().unwrap()),
+                day_count: DayCount::Act365,
+                ..Product::default()
+            },
+        )]))
+        .unwrap();
+        let id = created(bs.create_str(DEFAULT_NAME, "1000").unwrap());
+        bs.journal[0].timestamp = "2024-01-10T12:00:00Z".parse().unwrap();
+        bs.set_product(id, Some("savings"), date("2024-01-11"))
+            .unwrap();
+        assert_eq!(
+            bs.get_account(id).unwrap().accrued_to(),
+            Some(date("2024-01-10"))
+        );
+
+        // $1000.00 at 3.65% earns 10 cents a day, posted at the end of the month
+        assert!(bs.accrue(date("2024-01-20")).unwrap().is_empty());
+        let account = bs.get_account(id).unwrap();
+        assert_eq!(account.accrued_to(), Some(date("2024-01-20")));
+        assert_eq!(account.accrued_interest(), 100 * ACCRUAL_SCALE as u64);
+        assert!(bs.accrue(date("2024-01-20")).unwrap().is_empty());
+        assert_eq!(
+            bs.get_account(id).unwrap().accrued_interest(),
+            100 * ACCRUAL_SCALE as u64
+        );
+
+        // Doubling the balance on the 25th doubles the interest from that day on
+        bs.deposit_str(DEFAULT_NAME, "1000").unwrap();
+        bs.journal[1].timestamp = "2024-01-25T09:00:00Z".parse().unwrap();
+        let receipts = bs.accrue(date("2024-02-01")).unwrap();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].kind(), TransactionKind::Interest);
+        assert_eq!(receipts[0].amount(), Money::usd(Cents(280)));
+        assert_eq!(receipts[0].balance(id), Some(SignedCents(200280)));
+        assert_eq!(
+            bs.journal().last().unwrap().timestamp(),
+            "2024-01-31T23:59:59Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        // The posted interest earns interest on the first of February
+        assert_eq!(
+            bs.get_account(id).unwrap().accrued_interest(),
+            20_028_000_000
+        );
+        assert_eq!(bs.ledger().balance(INTEREST_ACCOUNT, Currency::USD), -280);
+        assert!(bs.trial_balance().is_balanced());
+        assert!(bs.modified_accounts().any(|x| x.id() == id));
+
+        let journal = bs.journal().len();
+        assert!(bs.accrue(date("2024-02-01")).unwrap().is_empty());
+        assert!(bs.accrue(date("2024-01-31")).unwrap().is_empty());
+        assert_eq!(bs.journal().len(), journal);
+
+        // Interest accrued late is recorded as of the end of the month after the transactions
+        // posted since, which stay before it in the journal and do not earn interest in it
+        bs.deposit_str(DEFAULT_NAME, "1000").unwrap();
+        bs.journal[journal].timestamp = "2024-03-03T09:00:00Z".parse().unwrap();
+        let receipts = bs.accrue(date("2024-03-05")).unwrap();
+        assert_eq!(receipts.len(), 1);
+        let history = bs.history(Some(id)).unwrap();
+        assert_eq!(
+            history.iter().map(|x| (x.id, x.kind())).collect::<Vec<_>>(),
+            [
+                (1, TransactionKind::Create),
+                (2, TransactionKind::Deposit),
+                (3, TransactionKind::Interest),
+                (4, TransactionKind::Deposit),
+                (5, TransactionKind::Interest),
+            ]
+        );
+        assert!(history[4].timestamp() < history[3].timestamp());
+        assert_eq!(
+            history[4].timestamp(),
+            "2024-02-29T23:59:59Z".parse::<DateTime<Utc>>().unwrap()
+        );
+
+        // Interest cannot be accrued ahead of time
+        let tomorrow = Utc::now().date_naive().succ_opt().unwrap();
+        assert_eq!(
+            bs.accrue(tomorrow)
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap(),
+            BankingSystemError::FutureDate(tomorrow)
+        );
+        assert_eq!(bs.journal().len(), journal + 2);
+    }
+
+    #[test]
+    fn test_set_product() {
+        let mut bs =
+            BankingSystem::new(Vec::from([account(1, DEFAULT_NAME, 100)]), Vec::new()).unwrap();
+        bs.set_products(BTreeMap::from([(
+            String::from("savings"),
+            Product {
+                rate: Some("1".parse().unwrap()),
+                day_count: DayCount::Thirty360,
+                ..Product::default()
+            },
+        )]))
+        .unwrap();
+        let from = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+
+        assert_eq!(
+            bs.set_product(AccountId(1), Some("checking"), from)
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap(),
+            BankingSystemError::UnknownProduct(String::from("checking"))
+        );
+
+        let account = bs.set_product(AccountId(1), Some("savings"), from).unwrap();
+        assert_eq!(account.product(), Some("savings"));
+        assert_eq!(account.accrued_to(), from.pred_opt());
+
+        let account = bs.set_product(AccountId(1), None, from).unwrap();
+        assert_eq!(account.product(), None);
+        assert_eq!(account.accrued_to(), None);
+
+        bs.get_account_mut(AccountId(1)).unwrap().status = AccountStatus::Closed;
+        assert!(bs.set_product(AccountId(1), Some("savings"), from).is_err());
+    }
+
+    #[test]
+    fn test_fees() {
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+        bs.set_products(BTreeMap::from([(
+            String::from("checking"),
+            Product {
+                fees: FeeSchedule {
+                    withdraw: Some(Fee {
+                        flat: Some(String::from("1")),
+                        ..Fee::default()
+                    }),
+                    transfer: Some(Fee {
+                        percent: Some("0.5".parse().unwrap()),
+                        min: Some(String::from("0.25")),
+                        max: Some(String::from("5")),
+                        ..Fee::default()
+                    }),
+                },
+                ..Product::default()
+            },
+        )]))
+        .unwrap();
+        let user1 = created(bs.create_str("user1", "100").unwrap());
+        let user2 = created(bs.create_str("user2", "0").unwrap());
+        bs.set_product(user1, Some("checking"), NaiveDate::MIN)
+            .unwrap();
+
+        // The fee is debited together with the amount and credited to the fee account
+        let receipt = bs.withdraw_str("user1", "10").unwrap();
+        assert_eq!(receipt.amount(), Money::usd(Cents(1000)));
+        assert_eq!(receipt.fee(), Some(Money::usd(Cents(100))));
+        assert_eq!(receipt.balance(user1), Some(SignedCents(8900)));
+        let entries = bs.journal().last().unwrap().entries();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            (entries[2].account(), entries[2].side()),
+            (FEE_ACCOUNT, Side::Credit)
+        );
+
+        // 0.5% of $10.00 is raised to the minimum fee
+        let receipt = bs.transfer_str("user1", "user2", "10").unwrap();
+        assert_eq!(receipt.fee(), Some(Money::usd(Cents(25))));
+        assert_eq!(receipt.balance(user1), Some(SignedCents(7875)));
+        assert_eq!(receipt.balance(user2), Some(SignedCents(1000)));
+
+        // The fee counts towards the overdraft limit
+        let journal = bs.journal().len();
+        assert_eq!(
+            bs.withdraw_str("user1", "78")
+                .unwrap_err()
+                .downcast::<AccountError>()
+                .unwrap(),
+            AccountError::AccountOverdraft {
+                name: String::from("user1"),
+                balance: Money::new(SignedCents(7875), Currency::USD),
+                available: Money::usd(Cents(7875)),
+                withdraw_amount: Money::usd(Cents(7900)),
+            }
+        );
+        assert_eq!(bs.journal().len(), journal);
+
+        // Accounts without a product are not charged
+        let receipt = bs.withdraw_str("user2", "1").unwrap();
+        assert_eq!(receipt.fee(), None);
+        assert_eq!(receipt.balance(user2), Some(SignedCents(900)));
+
+        assert_eq!(bs.ledger().balance(FEE_ACCOUNT, Currency::USD), 125);
+        assert!(bs.trial_balance().is_balanced());
+
+        // Fees are checked against the currency of the account when it is given the product
+        let yen = created(bs.create_str("user3", "100 JPY").unwrap());
+        let mut products = bs.products().clone();
+        products
+            .get_mut("checking")
+            .unwrap()
+            .fees
+            .withdraw
+            .as_mut()
+            .unwrap()
+            .flat = Some(String::from("1.5"));
+        bs.set_products(products.clone()).unwrap();
+        let invalid_fee = |x: Result<_>| x.unwrap_err().downcast::<BankingSystemError>().unwrap();
+        assert_eq!(
+            invalid_fee(
+                bs.set_product(yen, Some("checking"), NaiveDate::MIN)
+                    .map(|_| ())
+            ),
+            BankingSystemError::InvalidFee {
+                product: String::from("checking"),
+                currency: Currency::JPY,
+                reason: Box::new(BankingSystemError::InvalidAmount(String::from("1.5")))
+            }
+        );
+
+        // and against the currencies of the accounts on it when the products are set
+        products.get_mut("checking").unwrap().fees.transfer = Some(Fee {
+            min: Some(String::from("5")),
+            max: Some(String::from("1")),
+            ..Fee::default()
+        });
+        assert_eq!(bs.set_products(products).unwrap_err().code(), "invalid_fee");
+        assert_eq!(
+            bs.withdraw_str("user1", "1").unwrap().fee(),
+            Some(Money::usd(Cents(150)))
+        );
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+        bs.set_products(BTreeMap::from([(
+            String::from("checking"),
+            Product {
+                fees: FeeSchedule {
+                    withdraw: Some(Fee {
+                        flat: Some(String::from("1")),
+                        ..Fee::default()
+                    }),
+                    ..FeeSchedule::default()
+                },
+                ..Product::default()
+            },
+        )]))
+        .unwrap();
+        let rates = RateTable::from_iter([RateRecord {
+            from: Currency::EUR,
+            to: Currency::USD,
+            rate: "1.0845".parse().unwrap(),
+            effective: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+        }]);
+        bs.set_exchange(ForeignExchange::new(rates, RoundingMode::HalfEven, 100).unwrap());
+        let euros = created(bs.create_str("euros", "100 EUR").unwrap());
+        created(bs.create_str("dollars", "0").unwrap());
+        bs.set_product(euros, Some("checking"), NaiveDate::MIN)
+            .unwrap();
+        let deposit = bs.deposit_str("euros", "5").unwrap().transaction_id();
+        bs.mark_saved();
+        bs.withdraw_str("euros", "1").unwrap();
+        let before = bs.clone();
+
+        // Fees, conversions, reversals and new accounts and currencies are all undone
+        let savepoint = bs.savepoint();
+        bs.withdraw_str("euros", "10").unwrap();
+        bs.transfer_str("euros", "dollars", "10").unwrap();
+        bs.reverse(deposit).unwrap();
+        created(bs.create_str("pounds", "10 GBP").unwrap());
+        bs.rollback(savepoint);
+        assert_eq!(bs.accounts(), before.accounts());
+        assert_eq!(bs.journal(), before.journal());
+        assert_eq!(bs.ledger(), before.ledger());
+        assert_eq!(bs.reversed_by(deposit), None);
+        assert_eq!(
+            bs.modified_accounts().map(|x| x.id).collect::<Vec<_>>(),
+            Vec::from([euros])
+        );
+
+        // The ids and names of removed accounts are free again
+        let pounds = created(bs.create_str("pounds", "10 GBP").unwrap());
+        assert_eq!(pounds, AccountId(3));
+        assert!(bs.trial_balance().is_balanced());
+    }
+
+    #[test]
+    fn test_holds() {
+        let mut bs =
+            BankingSystem::new(Vec::from([account(1, DEFAULT_NAME, 10_000)]), Vec::new()).unwrap();
+        let expires = Utc::now() + chrono::Duration::days(7);
+        let journal = bs.journal().len();
+
+        // Holds reduce the available balance without posting anything
+        let hold = bs
+            .hold(AccountId(1), Money::usd(Cents(6000)), expires)
+            .unwrap()
+            .id();
+        let account = bs.get_account(AccountId(1)).unwrap();
+        assert_eq!(account.balance(), SignedCents(10_000));
+        assert_eq!(account.available(), Cents(4000));
+        assert_eq!(bs.journal().len(), journal);
+        assert_eq!(
+            bs.hold(AccountId(1), Money::usd(Cents(4001)), expires)
+                .unwrap_err()
+                .downcast::<AccountError>()
+                .unwrap()
+                .code(),
+            "insufficient_funds"
+        );
+        assert!(bs.withdraw(AccountId(1), Money::usd(Cents(4001))).is_err());
+
+        // A partial capture posts the amount captured and releases the rest
+        assert_eq!(
+            bs.capture(hold, Some(Money::usd(Cents(6001))))
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap()
+                .code(),
+            "capture_exceeds_hold"
+        );
+        let receipt = bs.capture(hold, Some(Money::usd(Cents(5000)))).unwrap();
+        assert_eq!(receipt.kind(), TransactionKind::Capture);
+        assert_eq!(receipt.balance(AccountId(1)), Some(SignedCents(5000)));
+        let account = bs.get_account(AccountId(1)).unwrap();
+        assert_eq!(account.held(), Cents::ZERO);
+        assert_eq!(account.available(), Cents(5000));
+        assert_eq!(bs.get_hold(hold).unwrap().status(), HoldStatus::Captured);
+        assert_eq!(
+            bs.capture(hold, None)
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap(),
+            BankingSystemError::HoldNotOpen {
+                id: hold,
+                status: HoldStatus::Captured
+            }
+        );
+
+        // Released and expired holds free their funds
+        let released = bs
+            .hold(AccountId(1), Money::usd(Cents(1000)), expires)
+            .unwrap()
+            .id();
+        let expired = bs
+            .hold(AccountId(1), Money::usd(Cents(2000)), expires)
+            .unwrap()
+            .id();
+        assert_eq!(bs.get_account(AccountId(1)).unwrap().held(), Cents(3000));
+        assert_eq!(bs.release(released).unwrap().status(), HoldStatus::Released);
+        assert_eq!(bs.expire_holds(Utc::now()), Vec::<u64>::new());
+        assert_eq!(bs.expire_holds(expires), Vec::from([expired]));
+        assert_eq!(bs.get_account(AccountId(1)).unwrap().held(), Cents::ZERO);
+        assert_eq!(
+            bs.release(expired)
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap()
+                .code(),
+            "hold_not_open"
+        );
+        assert_eq!(
+            bs.release(10)
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap(),
+            BankingSystemError::HoldNotFound(10)
+        );
+        assert_eq!(bs.modified_holds().count(), 3);
+        assert!(bs.trial_balance().is_balanced());
+    }
+
+    #[test]
+    fn test_set_holds() {
+        let mut bs =
+            BankingSystem::new(Vec::from([account(1, DEFAULT_NAME, 10_000)]), Vec::new()).unwrap();
+        let expires = Utc::now() + chrono::Duration::days(7);
+        bs.hold(AccountId(1), Money::usd(Cents(3000)), expires)
+            .unwrap();
+        let released = bs
+            .hold(AccountId(1), Money::usd(Cents(2000)), expires)
+            .unwrap()
+            .id();
+        bs.release(released).unwrap();
+
+        // Only open holds reserve funds once restored, and new ids continue after the last hold
+        let mut loaded = BankingSystem::new(bs.accounts().to_vec(), bs.journal().to_vec()).unwrap();
+        loaded.set_holds(bs.holds().cloned().collect());
+        assert_eq!(
+            loaded.get_account(AccountId(1)).unwrap().held(),
+            Cents(3000)
+        );
+        assert_eq!(
+            loaded
+                .hold(AccountId(1), Money::usd(Cents(100)), expires)
+                .unwrap()
+                .id(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_standing_orders() {
+        let mut bs = BankingSystem::new(
+            Vec::from([
+                account(1, "user1", 20_000),
+                account(2, "user2", 0),
+                account(3, "user3", 0),
+            ]),
+            Vec::new(),
+        )
+        .unwrap();
+        let date = |s: &str| s.parse::<NaiveDate>().unwrap();
+        let rent = bs
+            .create_order(
+                AccountId(1),
+                AccountId(2),
+                Money::usd(Cents(10_000)),
+                Schedule::Monthly(1),
+                date("2024-01-01"),
+                Some(date("2024-03-01")),
+            )
+            .unwrap()
+            .id();
+        let weekly = bs
+            .create_order(
+                AccountId(1),
+                AccountId(3),
+                Money::usd(Cents(500)),
+                Schedule::Weekly(chrono::Weekday::Mon),
+                date("2024-01-02"),
+                None,
+            )
+            .unwrap()
+            .id();
+        assert_eq!(bs.get_order(weekly).unwrap().next(), date("2024-01-08"));
+        assert_eq!(
+            bs.create_order(
+                AccountId(1),
+                AccountId(2),
+                Money::new(Cents(100), Currency::EUR),
+                Schedule::Daily,
+                date("2024-01-01"),
+                None,
+            )
+            .unwrap_err()
+            .downcast::<AccountError>()
+            .unwrap()
+            .code(),
+            "currency_mismatch"
+        );
+        assert_eq!(
+            bs.create_order(
+                AccountId(1),
+                AccountId(1),
+                Money::usd(Cents(100)),
+                Schedule::Daily,
+                date("2024-01-01"),
+                None,
+            )
+            .unwrap_err()
+            .downcast::<BankingSystemError>()
+            .unwrap(),
+            BankingSystemError::OrderToSelf(String::from("00000018"))
+        );
+        assert_eq!(
+            bs.create_order(
+                AccountId(1),
+                AccountId(2),
+                Money::usd(Cents::ZERO),
+                Schedule::Daily,
+                date("2024-01-01"),
+                None,
+            )
+            .unwrap_err()
+            .downcast::<BankingSystemError>()
+            .unwrap(),
+            BankingSystemError::ZeroOrderAmount
+        );
+
+        // Instances due are run oldest first, catching up on every one missed
+        let orders = bs.orders().cloned().collect::<Vec<_>>();
+        let runs = bs.run_due(date("2024-01-10")).unwrap();
+        assert_eq!(
+            runs.iter()
+                .map(|x| (x.order(), x.date(), x.status()))
+                .collect::<Vec<_>>(),
+            [
+                (rent, date("2024-01-01"), RunStatus::Executed),
+                (weekly, date("2024-01-08"), RunStatus::Executed),
+            ]
+        );
+        assert_eq!(
+            bs.journal().last().unwrap().id,
+            runs[1].transaction_id().unwrap()
+        );
+        assert_eq!(
+            bs.get_account(AccountId(2)).unwrap().balance(),
+            SignedCents(10_000)
+        );
+
+        // Running again for the same date runs nothing
+        assert_eq!(bs.run_due(date("2024-01-10")).unwrap(), Vec::new());
+
+        // Orders not saved after their transfers are moved on past the instances in the journal
+        let mut crashed =
+            BankingSystem::new(bs.accounts().to_vec(), bs.journal().to_vec()).unwrap();
+        crashed.set_orders(orders.clone(), Vec::new());
+        assert_eq!(crashed.runs(), runs);
+        assert_eq!(crashed.unsaved_runs(), runs);
+        assert_eq!(crashed.modified_orders().count(), 2);
+        assert_eq!(crashed.run_due(date("2024-01-10")).unwrap(), Vec::new());
+        assert_eq!(
+            bs.journal()[runs[0].transaction_id().unwrap() as usize - 1].order(),
+            Some((rent, date("2024-01-01")))
+        );
+
+        // Instances that cannot be transferred are recorded with the reason and not retried
+        let runs = bs.run_due(date("2024-02-01")).unwrap();
+        assert_eq!(runs.len(), 4);
+        let failed = runs.last().unwrap();
+        assert_eq!(
+            (failed.order(), failed.status(), failed.reason()),
+            (rent, RunStatus::Failed, Some("account_overdraft"))
+        );
+        assert_eq!(failed.transaction_id(), None);
+
+        bs.freeze(AccountId(1)).unwrap();
+        let runs = bs.run_due(date("2024-03-31")).unwrap();
+        assert!(runs
+            .iter()
+            .all(|x| x.status() == RunStatus::Skipped && x.reason() == Some("account_frozen")));
+        assert!(bs.get_order(rent).unwrap().is_finished());
+        assert_eq!(runs.iter().filter(|x| x.order() == rent).count(), 1);
+        assert_eq!(bs.unsaved_runs().len(), bs.runs().len());
+        assert_eq!(bs.modified_orders().count(), 2);
+        assert!(bs.trial_balance().is_balanced());
+
+        // Restored orders continue from the next instance not run
+        let mut loaded = BankingSystem::new(bs.accounts().to_vec(), bs.journal().to_vec()).unwrap();
+        loaded.set_orders(bs.orders().cloned().collect(), bs.runs().to_vec());
+        assert!(loaded.unsaved_runs().is_empty());
+        assert_eq!(loaded.run_due(date("2024-03-31")).unwrap(), Vec::new());
+        assert_eq!(
+            bs.get_order(3).unwrap_err(),
+            BankingSystemError::OrderNotFound(3)
+        );
+
+        // Instances due after today are not run ahead of time
+        let tomorrow = Utc::now().date_naive().succ_opt().unwrap();
+        assert_eq!(
+            loaded
+                .run_due(tomorrow)
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap(),
+            BankingSystemError::FutureDate(tomorrow)
+        );
+    }
+
+    #[test]
+    fn test_transaction_builder() {
+        let mut bs = BankingSystem::new(
+            Vec::from([
+                account(1, "user1", 10_000),
+                account(2, "user2", 0),
+                account(3, "user3", 500),
+            ]),
+            Vec::new(),
+        )
+        .unwrap();
+        let journal = bs.journal().len();
+
+        // Payout from one account to many
+        let mut transaction = bs.transaction();
+        transaction
+            .debit(AccountId(1), Money::usd(Cents(3000)))
+            .credit(AccountId(2), Money::usd(Cents(1000)))
+            .credit(AccountId(3), Money::usd(Cents(2000)));
+        transaction.validate().unwrap();
+        let receipt = transaction.commit().unwrap();
+        assert_eq!(receipt.kind(), TransactionKind::Settlement);
+        assert_eq!(receipt.amount(), Money::usd(Cents(3000)));
+        assert_eq!(
+            receipt.balances(),
+            [
+                (AccountId(1), SignedCents(7000)),
+                (AccountId(2), SignedCents(1000)),
+                (AccountId(3), SignedCents(2500)),
+            ]
+        );
+
+        // Settlement between three parties and the external account
+        let mut transaction = bs.transaction();
+        transaction
+            .debit(AccountId(2), Money::usd(Cents(500)))
+            .debit(AccountId(3), Money::usd(Cents(500)))
+            .debit_external(Money::usd(Cents(100)))
+            .credit(AccountId(1), Money::usd(Cents(1100)));
+        transaction.commit().unwrap();
+        assert_eq!(
+            bs.get_account(AccountId(1)).unwrap().balance(),
+            SignedCents(8100)
+        );
+        assert_eq!(bs.journal().len(), journal + 2);
+        assert!(bs.trial_balance().is_balanced());
+
+        // Nothing is posted if any posting fails
+        let accounts = bs.accounts().to_vec();
+        let mut transaction = bs.transaction();
+        transaction
+            .debit(AccountId(2), Money::usd(Cents(100)))
+            .debit(AccountId(3), Money::usd(Cents(5000)))
+            .credit(AccountId(1), Money::usd(Cents(5100)));
+        assert_eq!(
+            transaction
+                .commit()
+                .unwrap_err()
+                .downcast::<AccountError>()
+                .unwrap()
+                .code(),
+            "account_overdraft"
+        );
+        assert_eq!(bs.accounts(), accounts);
+        assert_eq!(bs.journal().len(), journal + 2);
+
+        let mut transaction = bs.transaction();
+        transaction
+            .debit(AccountId(1), Money::usd(Cents(100)))
+            .credit(AccountId(2), Money::usd(Cents(90)));
+        assert_eq!(
+            transaction
+                .validate()
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap(),
+            BankingSystemError::UnbalancedTransaction {
+                debits: Money::usd(Cents(100)),
+                credits: Money::usd(Cents(90)),
+            }
+        );
+        transaction.abort();
+        assert_eq!(
+            bs.transaction()
+                .commit()
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap(),
+            BankingSystemError::EmptyTransaction
+        );
+        assert_eq!(bs.accounts(), accounts);
+
+        // The overdraft limit applies to the net change of each account, whatever the order
+        let mut transaction = bs.transaction();
+        transaction
+            .debit(AccountId(2), Money::usd(Cents(1000)))
+            .credit(AccountId(3), Money::usd(Cents(1000)))
+            .debit(AccountId(1), Money::usd(Cents(600)))
+            .credit(AccountId(2), Money::usd(Cents(600)));
+        transaction.commit().unwrap();
+        assert_eq!(
+            bs.get_account(AccountId(2)).unwrap().balance(),
+            SignedCents(100)
+        );
+        let accounts = bs.accounts().to_vec();
+        let mut transaction = bs.transaction();
+        transaction
+            .credit(AccountId(2), Money::usd(Cents(100)))
+            .debit(AccountId(2), Money::usd(Cents(300)))
+            .credit(AccountId(1), Money::usd(Cents(200)));
+        assert_eq!(
+            transaction
+                .commit()
+                .unwrap_err()
+                .downcast::<AccountError>()
+                .unwrap(),
+            AccountError::AccountOverdraft {
+                name: String::from("user2"),
+                balance: Money::new(SignedCents(100), Currency::USD),
+                available: Money::usd(Cents(100)),
+                withdraw_amount: Money::usd(Cents(200)),
+            }
+        );
+        assert_eq!(bs.accounts(), accounts);
+    }
+
+    #[test]
+    fn test_reverse() {
+        let mut bs = BankingSystem::new(
+            Vec::from([account(1, "user1", 1000), account(2, "user2", 0)]),
+            Vec::new(),
+        )
+        .unwrap();
+        let deposit = bs
+            .deposit(AccountId(1), Money::usd(Cents(500)))
+            .unwrap()
+            .transaction_id();
+        let transfer = bs
+            .transfer(AccountId(1), AccountId(2), Money::usd(Cents(1200)))
+            .unwrap()
+            .transaction_id();
+
+        // The reversal swaps every entry of the original and is linked to it
+        let receipt = bs.reverse(transfer).unwrap();
+        assert_eq!(receipt.kind(), TransactionKind::Reversal);
+        assert_eq!(
+            receipt.balances(),
+            [
+                (AccountId(1), SignedCents(1500)),
+                (AccountId(2), SignedCents(0)),
+            ]
+        );
+        assert_eq!(bs.journal().last().unwrap().reverses(), Some(transfer));
+        assert_eq!(bs.reversed_by(transfer), Some(receipt.transaction_id()));
+        assert_eq!(bs.reversed_by(deposit), None);
+
+        let error = |result: Result<Receipt>| {
+            result
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap()
+        };
+        assert_eq!(
+            error(bs.reverse(transfer)),
+            BankingSystemError::AlreadyReversed {
+                id: transfer,
+                by: receipt.transaction_id()
+            }
+        );
+        assert_eq!(
+            error(bs.reverse(receipt.transaction_id())),
+            BankingSystemError::NotReversible {
+                id: receipt.transaction_id(),
+                kind: TransactionKind::Reversal
+            }
+        );
+        assert_eq!(
+            error(bs.reverse(1)),
+            BankingSystemError::NotReversible {
+                id: 1,
+                kind: TransactionKind::Opening
+            }
+        );
+        assert_eq!(
+            error(bs.reverse(100)),
+            BankingSystemError::TransactionNotFound(100)
+        );
+
+        // A reversal that would overdraft is refused and not posted
+        bs.withdraw(AccountId(1), Money::usd(Cents(1500))).unwrap();
+        let journal = bs.journal().len();
+        assert_eq!(
+            bs.reverse(deposit)
+                .unwrap_err()
+                .downcast::<AccountError>()
+                .unwrap()
+                .code(),
+            "account_overdraft"
+        );
+        assert_eq!(bs.journal().len(), journal);
+        assert_eq!(bs.reversed_by(deposit), None);
+        assert!(bs.trial_balance().is_balanced());
+
+        // Reversals are found again when the journal is loaded
+        let loaded = BankingSystem::new(bs.accounts().to_vec(), bs.journal().to_vec()).unwrap();
+        assert_eq!(loaded.reversed_by(transfer), Some(receipt.transaction_id()));
+    }
+
+    #[test]
+    fn test_reverse_settlement() {
+        let mut bs = BankingSystem::new(
+            Vec::from([
+                account(1, "user1", 1000),
+                account(2, "user2", 0),
+                account(3, "user3", 0),
+            ]),
+            Vec::new(),
+        )
+        .unwrap();
+        let accounts = bs.accounts().to_vec();
+
+        // The reversal debits user2 before crediting it, which only its net change allows
+        let mut transaction = bs.transaction();
+        transaction
+            .debit(AccountId(1), Money::usd(Cents(500)))
+            .credit(AccountId(2), Money::usd(Cents(500)))
+            .debit(AccountId(2), Money::usd(Cents(500)))
+            .credit(AccountId(3), Money::usd(Cents(500)));
+        let settlement = transaction.commit().unwrap().transaction_id();
+        let receipt = bs.reverse(settlement).unwrap();
+        assert_eq!(receipt.kind(), TransactionKind::Reversal);
+        assert_eq!(bs.reversed_by(settlement), Some(receipt.transaction_id()));
+        assert_eq!(bs.accounts(), accounts);
+        assert!(bs.trial_balance().is_balanced());
+    }
+
+    #[test]
+    fn test_idempotent() {
+        let mut bs = BankingSystem::new(
+            Vec::from([account(1, "user1", 1000), account(2, "user2", 0)]),
+            Vec::new(),
+        )
+        .unwrap();
+        bs.set_products(BTreeMap::from([(
+            String::from("checking"),
+            Product {
+                fees: FeeSchedule {
+                    withdraw: Some(Fee {
+                        flat: Some(String::from("1")),
+                        ..Fee::default()
+                    }),
+                    ..FeeSchedule::default()
+                },
+                ..Product::default()
+            },
+        )]))
+        .unwrap();
+        bs.set_product(AccountId(1), Some("checking"), NaiveDate::MIN)
+            .unwrap();
+        let withdraw = |bs: &mut BankingSystem, key, cents| {
+            let amount = Money::usd(Cents(cents));
+            bs.idempotent(key, &format!("withdraw 00000018 {amount}"), |bs| {
+                bs.withdraw(AccountId(1), amount)
+            })
+        };
+
+        let receipt = withdraw(&mut bs, Some("key1"), 200).unwrap();
+        assert_eq!(receipt.balance(AccountId(1)), Some(SignedCents(700)));
+        let journal = bs.journal().len();
+
+        // A retry returns the original receipt without withdrawing again
+        assert_eq!(withdraw(&mut bs, Some("key1"), 200).unwrap(), receipt);
+        assert_eq!(bs.journal().len(), journal);
+        assert_eq!(
+            bs.get_account(AccountId(1)).unwrap().balance(),
+            SignedCents(700)
+        );
+        assert_eq!(
+            bs.unsaved_idempotency_keys()
+                .map(|x| (x.key(), x.request(), x.transaction_id()))
+                .collect::<Vec<_>>(),
+            [(
+                "key1",
+                "withdraw 00000018 $2.00",
+                Some(receipt.transaction_id())
+            )]
+        );
+
+        // The key cannot be used for another request
+        assert_eq!(
+            withdraw(&mut bs, Some("key1"), 300)
+                .unwrap_err()
+                .downcast::<BankingSystemError>()
+                .unwrap(),
+            BankingSystemError::IdempotencyKeyReused {
+                key: String::from("key1"),
+                request: String::from("withdraw 00000018 $2.00")
+            }
+        );
+
+        // Failures are not recorded, so the key can be retried
+        assert!(withdraw(&mut bs, Some("key2"), 1000).is_err());
+        assert_eq!(bs.idempotency_keys().count(), 1);
+        bs.deposit(AccountId(1), Money::usd(Cents(1000))).unwrap();
+        assert!(withdraw(&mut bs, Some("key2"), 1000).is_ok());
+
+        // Without a key every request is applied
+        withdraw(&mut bs, None, 100).unwrap();
+        withdraw(&mut bs, None, 100).unwrap();
+        assert_eq!(
+            bs.get_account(AccountId(1)).unwrap().balance(),
+            SignedCents(200)
+        );
+        assert_eq!(bs.idempotency_keys().count(), 2);
+
+        // Keys are kept once restored
+        let mut loaded = BankingSystem::new(bs.accounts().to_vec(), bs.journal().to_vec()).unwrap();
+        loaded.set_idempotency_keys(bs.idempotency_keys().cloned().collect());
+        assert_eq!(withdraw(&mut loaded, Some("key1"), 200).unwrap(), receipt);
+        assert_eq!(loaded.unsaved_idempotency_keys().count(), 0);
+
+        // Any outcome is kept, including that of requests that post nothing
+        let freeze = |bs: &mut BankingSystem| {
+            bs.idempotent(Some("key3"), "freeze 00000018", |bs| {
+                Ok(bs.freeze(AccountId(1))?.clone())
+            })
+        };
+        let account = freeze(&mut loaded).unwrap();
+        assert_eq!(account.status(), AccountStatus::Frozen);
+        assert_eq!(freeze(&mut loaded).unwrap(), account);
+        assert_eq!(
+            loaded.idempotency_keys().last().unwrap().transaction_id(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_load_duplicate_accounts() {
+        assert_eq!(
+            BankingSystem::new(
+                Vec::from([account(1, "user1", 100), account(1, "user2", 200)]),
+                Vec::new()
+            )
+            .unwrap_err(),
+            BankingSystemError::DuplicateAccountId(AccountId(1))
+        );
+        // Names are compared after normalization
+        assert_eq!(
+            BankingSystem::new(
+                Vec::from([
+                    account(1, "Andr\u{e9}", 100),
+                    account(2, "Andre\u{301}", 200)
+                ]),
+                Vec::new()
+            )
+            .unwrap_err(),
+            BankingSystemError::DuplicateAccountName(String::from("Andre\u{301}"))
+        );
+
+        // Accounts already in the journal are checked too
+        let bs = BankingSystem::new(Vec::from([account(1, "user1", 100)]), Vec::new()).unwrap();
+        assert_eq!(
+            BankingSystem::new(
+                Vec::from([account(1, "user1", 100), account(1, "user1", 100)]),
+                bs.journal().to_vec()
+            )
+            .unwrap_err(),
+            BankingSystemError::DuplicateAccountId(AccountId(1))
+        );
+    }
+
+    #[test]
+    fn test_load_closed_account() {
+        let mut closed = account(1, DEFAULT_NAME, 0);
+        closed.status = AccountStatus::Closed;
+
+        // The opening transaction is posted even though the account is closed
+        let bs = BankingSystem::new(Vec::from([closed.clone()]), Vec::new()).unwrap();
+        assert_eq!(bs.journal().len(), 1);
+        assert_eq!(bs.accounts(), &[closed]);
+    }
+}
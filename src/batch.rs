@@ -0,0 +1,292 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::banking_system::{error_code, BankingSystem, BankingSystemError, Receipt};
+
+/// Operation of a line of a batch file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchOp {
+    Create,
+    Deposit,
+    Withdraw,
+    Transfer,
+}
+
+impl Display for BatchOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self {
+            Self::Create => "create",
+            Self::Deposit => "deposit",
+            Self::Withdraw => "withdraw",
+            Self::Transfer => "transfer",
+        };
+        write!(f, "{op}")
+    }
+}
+
+/// Operation read from a batch file, with its arguments written like on the command line.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BatchLine {
+    pub op: BatchOp,
+    /// Name of the account created, or id or name of the account deposited to, withdrawn from or
+    /// transferred from.
+    pub account: String,
+    /// Id or name of the account transferred to.
+    #[serde(default)]
+    pub to: Option<String>,
+    pub amount: String,
+}
+
+impl BatchLine {
+    fn apply(&self, bs: &mut BankingSystem) -> Result<Receipt> {
+        match self.op {
+            BatchOp::Create => bs.create_str(&self.account, &self.amount),
+            BatchOp::Deposit => bs.deposit_str(&self.account, &self.amount),
+            BatchOp::Withdraw => bs.withdraw_str(&self.account, &self.amount),
+            BatchOp::Transfer => {
+                let to = self
+                    .to
+                    .as_deref()
+                    .ok_or(BankingSystemError::MissingTransferTarget)?;
+                bs.transfer_str(&self.account, to, &self.amount)
+            },
+        }
+    }
+}
+
+/// Read the lines of a batch file, a JSON array of operations if its extension is `json` and a
+/// CSV file with the columns `op`, `account`, `to` and `amount` otherwise. Lines are numbered by
+/// their line in a CSV file and by their position in a JSON array, starting from 1.
+pub fn read_batch(path: &Path) -> Result<Vec<(u64, BatchLine)>> {
+    if path
+        .extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("json"))
+    {
+        let lines: Vec<BatchLine> = serde_json::from_reader(File::open(path)?)?;
+        return Ok((1..).zip(lines).collect());
+    }
+
+    let mut rdr = ::csv::ReaderBuilder::new()
+        .trim(::csv::Trim::All)
+        .from_reader(File::open(path)?);
+    let headers = rdr.headers()?.clone();
+    rdr.records()
+        .map(|record| {
+            let record = record?;
+            let line = record.position().map_or(0, |x| x.line());
+            Ok((line, record.deserialize(Some(&headers))?))
+        })
+        .collect()
+}
+
+/// Outcome of a line of a batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LineStatus {
+    /// Posted and committed with the rest of the batch.
+    Applied,
+    /// Posted, but undone because a later line failed.
+    RolledBack,
+    /// Failed, undoing the whole batch.
+    Failed,
+    /// Not run because an earlier line failed.
+    NotRun,
+}
+
+impl Display for LineStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            Self::Applied => "applied",
+            Self::RolledBack => "rolled back",
+            Self::Failed => "failed",
+            Self::NotRun => "not run",
+        };
+        write!(f, "{status}")
+    }
+}
+
+/// Report of a line of a batch, with the transaction posted if it was applied and the code and
+/// message of the error if it failed.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct LineReport {
+    pub line: u64,
+    pub op: BatchOp,
+    pub status: LineStatus,
+    pub transaction_id: Option<u64>,
+    pub reason: Option<String>,
+    pub message: Option<String>,
+}
+
+impl Display for LineReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {} {}", self.line, self.op, self.status)?;
+        if let Some(id) = self.transaction_id {
+            write!(f, " as transaction {id}")?;
+        }
+        if let Some(message) = &self.message {
+            write!(f, ": {message}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Apply every line of a batch in order, keeping the changes only if all of them succeed. On the
+/// first line that fails the system is left as it was, and the lines after it are not run.
+///
+/// Returns a report of every line and whether the batch was committed.
+pub fn apply_batch(bs: &mut BankingSystem, lines: &[(u64, BatchLine)]) -> (Vec<LineReport>, bool) {
+    let savepoint = bs.savepoint();
+    let mut reports = Vec::with_capacity(lines.len());
+    let mut failed = false;
+
+    for (line, batch_line) in lines {
+        let mut report = LineReport {
+            line: *line,
+            op: batch_line.op,
+            status: LineStatus::NotRun,
+            transaction_id: None,
+            reason: None,
+            message: None,
+        };
+        if !failed {
+            match batch_line.apply(bs) {
+                Ok(receipt) => {
+                    report.status = LineStatus::Applied;
+                    report.transaction_id = Some(receipt.transaction_id());
+                },
+                Err(error) => {
+                    failed = true;
+                    report.status = LineStatus::Failed;
+                    report.reason = Some(error_code(&error).to_owned());
+                    report.message = Some(error.to_string());
+                },
+            }
+        }
+        reports.push(report);
+    }
+
+    if failed {
+        for report in &mut reports {
+            if report.status == LineStatus::Applied {
+                report.status = LineStatus::RolledBack;
+                report.transaction_id = None;
+            }
+        }
+        bs.rollback(savepoint);
+    }
+    (reports, !failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::{AccountId, Cents, SignedCents};
+    use crate::currency::Money;
+
+    fn line(op: BatchOp, account: &str, to: Option<&str>, amount: &str) -> BatchLine {
+        BatchLine {
+            op,
+            account: account.to_owned(),
+            to: to.map(str::to_owned),
+            amount: amount.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_read_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let csv = dir.path().join("batch.csv");
+        std::fs::write(
+            &csv,
+            "op,account,to,amount\ncreate,user1,,10\ntransfer, user1, user2, 2.50\n",
+        )
+        .unwrap();
+        assert_eq!(
+            read_batch(&csv).unwrap(),
+            [
+                (2, line(BatchOp::Create, "user1", None, "10")),
+                (3, line(BatchOp::Transfer, "user1", Some("user2"), "2.50")),
+            ]
+        );
+
+        let json = dir.path().join("batch.json");
+        std::fs::write(
+            &json,
+            r#"[{"op": "deposit", "account": "user1", "amount": "5"}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            read_batch(&json).unwrap(),
+            [(1, line(BatchOp::Deposit, "user1", None, "5"))]
+        );
+
+        std::fs::write(&csv, "op,account,to,amount\nrefund,user1,,10\n").unwrap();
+        assert!(read_batch(&csv).is_err());
+    }
+
+    #[test]
+    fn test_apply_batch() {
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+        let lines = Vec::from([
+            (2, line(BatchOp::Create, "user1", None, "10")),
+            (3, line(BatchOp::Create, "user2", None, "0")),
+            (4, line(BatchOp::Transfer, "user1", Some("user2"), "2.50")),
+            (5, line(BatchOp::Withdraw, "user2", None, "1")),
+        ]);
+
+        let (reports, committed) = apply_batch(&mut bs, &lines);
+        assert!(committed);
+        assert!(reports.iter().all(|x| x.status == LineStatus::Applied));
+        assert_eq!(
+            bs.find_account("user2").unwrap().balance(),
+            SignedCents(150)
+        );
+        assert_eq!(
+            reports[3].transaction_id,
+            Some(bs.journal().last().unwrap().id)
+        );
+    }
+
+    #[test]
+    fn test_apply_batch_rolls_back() {
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+        bs.create("user1", Money::usd(Cents(1000))).unwrap();
+        let journal = bs.journal().to_vec();
+        let lines = Vec::from([
+            (2, line(BatchOp::Deposit, "user1", None, "5")),
+            (3, line(BatchOp::Withdraw, "user1", None, "20")),
+            (4, line(BatchOp::Transfer, "user1", None, "1")),
+        ]);
+
+        let (reports, committed) = apply_batch(&mut bs, &lines);
+        assert!(!committed);
+        assert_eq!(
+            reports
+                .iter()
+                .map(|x| (x.line, x.status, x.reason.as_deref()))
+                .collect::<Vec<_>>(),
+            [
+                (2, LineStatus::RolledBack, None),
+                (3, LineStatus::Failed, Some("account_overdraft")),
+                (4, LineStatus::NotRun, None),
+            ]
+        );
+        assert_eq!(bs.journal(), journal);
+        assert_eq!(
+            bs.get_account(AccountId(1)).unwrap().balance(),
+            SignedCents(1000)
+        );
+
+        // A transfer must name the account transferred to
+        let (reports, _) = apply_batch(&mut bs, &lines[2..]);
+        assert_eq!(
+            reports[0].reason.as_deref(),
+            Some("missing_transfer_target")
+        );
+    }
+}
@@ -0,0 +1,364 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::exchange::RoundingMode;
+use crate::interest::Product;
+
+/// Accounts file used when no data file or ledger is configured.
+pub const DEFAULT_PATH: &str = "./banking_system.csv";
+/// Database used with SQLite storage when no data file or ledger is configured.
+#[cfg(feature = "sqlite")]
+pub const DEFAULT_SQLITE_PATH: &str = "./banking_system.db";
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("could not read config file {}: {source}", path.display())]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid config file {}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("ledger {0} is not defined in the config file")]
+    UnknownLedger(String),
+}
+
+impl ConfigError {
+    /// Stable identifier of the error for machine-readable output.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Read { .. } => "config_read",
+            Self::Parse { .. } => "config_parse",
+            Self::UnknownLedger(_) => "unknown_ledger",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// CSV file for accounts with the journal in a second CSV file next to it
+    Csv,
+    /// SQLite database
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+impl StorageBackend {
+    /// Backend for a data file based on its extension. `.db`, `.sqlite` and `.sqlite3` files are
+    /// SQLite databases, everything else is CSV.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|x| x.to_str()) {
+            #[cfg(feature = "sqlite")]
+            Some("db" | "sqlite" | "sqlite3") => Self::Sqlite,
+            _ => Self::Csv,
+        }
+    }
+}
+
+/// Location and storage backend of a ledger.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct LedgerConfig {
+    pub path: PathBuf,
+    /// Backend to store the ledger with, inferred from the path if not given.
+    pub storage: Option<StorageBackend>,
+}
+
+impl LedgerConfig {
+    pub fn storage(&self) -> StorageBackend {
+        self.storage
+            .unwrap_or_else(|| StorageBackend::from_path(&self.path))
+    }
+}
+
+/// Conversion of transfers between accounts in different currencies.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ExchangeConfig {
+    /// CSV file of exchange rates with their effective dates. Transfers between currencies are
+    /// rejected without one.
+    pub rates: Option<PathBuf>,
+    /// Rounding of converted amounts, half-even if not given.
+    pub rounding: Option<RoundingMode>,
+    /// Part of every converted amount credited to the house account, in basis points.
+    #[serde(default)]
+    pub spread_bps: u32,
+}
+
+/// Configuration file defining named ledgers, e.g.
+///
+/// ```toml
+/// default_ledger = "main"
+/// lock_timeout = 30
+///
+/// [exchange]
+/// rates = "rates.csv"
+/// rounding = "half-up"
+/// spread_bps = 25
+///
+/// [products.savings]
+/// rate = "2.5"
+/// day_count = "act/365"
+///
+/// [products.checking.fees]
+/// withdraw = { flat = "1.00" }
+/// transfer = { percent = "0.5", min = "0.25", max = "5" }
+///
+/// [ledgers.main]
+/// path = "/var/lib/banking-rs/main.csv"
+///
+/// [ledgers.test]
+/// path = "test.db"
+/// storage = "sqlite"
+/// ```
+///
+/// Relative ledger and rates paths are relative to the directory of the configuration file.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Ledger used when none is selected.
+    pub default_ledger: Option<String>,
+    /// Seconds to wait for another process to release the data file.
+    pub lock_timeout: Option<f64>,
+    #[serde(default)]
+    pub ledgers: BTreeMap<String, LedgerConfig>,
+    #[serde(default)]
+    pub exchange: ExchangeConfig,
+    /// Products setting the interest accounts earn and the fees they are charged, by name.
+    #[serde(default)]
+    pub products: BTreeMap<String, Product>,
+}
+
+impl Config {
+    /// Configuration file in the user's configuration directory, e.g.
+    /// `~/.config/banking-rs/config.toml` on Linux.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|x| x.join("banking-rs").join("config.toml"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_owned(),
+            source,
+        })?;
+        let mut config = toml::from_str::<Self>(&text).map_err(|source| ConfigError::Parse {
+            path: path.to_owned(),
+            source,
+        })?;
+
+        if let Some(dir) = path.parent() {
+            for ledger in config.ledgers.values_mut() {
+                if ledger.path.is_relative() {
+                    ledger.path = dir.join(&ledger.path);
+                }
+            }
+            if let Some(rates) = config.exchange.rates.as_mut() {
+                if rates.is_relative() {
+                    *rates = dir.join(&*rates);
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Load the configuration file at the default path, if there is one.
+    pub fn load_default() -> Result<Self, ConfigError> {
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::load(&path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Select the ledger to use.
+    ///
+    /// An explicit data file takes precedence over a named ledger, which takes precedence over the
+    /// default ledger of the configuration. Without any of them the default path in the current
+    /// directory is used. An explicit storage backend overrides the configured one.
+    pub fn resolve(
+        &self,
+        data: Option<&Path>,
+        ledger: Option<&str>,
+        storage: Option<StorageBackend>,
+    ) -> Result<LedgerConfig, ConfigError> {
+        let resolved = match (data, ledger.or(self.default_ledger.as_deref())) {
+            (Some(path), _) => LedgerConfig {
+                path: path.to_owned(),
+                storage: None,
+            },
+            (None, Some(name)) => self
+                .ledgers
+                .get(name)
+                .cloned()
+                .ok_or_else(|| ConfigError::UnknownLedger(name.to_owned()))?,
+            (None, None) => {
+                let path = match storage {
+                    #[cfg(feature = "sqlite")]
+                    Some(StorageBackend::Sqlite) => DEFAULT_SQLITE_PATH,
+                    _ => DEFAULT_PATH,
+                };
+                LedgerConfig {
+                    path: PathBuf::from(path),
+                    storage: None,
+                }
+            },
+        };
+
+        Ok(LedgerConfig {
+            storage: storage.or(resolved.storage),
+            ..resolved
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fee::FeeSchedule;
+    use crate::interest::DayCount;
+
+    const CONFIG: &str = r#"
+default_ledger = "main"
+lock_timeout = 30
+
+[ledgers.main]
+path = "/var/lib/banking-rs/main.csv"
+
+[ledgers.branch]
+path = "branch.csv"
+storage = "csv"
+
+[exchange]
+rates = "rates.csv"
+rounding = "half-up"
+spread_bps = 25
+
+[products.savings]
+rate = "2.5"
+
+[products.business]
+rate = "1.25"
+day_count = "30/360"
+
+[products.business.fees]
+withdraw = { flat = "1" }
+transfer = { percent = "0.5", min = "0.25", max = "5" }
+"#;
+
+    fn write_config(dir: &Path) -> PathBuf {
+        let path = dir.join("config.toml");
+        std::fs::write(&path, CONFIG).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::load(&write_config(dir.path())).unwrap();
+
+        assert_eq!(config.default_ledger.as_deref(), Some("main"));
+        assert_eq!(config.lock_timeout, Some(30.0));
+        assert_eq!(
+            config.ledgers["main"].path,
+            Path::new("/var/lib/banking-rs/main.csv")
+        );
+        assert_eq!(config.ledgers["branch"].path, dir.path().join("branch.csv"));
+        assert_eq!(config.ledgers["branch"].storage, Some(StorageBackend::Csv));
+        assert_eq!(
+            config.exchange,
+            ExchangeConfig {
+                rates: Some(dir.path().join("rates.csv")),
+                rounding: Some(RoundingMode::HalfUp),
+                spread_bps: 25,
+            }
+        );
+        assert_eq!(
+            config.products["savings"].rate.map(|x| x.to_string()),
+            Some(String::from("2.5"))
+        );
+        assert_eq!(config.products["savings"].day_count, DayCount::Act365);
+        assert_eq!(config.products["business"].day_count, DayCount::Thirty360);
+        assert_eq!(config.products["savings"].fees, FeeSchedule::default());
+        let fees = &config.products["business"].fees;
+        assert_eq!(fees.withdraw.as_ref().unwrap().flat.as_deref(), Some("1"));
+        assert_eq!(fees.transfer.as_ref().unwrap().max.as_deref(), Some("5"));
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+
+        assert!(matches!(Config::load(&path), Err(ConfigError::Read { .. })));
+
+        std::fs::write(&path, "unknown = 1").unwrap();
+        assert!(matches!(
+            Config::load(&path),
+            Err(ConfigError::Parse { .. })
+        ));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::load(&write_config(dir.path())).unwrap();
+
+        assert_eq!(
+            config.resolve(None, None, None).unwrap().path,
+            Path::new("/var/lib/banking-rs/main.csv")
+        );
+        assert_eq!(
+            config.resolve(None, Some("branch"), None).unwrap().path,
+            dir.path().join("branch.csv")
+        );
+        assert_eq!(
+            config
+                .resolve(Some(Path::new("other.csv")), Some("branch"), None)
+                .unwrap()
+                .path,
+            Path::new("other.csv")
+        );
+        assert!(matches!(
+            config.resolve(None, Some("missing"), None),
+            Err(ConfigError::UnknownLedger(name)) if name == "missing"
+        ));
+
+        let ledger = Config::default().resolve(None, None, None).unwrap();
+        assert_eq!(ledger.path, Path::new(DEFAULT_PATH));
+        assert_eq!(ledger.storage(), StorageBackend::Csv);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_resolve_storage() {
+        let config = Config::default();
+
+        let ledger = config
+            .resolve(None, None, Some(StorageBackend::Sqlite))
+            .unwrap();
+        assert_eq!(ledger.path, Path::new(DEFAULT_SQLITE_PATH));
+        assert_eq!(ledger.storage(), StorageBackend::Sqlite);
+
+        let ledger = config
+            .resolve(Some(Path::new("ledger.db")), None, None)
+            .unwrap();
+        assert_eq!(ledger.storage(), StorageBackend::Sqlite);
+
+        let ledger = config
+            .resolve(
+                Some(Path::new("ledger.db")),
+                None,
+                Some(StorageBackend::Csv),
+            )
+            .unwrap();
+        assert_eq!(ledger.storage(), StorageBackend::Csv);
+    }
+}
@@ -0,0 +1,441 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::account::{Cents, SignedCents};
+use crate::banking_system::BankingSystemError;
+
+/// Active ISO 4217 currency codes and the number of digits of their minor unit.
+#[rustfmt::skip]
+const CURRENCIES: &[(&str, u8)] = &[
+    ("AED", 2), ("AFN", 2), ("ALL", 2), ("AMD", 2), ("ANG", 2), ("AOA", 2), ("ARS", 2),
+    ("AUD", 2), ("AWG", 2), ("AZN", 2), ("BAM", 2), ("BBD", 2), ("BDT", 2), ("BGN", 2),
+    ("BHD", 3), ("BIF", 0), ("BMD", 2), ("BND", 2), ("BOB", 2), ("BRL", 2), ("BSD", 2),
+    ("BTN", 2), ("BWP", 2), ("BYN", 2), ("BZD", 2), ("CAD", 2), ("CDF", 2), ("CHF", 2),
+    ("CLF", 4), ("CLP", 0), ("CNY", 2), ("COP", 2), ("CRC", 2), ("CUP", 2), ("CVE", 2),
+    ("CZK", 2), ("DJF", 0), ("DKK", 2), ("DOP", 2), ("DZD", 2), ("EGP", 2), ("ERN", 2),
+    ("ETB", 2), ("EUR", 2), ("FJD", 2), ("FKP", 2), ("GBP", 2), ("GEL", 2), ("GHS", 2),
+    ("GIP", 2), ("GMD", 2), ("GNF", 0), ("GTQ", 2), ("GYD", 2), ("HKD", 2), ("HNL", 2),
+    ("HTG", 2), ("HUF", 2), ("IDR", 2), ("ILS", 2), ("INR", 2), ("IQD", 3), ("IRR", 2),
+    ("ISK", 0), ("JMD", 2), ("JOD", 3), ("JPY", 0), ("KES", 2), ("KGS", 2), ("KHR", 2),
+    ("KMF", 0), ("KPW", 2), ("KRW", 0), ("KWD", 3), ("KYD", 2), ("KZT", 2), ("LAK", 2),
+    ("LBP", 2), ("LKR", 2), ("LRD", 2), ("LSL", 2), ("LYD", 3), ("MAD", 2), ("MDL", 2),
+    ("MGA", 2), ("MKD", 2), ("MMK", 2), ("MNT", 2), ("MOP", 2), ("MRU", 2), ("MUR", 2),
+    ("MVR", 2), ("MWK", 2), ("MXN", 2), ("MYR", 2), ("MZN", 2), ("NAD", 2), ("NGN", 2),
+    ("NIO", 2), ("NOK", 2), ("NPR", 2), ("NZD", 2), ("OMR", 3), ("PAB", 2), ("PEN", 2),
+    ("PGK", 2), ("PHP", 2), ("PKR", 2), ("PLN", 2), ("PYG", 0), ("QAR", 2), ("RON", 2),
+    ("RSD", 2), ("RUB", 2), ("RWF", 0), ("SAR", 2), ("SBD", 2), ("SCR", 2), ("SDG", 2),
+    ("SEK", 2), ("SGD", 2), ("SHP", 2), ("SLE", 2), ("SOS", 2), ("SRD", 2), ("SSP", 2),
+    ("STN", 2), ("SVC", 2), ("SYP", 2), ("SZL", 2), ("THB", 2), ("TJS", 2), ("TMT", 2),
+    ("TND", 3), ("TOP", 2), ("TRY", 2), ("TTD", 2), ("TWD", 2), ("TZS", 2), ("UAH", 2),
+    ("UGX", 0), ("USD", 2), ("UYI", 0), ("UYU", 2), ("UYW", 4), ("UZS", 2), ("VED", 2),
+    ("VES", 2), ("VND", 0), ("VUV", 0), ("WST", 2), ("XAF", 0), ("XCD", 2), ("XOF", 0),
+    ("XPF", 0), ("YER", 2), ("ZAR", 2), ("ZMW", 2), ("ZWG", 2),
+];
+
+/// ISO 4217 currency, which determines the number of decimal places of amounts in it.
+///
+/// Amounts are stored as whole numbers of the minor unit of their currency, e.g. cents for US
+/// dollars, yen for Japanese yen and fils for Kuwaiti dinars.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Currency {
+    code: [u8; 3],
+    exponent: u8,
+}
+
+impl Default for Currency {
+    /// US dollars, the currency of accounts stored before accounts had a currency.
+    fn default() -> Self {
+        Self::USD
+    }
+}
+
+impl Currency {
+    pub const USD: Self = Self::new(*b"USD", 2);
+    pub const EUR: Self = Self::new(*b"EUR", 2);
+    pub const GBP: Self = Self::new(*b"GBP", 2);
+    pub const JPY: Self = Self::new(*b"JPY", 0);
+    pub const KWD: Self = Self::new(*b"KWD", 3);
+
+    const fn new(code: [u8; 3], exponent: u8) -> Self {
+        Self { code, exponent }
+    }
+
+    /// Three letter ISO 4217 code, e.g. `EUR`.
+    pub fn code(&self) -> &str {
+        std::str::from_utf8(&self.code).expect("currency code should be ASCII")
+    }
+
+    /// Number of decimal places of the minor unit, e.g. 2 for US dollars and 0 for Japanese yen.
+    pub const fn exponent(self) -> u32 {
+        self.exponent as u32
+    }
+
+    /// Number of minor units in one major unit.
+    const fn scale(self) -> u64 {
+        10u64.pow(self.exponent as u32)
+    }
+
+    fn symbol(self) -> Option<&'static str> {
+        match &self.code {
+            b"USD" => Some("$"),
+            b"EUR" => Some("€"),
+            b"GBP" => Some("£"),
+            b"JPY" => Some("¥"),
+            _ => None,
+        }
+    }
+
+    /// Write an amount of minor units with the symbol of the currency, or its code if it has no
+    /// common symbol, e.g. `$1.05`, `-€1.05`, `¥105` or `KWD 1.050`.
+    pub(crate) fn fmt_amount(
+        self,
+        f: &mut std::fmt::Formatter<'_>,
+        amount: i128,
+    ) -> std::fmt::Result {
+        let sign = if amount < 0 { "-" } else { "" };
+        match self.symbol() {
+            Some(symbol) => write!(f, "{sign}{symbol}")?,
+            None => write!(f, "{sign}{} ", self.code())?,
+        }
+
+        let amount = amount.unsigned_abs();
+        let scale = self.scale() as u128;
+        write!(f, "{}", amount / scale)?;
+        if self.exponent > 0 {
+            write!(
+                f,
+                ".{:0width$}",
+                amount % scale,
+                width = self.exponent as usize
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Parse a non-negative amount with up to as many decimal places as the currency has.
+    pub fn parse_amount(self, s: &str) -> Result<Cents, BankingSystemError> {
+        parse_minor_units(s, self.exponent()).map(Cents)
+    }
+
+    /// Parse an amount like `parse_amount`, with an optional leading `-` or `+` sign.
+    pub fn parse_signed_amount(self, s: &str) -> Result<SignedCents, BankingSystemError> {
+        let (negative, unsigned) = match s.strip_prefix('-') {
+            Some(unsigned) => (true, unsigned),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        // Unsigned amounts accept a leading plus sign of their own, which must not follow another
+        // sign
+        if unsigned.starts_with(['-', '+']) {
+            return Err(BankingSystemError::InvalidSignedAmount(s.to_owned()));
+        }
+
+        let cents = self.parse_amount(unsigned).map_err(|error| match error {
+            BankingSystemError::AmountOverflow(_) => {
+                BankingSystemError::AmountOverflow(s.to_owned())
+            },
+            _ => BankingSystemError::InvalidSignedAmount(s.to_owned()),
+        })?;
+        let amount = if negative {
+            SignedCents::ZERO.checked_sub_cents(cents)
+        } else {
+            SignedCents::ZERO.checked_add_cents(cents)
+        };
+        amount.ok_or(BankingSystemError::AmountOverflow(s.to_owned()))
+    }
+}
+
+/// Parse a non-negative number with up to `exponent` decimal places into a whole number of minor
+/// units. The string must only contain digits and up to one period as a decimal separator.
+pub(crate) fn parse_minor_units(s: &str, exponent: u32) -> Result<u64, BankingSystemError> {
+    let invalid = || BankingSystemError::InvalidAmount(s.to_owned());
+    let overflow = || BankingSystemError::AmountOverflow(s.to_owned());
+    let scale = 10u64.pow(exponent);
+
+    // Has no decimal part
+    if !s.contains('.') {
+        return s
+            .parse::<u64>()
+            .map_err(|_| invalid())?
+            .checked_mul(scale)
+            .ok_or_else(overflow);
+    }
+
+    let (int_part_str, dec_part_str) = s
+        .rsplit_once('.')
+        .expect("parse_minor_units string should have period");
+
+    let integer_part = if int_part_str.chars().count() < 1 {
+        0 // Number has no leading zero
+    } else {
+        int_part_str.parse::<u64>().map_err(|_| invalid())?
+    };
+
+    // Invalid decimal part length
+    let decimals = dec_part_str.chars().count();
+    if decimals < 1 || decimals > exponent as usize {
+        return Err(invalid());
+    }
+    if !dec_part_str.bytes().all(|x| x.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    // Decimal part may stop before the last decimal place
+    let decimal_part =
+        dec_part_str.parse::<u64>().map_err(|_| invalid())? * 10u64.pow(exponent - decimals as u32);
+
+    integer_part
+        .checked_mul(scale)
+        .ok_or_else(overflow)?
+        .checked_add(decimal_part)
+        .ok_or_else(overflow)
+}
+
+impl Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+/// Parse an ISO 4217 currency code, in upper or lower case.
+impl FromStr for Currency {
+    type Err = BankingSystemError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = s.to_ascii_uppercase();
+        CURRENCIES
+            .iter()
+            .find(|(x, _)| *x == code)
+            .map(|(x, exponent)| {
+                let code = x.as_bytes();
+                Self::new([code[0], code[1], code[2]], *exponent)
+            })
+            .ok_or(BankingSystemError::UnknownCurrency(s.to_owned()))
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Amount of money in a currency. The amount is in the minor unit of the currency, and is signed
+/// for amounts such as adjustments that can go either way.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub struct Money<A = Cents> {
+    pub(crate) amount: A,
+    pub(crate) currency: Currency,
+}
+
+impl<A: Copy> Money<A> {
+    pub const fn new(amount: A, currency: Currency) -> Self {
+        Self { amount, currency }
+    }
+
+    pub fn amount(&self) -> A {
+        self.amount
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+}
+
+impl Money {
+    /// Amount in US dollars, the default currency.
+    pub const fn usd(amount: Cents) -> Self {
+        Self::new(amount, Currency::USD)
+    }
+
+    /// Parse an amount followed or preceded by a currency code, such as `10.50 EUR`. Amounts
+    /// without a code are in the given currency.
+    pub fn parse(s: &str, currency: Currency) -> Result<Self, BankingSystemError> {
+        let (amount, currency) = split_currency(s, currency)?;
+        Ok(Self::new(currency.parse_amount(amount)?, currency))
+    }
+}
+
+impl Money<SignedCents> {
+    /// Parse a signed amount like `Money::parse`, e.g. `-10.50 EUR`.
+    pub fn parse_signed(s: &str, currency: Currency) -> Result<Self, BankingSystemError> {
+        let (amount, currency) = split_currency(s, currency)?;
+        Ok(Self::new(currency.parse_signed_amount(amount)?, currency))
+    }
+}
+
+/// Split an amount from the currency code written before or after it.
+fn split_currency(s: &str, currency: Currency) -> Result<(&str, Currency), BankingSystemError> {
+    let mut parts = s.split_whitespace();
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(amount), None, None) => Ok((amount, currency)),
+        (Some(first), Some(second), None) => {
+            // The code is the part that starts with a letter
+            if first.starts_with(|x: char| x.is_ascii_alphabetic()) {
+                Ok((second, first.parse()?))
+            } else {
+                Ok((first, second.parse()?))
+            }
+        },
+        _ => Err(BankingSystemError::InvalidAmount(s.to_owned())),
+    }
+}
+
+impl Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.currency.fmt_amount(f, self.amount.0 as i128)
+    }
+}
+
+impl Display for Money<SignedCents> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.currency.fmt_amount(f, self.amount.0 as i128)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_currency() {
+        assert_eq!(Currency::from_str("EUR"), Ok(Currency::EUR));
+        assert_eq!(Currency::from_str("jpy"), Ok(Currency::JPY));
+        assert_eq!(Currency::from_str("KWD").unwrap().exponent(), 3);
+        assert_eq!(Currency::from_str("CLF").unwrap().exponent(), 4);
+        for s in ["", "EU", "EURO", "XYZ", "$"] {
+            assert_eq!(
+                Currency::from_str(s),
+                Err(BankingSystemError::UnknownCurrency(s.to_owned()))
+            );
+        }
+        assert_eq!(Currency::default(), Currency::USD);
+        assert_eq!(Currency::GBP.to_string(), "GBP");
+    }
+
+    #[test]
+    fn test_currency_table() {
+        for (code, exponent) in CURRENCIES {
+            let currency = Currency::from_str(code).unwrap();
+            assert_eq!(currency.code(), *code);
+            assert_eq!(currency.exponent(), *exponent as u32);
+        }
+        assert!(CURRENCIES.windows(2).all(|x| x[0].0 < x[1].0));
+        for currency in [
+            Currency::USD,
+            Currency::EUR,
+            Currency::GBP,
+            Currency::JPY,
+            Currency::KWD,
+        ] {
+            assert_eq!(Currency::from_str(currency.code()), Ok(currency));
+        }
+    }
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(Currency::EUR.parse_amount("1.05"), Ok(Cents(105)));
+        assert_eq!(Currency::JPY.parse_amount("105"), Ok(Cents(105)));
+        assert_eq!(Currency::KWD.parse_amount("1.05"), Ok(Cents(1050)));
+        assert_eq!(Currency::KWD.parse_amount("1.005"), Ok(Cents(1005)));
+        assert_eq!(Currency::KWD.parse_amount(".5"), Ok(Cents(500)));
+        for (currency, s) in [
+            (Currency::JPY, "1.0"),
+            (Currency::JPY, "1."),
+            (Currency::EUR, "1.005"),
+            (Currency::KWD, "1.0005"),
+            (Currency::KWD, "1.+05"),
+        ] {
+            assert_eq!(
+                currency.parse_amount(s),
+                Err(BankingSystemError::InvalidAmount(s.to_owned()))
+            );
+        }
+        let overflow = (u64::MAX / 1000 + 1).to_string();
+        assert_eq!(
+            Currency::KWD.parse_amount(&overflow),
+            Err(BankingSystemError::AmountOverflow(overflow))
+        );
+        assert_eq!(
+            Currency::JPY.parse_signed_amount("-105"),
+            Ok(SignedCents(-105))
+        );
+    }
+
+    #[test]
+    fn test_parse_money() {
+        assert_eq!(
+            Money::parse("10.50", Currency::EUR),
+            Ok(Money::new(Cents(1050), Currency::EUR))
+        );
+        assert_eq!(
+            Money::parse("10.50 gbp", Currency::EUR),
+            Ok(Money::new(Cents(1050), Currency::GBP))
+        );
+        assert_eq!(
+            Money::parse("JPY 1050", Currency::EUR),
+            Ok(Money::new(Cents(1050), Currency::JPY))
+        );
+        assert_eq!(
+            Money::parse_signed("-1.050 KWD", Currency::USD),
+            Ok(Money::new(SignedCents(-1050), Currency::KWD))
+        );
+        assert_eq!(
+            Money::parse("10.50 XYZ", Currency::EUR),
+            Err(BankingSystemError::UnknownCurrency(String::from("XYZ")))
+        );
+        assert_eq!(
+            Money::parse("10.50 EUR EUR", Currency::EUR),
+            Err(BankingSystemError::InvalidAmount(String::from(
+                "10.50 EUR EUR"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_display_money() {
+        assert_eq!(Money::usd(Cents(105)).to_string(), "$1.05");
+        assert_eq!(Money::new(Cents(105), Currency::EUR).to_string(), "€1.05");
+        assert_eq!(Money::new(Cents(5), Currency::GBP).to_string(), "£0.05");
+        assert_eq!(Money::new(Cents(105), Currency::JPY).to_string(), "¥105");
+        assert_eq!(
+            Money::new(Cents(1005), Currency::KWD).to_string(),
+            "KWD 1.005"
+        );
+        assert_eq!(
+            Money::new(SignedCents(-1005), Currency::KWD).to_string(),
+            "-KWD 1.005"
+        );
+        assert_eq!(
+            Money::new(SignedCents(-105), Currency::EUR).to_string(),
+            "-€1.05"
+        );
+    }
+
+    #[test]
+    fn test_currency_serde() {
+        let mut wtr = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(Vec::new());
+        wtr.serialize(Currency::KWD).unwrap();
+        let data = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+        assert_eq!(data, "KWD\n");
+
+        let currency = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(data.as_bytes())
+            .deserialize::<Currency>()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(currency, Currency::KWD);
+    }
+}
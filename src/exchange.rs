@@ -0,0 +1,456 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs::File;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::account::Cents;
+use crate::banking_system::BankingSystemError;
+use crate::currency::{parse_minor_units, Currency, Money};
+
+/// Number of decimal places of an exchange rate.
+const RATE_DECIMALS: u32 = 9;
+pub(crate) const RATE_SCALE: u128 = 10u128.pow(RATE_DECIMALS);
+
+/// Basis points in a whole amount.
+const BASIS_POINTS: u32 = 10_000;
+
+/// Units of one currency per unit of another, with up to 9 decimal places.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Rate(pub(crate) u64);
+
+impl Rate {
+    /// Rate of the opposite direction, rounded to the nearest representable rate. None if it is
+    /// too small to be represented.
+    pub fn inverse(self) -> Option<Self> {
+        let inverse = RoundingMode::HalfEven.divide(RATE_SCALE * RATE_SCALE, self.0 as u128);
+        u64::try_from(inverse).ok().filter(|x| *x > 0).map(Self)
+    }
+}
+
+impl FromStr for Rate {
+    type Err = BankingSystemError;
+
+    /// Parse a positive decimal number, e.g. `1.0845`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match parse_minor_units(s, RATE_DECIMALS) {
+            Ok(rate) if rate > 0 => Ok(Self(rate)),
+            _ => Err(BankingSystemError::InvalidRate(s.to_owned())),
+        }
+    }
+}
+
+impl Display for Rate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scale = RATE_SCALE as u64;
+        write!(f, "{}", self.0 / scale)?;
+        let decimals = format!("{:09}", self.0 % scale);
+        let decimals = decimals.trim_end_matches('0');
+        if !decimals.is_empty() {
+            write!(f, ".{decimals}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for Rate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// How converted amounts that fall between two minor units are rounded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum RoundingMode {
+    /// To the nearest unit, and halfway amounts to the even one
+    #[default]
+    HalfEven,
+    /// To the nearest unit, and halfway amounts up
+    HalfUp,
+    /// Towards zero
+    Down,
+    /// Away from zero
+    Up,
+}
+
+impl RoundingMode {
+    /// Divide rounding the quotient to a whole number.
+    pub fn divide(self, numerator: u128, denominator: u128) -> u128 {
+        let quotient = numerator / denominator;
+        let remainder = numerator % denominator;
+        let round_up = match self {
+            Self::Down => false,
+            Self::Up => remainder > 0,
+            Self::HalfUp => remainder >= denominator - remainder,
+            Self::HalfEven => match remainder.cmp(&(denominator - remainder)) {
+                std::cmp::Ordering::Less => false,
+                std::cmp::Ordering::Equal => quotient % 2 == 1,
+                std::cmp::Ordering::Greater => true,
+            },
+        };
+        quotient + round_up as u128
+    }
+}
+
+/// Exchange rate between two currencies from the date it takes effect.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RateRecord {
+    pub from: Currency,
+    pub to: Currency,
+    pub rate: Rate,
+    pub effective: NaiveDate,
+}
+
+/// Exchange rates by currency pair, each in effect until the next rate of the pair.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RateTable {
+    rates: BTreeMap<(Currency, Currency), Vec<(NaiveDate, Rate)>>,
+}
+
+impl FromIterator<RateRecord> for RateTable {
+    fn from_iter<T: IntoIterator<Item = RateRecord>>(iter: T) -> Self {
+        let mut table = Self::default();
+        for record in iter {
+            table.insert(record);
+        }
+        table
+    }
+}
+
+impl RateTable {
+    /// Read rates from a CSV file with `from`, `to`, `rate` and `effective` columns, e.g.
+    ///
+    /// ```csv
+    /// from,to,rate,effective
+    /// EUR,USD,1.0845,2024-01-01
+    /// ```
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(::csv::Reader::from_reader(File::open(path)?)
+            .deserialize::<RateRecord>()
+            .collect::<Result<Self, _>>()?)
+    }
+
+    /// Add a rate. A later rate of the same pair and date replaces the earlier one.
+    pub fn insert(&mut self, record: RateRecord) {
+        let rates = self.rates.entry((record.from, record.to)).or_default();
+        let position = rates.partition_point(|(date, _)| *date <= record.effective);
+        if position > 0 && rates[position - 1].0 == record.effective {
+            rates[position - 1].1 = record.rate;
+        } else {
+            rates.insert(position, (record.effective, record.rate));
+        }
+    }
+
+    /// Rate in effect on the date, which is the latest rate of the pair effective on or before
+    /// it. A pair only listed in the opposite direction uses the inverse of that rate.
+    pub fn rate(&self, from: Currency, to: Currency, date: NaiveDate) -> Option<Rate> {
+        let effective = |from, to| {
+            let rates = self.rates.get(&(from, to))?;
+            let position = rates.partition_point(|(effective, _)| *effective <= date);
+            position.checked_sub(1).map(|x| rates[x].1)
+        };
+        effective(from, to).or_else(|| effective(to, from)?.inverse())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rates.is_empty()
+    }
+}
+
+/// Amounts of a foreign exchange, from the amount taken in one currency to the amount paid out in
+/// another.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub struct Conversion {
+    pub(crate) source: Money,
+    pub(crate) rate: Rate,
+    /// Source amount converted at the rate, less the spread.
+    pub(crate) destination: Money,
+    /// Part of the converted amount kept by the house, in the destination currency.
+    pub(crate) spread: Money,
+}
+
+impl Display for Conversion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} converted at {} to {}",
+            self.source, self.rate, self.destination
+        )?;
+        if self.spread.amount != Cents::ZERO {
+            write!(f, " after a spread of {}", self.spread)?;
+        }
+        Ok(())
+    }
+}
+
+impl Conversion {
+    pub fn source(&self) -> Money {
+        self.source
+    }
+
+    pub fn rate(&self) -> Rate {
+        self.rate
+    }
+
+    pub fn destination(&self) -> Money {
+        self.destination
+    }
+
+    pub fn spread(&self) -> Money {
+        self.spread
+    }
+}
+
+/// Conversion of amounts between currencies at the rates of a rate table, keeping a spread of
+/// the converted amount.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ForeignExchange {
+    rates: RateTable,
+    rounding: RoundingMode,
+    /// Part of every converted amount kept by the house, in hundredths of a percent.
+    spread_bps: u32,
+}
+
+impl ForeignExchange {
+    pub fn new(
+        rates: RateTable,
+        rounding: RoundingMode,
+        spread_bps: u32,
+    ) -> Result<Self, BankingSystemError> {
+        if spread_bps > BASIS_POINTS {
+            return Err(BankingSystemError::InvalidSpread(spread_bps));
+        }
+        Ok(Self {
+            rates,
+            rounding,
+            spread_bps,
+        })
+    }
+
+    pub fn rates(&self) -> &RateTable {
+        &self.rates
+    }
+
+    pub fn rounding(&self) -> RoundingMode {
+        self.rounding
+    }
+
+    pub fn spread_bps(&self) -> u32 {
+        self.spread_bps
+    }
+
+    /// Convert an amount to another currency at the rate in effect on the date. The converted
+    /// amount and the spread taken from it are both rounded to the minor unit of the currency.
+    pub fn convert(
+        &self,
+        amount: Money,
+        to: Currency,
+        date: NaiveDate,
+    ) -> Result<Conversion, BankingSystemError> {
+        let from = amount.currency;
+        let rate = self
+            .rates
+            .rate(from, to, date)
+            .ok_or(BankingSystemError::NoExchangeRate { from, to, date })?;
+        let overflow = || BankingSystemError::AmountOverflow(amount.to_string());
+
+        // Scale between the minor units of the currencies, e.g. cents to yen
+        let (multiplier, divisor) = if to.exponent() >= from.exponent() {
+            (10u128.pow(to.exponent() - from.exponent()), RATE_SCALE)
+        } else {
+            (1, RATE_SCALE * 10u128.pow(from.exponent() - to.exponent()))
+        };
+        let converted = (amount.amount.0 as u128)
+            .checked_mul(rate.0 as u128)
+            .and_then(|x| x.checked_mul(multiplier))
+            .ok_or_else(overflow)?;
+        let converted = self.rounding.divide(converted, divisor);
+        let spread = self
+            .rounding
+            .divide(converted * self.spread_bps as u128, BASIS_POINTS as u128);
+        let destination = u64::try_from(converted - spread).map_err(|_| overflow())?;
+
+        Ok(Conversion {
+            source: amount,
+            rate,
+            destination: Money::new(Cents(destination), to),
+            spread: Money::new(Cents(spread as u64), to),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    fn record(from: Currency, to: Currency, rate: &str, effective: &str) -> RateRecord {
+        RateRecord {
+            from,
+            to,
+            rate: rate.parse().unwrap(),
+            effective: date(effective),
+        }
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(Rate::from_str("1.0845"), Ok(Rate(1_084_500_000)));
+        assert_eq!(Rate::from_str("150"), Ok(Rate(150_000_000_000)));
+        assert_eq!(Rate::from_str("0.000000001"), Ok(Rate(1)));
+        assert_eq!(Rate(1_084_500_000).to_string(), "1.0845");
+        assert_eq!(Rate(150_000_000_000).to_string(), "150");
+        for s in ["0", "0.0", "-1", "1.0000000001", "", "abc"] {
+            assert_eq!(
+                Rate::from_str(s),
+                Err(BankingSystemError::InvalidRate(s.to_owned()))
+            );
+        }
+        assert_eq!(
+            Rate::from_str("0.8").unwrap().inverse(),
+            Some(Rate(1_250_000_000))
+        );
+    }
+
+    #[test]
+    fn test_rounding() {
+        let cases: [(RoundingMode, [u128; 5]); 4] = [
+            (RoundingMode::HalfEven, [2, 2, 2, 3, 4]),
+            (RoundingMode::HalfUp, [2, 2, 3, 3, 4]),
+            (RoundingMode::Down, [2, 2, 2, 2, 3]),
+            (RoundingMode::Up, [2, 3, 3, 3, 4]),
+        ];
+        // 2.0, 2.25, 2.5, 2.75 and 3.5
+        for (mode, expected) in cases {
+            let rounded = [(8, 4), (9, 4), (10, 4), (11, 4), (14, 4)]
+                .map(|(numerator, denominator)| mode.divide(numerator, denominator));
+            assert_eq!(rounded, expected, "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn test_effective_rates() {
+        let table = RateTable::from_iter([
+            record(Currency::EUR, Currency::USD, "1.10", "2024-01-01"),
+            record(Currency::EUR, Currency::USD, "1.20", "2024-06-01"),
+            record(Currency::EUR, Currency::USD, "1.25", "2024-06-01"),
+        ]);
+
+        let rate = |from, to, s| table.rate(from, to, date(s)).map(|x| x.to_string());
+        assert_eq!(rate(Currency::EUR, Currency::USD, "2023-12-31"), None);
+        assert_eq!(
+            rate(Currency::EUR, Currency::USD, "2024-01-01").as_deref(),
+            Some("1.1")
+        );
+        assert_eq!(
+            rate(Currency::EUR, Currency::USD, "2024-05-31").as_deref(),
+            Some("1.1")
+        );
+        assert_eq!(
+            rate(Currency::EUR, Currency::USD, "2025-01-01").as_deref(),
+            Some("1.25")
+        );
+        assert_eq!(
+            rate(Currency::USD, Currency::EUR, "2025-01-01").as_deref(),
+            Some("0.8")
+        );
+        assert_eq!(rate(Currency::EUR, Currency::GBP, "2025-01-01"), None);
+    }
+
+    #[test]
+    fn test_load_rates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rates.csv");
+        std::fs::write(
+            &path,
+            "from,to,rate,effective\nEUR,USD,1.0845,2024-01-01\nUSD,JPY,150.5,2024-01-01\n",
+        )
+        .unwrap();
+
+        let table = RateTable::load(&path).unwrap();
+        assert_eq!(
+            table.rate(Currency::USD, Currency::JPY, date("2024-01-02")),
+            Some(Rate(150_500_000_000))
+        );
+
+        std::fs::write(&path, "from,to,rate,effective\nEUR,USD,-1,2024-01-01\n").unwrap();
+        assert!(RateTable::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_convert() {
+        let rates = RateTable::from_iter([
+            record(Currency::EUR, Currency::USD, "1.0845", "2024-01-01"),
+            record(Currency::USD, Currency::JPY, "150.5", "2024-01-01"),
+            record(Currency::KWD, Currency::USD, "3.25", "2024-01-01"),
+        ]);
+        let exchange = ForeignExchange::new(rates.clone(), RoundingMode::HalfEven, 0).unwrap();
+        let on = date("2024-02-01");
+
+        // €10.00 is $10.845, rounded to the even cent
+        let conversion = exchange
+            .convert(Money::new(Cents(1000), Currency::EUR), Currency::USD, on)
+            .unwrap();
+        assert_eq!(conversion.destination(), Money::usd(Cents(1084)));
+        assert_eq!(conversion.spread(), Money::usd(Cents::ZERO));
+        assert_eq!(
+            conversion.to_string(),
+            "€10.00 converted at 1.0845 to $10.84"
+        );
+
+        // Cents to yen and fils to cents
+        let conversion = exchange
+            .convert(Money::usd(Cents(1001)), Currency::JPY, on)
+            .unwrap();
+        assert_eq!(
+            conversion.destination(),
+            Money::new(Cents(1507), Currency::JPY)
+        );
+        let conversion = exchange
+            .convert(Money::new(Cents(1005), Currency::KWD), Currency::USD, on)
+            .unwrap();
+        assert_eq!(conversion.destination(), Money::usd(Cents(327)));
+
+        // A spread of 1% is taken from the converted amount
+        let exchange = ForeignExchange::new(rates, RoundingMode::Up, 100).unwrap();
+        let conversion = exchange
+            .convert(Money::new(Cents(1000), Currency::EUR), Currency::USD, on)
+            .unwrap();
+        assert_eq!(conversion.spread(), Money::usd(Cents(11)));
+        assert_eq!(conversion.destination(), Money::usd(Cents(1074)));
+        assert_eq!(
+            conversion.to_string(),
+            "€10.00 converted at 1.0845 to $10.74 after a spread of $0.11"
+        );
+
+        assert_eq!(
+            exchange.convert(Money::usd(Cents(100)), Currency::GBP, on),
+            Err(BankingSystemError::NoExchangeRate {
+                from: Currency::USD,
+                to: Currency::GBP,
+                date: on,
+            })
+        );
+        assert_eq!(
+            ForeignExchange::new(RateTable::default(), RoundingMode::HalfEven, 10_001),
+            Err(BankingSystemError::InvalidSpread(10_001))
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use serde::{Deserialize, Serialize};
+
+use crate::account::Cents;
+use crate::banking_system::BankingSystemError;
+use crate::currency::{Currency, Money};
+use crate::exchange::{Rate, RoundingMode, RATE_SCALE};
+
+/// Fee charged on an operation, made up of a flat amount and a percentage of the amount of the
+/// operation, and kept between a minimum and a maximum if they are given.
+///
+/// Amounts are written like amounts on the command line, e.g. `"1.50"`, and are in the currency
+/// of the account charged, so they are checked against its decimal places when the product is
+/// given to an account or the products are set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fee {
+    pub flat: Option<String>,
+    /// Percentage of the amount, e.g. `0.5`, rounded half-even to the minor unit.
+    pub percent: Option<Rate>,
+    pub min: Option<String>,
+    pub max: Option<String>,
+}
+
+impl Fee {
+    /// Check that the amounts are valid in a currency and that the minimum is at most the
+    /// maximum.
+    pub fn validate(&self, currency: Currency) -> Result<(), BankingSystemError> {
+        let parse = |amount: &Option<String>| {
+            amount
+                .as_deref()
+                .map(|x| currency.parse_amount(x))
+                .transpose()
+        };
+
+        parse(&self.flat)?;
+        if let (Some(min), Some(max)) = (parse(&self.min)?, parse(&self.max)?) {
+            if min > max {
+                return Err(BankingSystemError::FeeMinAboveMax {
+                    min: Money::new(min, currency),
+                    max: Money::new(max, currency),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Fee charged on an amount, in the currency of the amount.
+    pub fn charge(&self, amount: Money) -> Result<Money, BankingSystemError> {
+        let currency = amount.currency;
+        let parse = |amount: &Option<String>| {
+            amount
+                .as_deref()
+                .map(|x| currency.parse_amount(x).map(|x| x.0 as u128))
+                .transpose()
+        };
+
+        let percentage = self.percent.map_or(0, |percent| {
+            RoundingMode::HalfEven.divide(
+                amount.amount.0 as u128 * percent.0 as u128,
+                100 * RATE_SCALE,
+            )
+        });
+        let mut fee = parse(&self.flat)?.unwrap_or_default() + percentage;
+        if let Some(max) = parse(&self.max)? {
+            fee = fee.min(max);
+        }
+        if let Some(min) = parse(&self.min)? {
+            fee = fee.max(min);
+        }
+
+        let fee =
+            u64::try_from(fee).map_err(|_| BankingSystemError::AmountOverflow(fee.to_string()))?;
+        Ok(Money::new(Cents(fee), currency))
+    }
+}
+
+/// Fees charged to accounts of a product, by the operation they are charged on.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeSchedule {
+    /// Charged on withdrawals, on the amount withdrawn.
+    pub withdraw: Option<Fee>,
+    /// Charged to the account transferred from, on the amount transferred.
+    pub transfer: Option<Fee>,
+}
+
+impl FeeSchedule {
+    /// Check every fee of the schedule for accounts in a currency.
+    pub fn validate(&self, currency: Currency) -> Result<(), BankingSystemError> {
+        for fee in [&self.withdraw, &self.transfer].into_iter().flatten() {
+            fee.validate(currency)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::currency::Currency;
+
+    fn fee(flat: Option<&str>, percent: Option<&str>, min: Option<&str>, max: Option<&str>) -> Fee {
+        Fee {
+            flat: flat.map(str::to_owned),
+            percent: percent.map(|x| x.parse().unwrap()),
+            min: min.map(str::to_owned),
+            max: max.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_flat_fee() {
+        let flat = fee(Some("1.50"), None, None, None);
+
+        assert_eq!(
+            flat.charge(Money::usd(Cents(100))),
+            Ok(Money::usd(Cents(150)))
+        );
+        assert_eq!(
+            flat.charge(Money::new(Cents(100), Currency::EUR)),
+            Ok(Money::new(Cents(150), Currency::EUR))
+        );
+        // Amounts must fit the decimal places of the currency charged
+        assert_eq!(
+            flat.charge(Money::new(Cents(100), Currency::JPY)),
+            Err(BankingSystemError::InvalidAmount(String::from("1.50")))
+        );
+        assert_eq!(
+            Fee::default().charge(Money::usd(Cents(100))),
+            Ok(Money::usd(Cents(0)))
+        );
+    }
+
+    #[test]
+    fn test_percentage_fee() {
+        let percentage = fee(None, Some("0.5"), Some("0.25"), Some("5"));
+
+        // 0.5% of $100.00 is $0.50, within the caps
+        assert_eq!(
+            percentage.charge(Money::usd(Cents(10_000))),
+            Ok(Money::usd(Cents(50)))
+        );
+        // 0.5% of $10.00 is $0.05, raised to the minimum
+        assert_eq!(
+            percentage.charge(Money::usd(Cents(1000))),
+            Ok(Money::usd(Cents(25)))
+        );
+        // 0.5% of $2000.00 is $10.00, lowered to the maximum
+        assert_eq!(
+            percentage.charge(Money::usd(Cents(200_000))),
+            Ok(Money::usd(Cents(500)))
+        );
+        // 0.5% of $1.01 is 0.505 cents, rounded half-even
+        assert_eq!(
+            fee(None, Some("0.5"), None, None).charge(Money::usd(Cents(101))),
+            Ok(Money::usd(Cents(1)))
+        );
+        // A flat amount is added to the percentage
+        assert_eq!(
+            fee(Some("0.30"), Some("2.9"), None, None).charge(Money::usd(Cents(10_000))),
+            Ok(Money::usd(Cents(320)))
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(
+            fee(Some("1.50"), Some("0.5"), Some("0.25"), Some("5")).validate(Currency::USD),
+            Ok(())
+        );
+        assert_eq!(
+            fee(Some("1,00"), None, None, None).validate(Currency::USD),
+            Err(BankingSystemError::InvalidAmount(String::from("1,00")))
+        );
+        assert_eq!(
+            fee(None, None, Some("1.50"), None).validate(Currency::JPY),
+            Err(BankingSystemError::InvalidAmount(String::from("1.50")))
+        );
+        assert_eq!(
+            fee(None, Some("1"), Some("5"), Some("1")).validate(Currency::USD),
+            Err(BankingSystemError::FeeMinAboveMax {
+                min: Money::usd(Cents(500)),
+                max: Money::usd(Cents(100))
+            })
+        );
+
+        let schedule = FeeSchedule {
+            withdraw: None,
+            transfer: Some(fee(Some("1.5"), None, None, None)),
+        };
+        assert!(schedule.validate(Currency::EUR).is_ok());
+        assert!(schedule.validate(Currency::JPY).is_err());
+    }
+}
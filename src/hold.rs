@@ -0,0 +1,164 @@
+use std::fmt::Display;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::account::{AccountId, Cents};
+use crate::currency::{Currency, Money};
+
+/// State of a hold. Only open holds reserve funds.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HoldStatus {
+    #[default]
+    Open,
+    /// Captured in full or in part, with any amount not captured released.
+    Captured,
+    Released,
+    /// Released because it was not captured before it expired.
+    Expired,
+}
+
+impl Display for HoldStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            Self::Open => "open",
+            Self::Captured => "captured",
+            Self::Released => "released",
+            Self::Expired => "expired",
+        };
+        write!(f, "{status}")
+    }
+}
+
+/// Authorization that reserves funds of an account, e.g. for a card payment, until it is
+/// captured, released or expires.
+///
+/// An open hold reduces the available balance of its account but not its ledger balance, as
+/// nothing is posted until the hold is captured. Holds are kept after they are closed, so their
+/// ids are never reused.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Hold {
+    pub(crate) id: u64,
+    pub(crate) account: AccountId,
+    /// Amount authorized, in the currency of the account.
+    pub(crate) amount: Cents,
+    /// Amount posted to the account when the hold was captured.
+    pub(crate) captured: Cents,
+    pub(crate) currency: Currency,
+    pub(crate) status: HoldStatus,
+    pub(crate) placed: DateTime<Utc>,
+    pub(crate) expires: DateTime<Utc>,
+}
+
+impl Display for Hold {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "hold: {}\taccount: {}\tamount: {}\tstatus: {}\texpires: {}",
+            self.id,
+            self.account,
+            self.amount(),
+            self.status,
+            self.expires.format("%Y-%m-%d %H:%M:%S")
+        )?;
+        if self.status == HoldStatus::Captured {
+            write!(f, "\tcaptured: {}", self.captured())?;
+        }
+        Ok(())
+    }
+}
+
+impl Hold {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn account(&self) -> AccountId {
+        self.account
+    }
+
+    pub fn amount(&self) -> Money {
+        Money::new(self.amount, self.currency)
+    }
+
+    pub fn captured(&self) -> Money {
+        Money::new(self.captured, self.currency)
+    }
+
+    pub fn status(&self) -> HoldStatus {
+        self.status
+    }
+
+    pub fn placed(&self) -> DateTime<Utc> {
+        self.placed
+    }
+
+    pub fn expires(&self) -> DateTime<Utc> {
+        self.expires
+    }
+
+    /// Amount still reserved, which is the full amount while the hold is open and nothing after.
+    pub fn reserved(&self) -> Cents {
+        match self.status {
+            HoldStatus::Open => self.amount,
+            _ => Cents::ZERO,
+        }
+    }
+
+    /// Whether the hold is open but past its expiry.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.status == HoldStatus::Open && self.expires <= now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hold(status: HoldStatus) -> Hold {
+        Hold {
+            id: 1,
+            account: AccountId(1),
+            amount: Cents(2500),
+            captured: Cents::ZERO,
+            currency: Currency::USD,
+            status,
+            placed: "2024-01-01T12:00:00Z".parse().unwrap(),
+            expires: "2024-01-08T12:00:00Z".parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_reserved() {
+        assert_eq!(hold(HoldStatus::Open).reserved(), Cents(2500));
+        for status in [
+            HoldStatus::Captured,
+            HoldStatus::Released,
+            HoldStatus::Expired,
+        ] {
+            assert_eq!(hold(status).reserved(), Cents::ZERO);
+        }
+    }
+
+    #[test]
+    fn test_is_expired() {
+        let open = hold(HoldStatus::Open);
+
+        assert!(!open.is_expired("2024-01-08T11:59:59Z".parse().unwrap()));
+        assert!(open.is_expired("2024-01-08T12:00:00Z".parse().unwrap()));
+        assert!(!hold(HoldStatus::Released).is_expired("2024-02-01T00:00:00Z".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_display_hold() {
+        let mut hold = hold(HoldStatus::Captured);
+        hold.captured = Cents(2000);
+
+        assert_eq!(
+            hold.to_string(),
+            "hold: 1\taccount: 00000018\tamount: $25.00\tstatus: captured\t\
+             expires: 2024-01-08 12:00:00\tcaptured: $20.00"
+        );
+    }
+}
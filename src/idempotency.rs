@@ -0,0 +1,43 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Key a client sent with a request, e.g. a deposit retried after a timeout, recorded with the
+/// request and its outcome once the request succeeded.
+///
+/// The request is written as the operation followed by its parameters, e.g.
+/// `deposit 00000018 $10.00`, so a retry only matches if it asks for exactly the same.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct IdempotencyKey {
+    pub(crate) key: String,
+    pub(crate) request: String,
+    /// Last transaction posted by the request, if it posted any.
+    pub(crate) transaction_id: Option<u64>,
+    /// Outcome of the request as JSON, returned again when the request is retried.
+    pub(crate) response: String,
+    pub(crate) used: DateTime<Utc>,
+}
+
+impl IdempotencyKey {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn request(&self) -> &str {
+        &self.request
+    }
+
+    /// Last transaction posted by the request, if it posted any.
+    pub fn transaction_id(&self) -> Option<u64> {
+        self.transaction_id
+    }
+
+    /// Outcome of the request as JSON.
+    pub fn response(&self) -> &str {
+        &self.response
+    }
+
+    /// When the request was first applied.
+    pub fn used(&self) -> DateTime<Utc> {
+        self.used
+    }
+}
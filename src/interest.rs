@@ -0,0 +1,159 @@
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::exchange::Rate;
+use crate::fee::FeeSchedule;
+
+/// Scale of accrued interest, which is kept in billionths of the minor unit of the currency so
+/// that interest of less than a cent a day adds up.
+pub(crate) const ACCRUAL_SCALE: u128 = 1_000_000_000;
+
+/// Convention for counting the days of an interest period and the days in a year.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum DayCount {
+    /// Actual days elapsed in a year of 365 days, also in leap years
+    #[default]
+    #[serde(rename = "act/365")]
+    Act365,
+    /// Every month has 30 days in a year of 360 days, with the last day of the month counted as
+    /// the 30th
+    #[serde(rename = "30/360")]
+    Thirty360,
+}
+
+impl DayCount {
+    /// Days of the period from one date to a later one.
+    pub fn days(self, from: NaiveDate, to: NaiveDate) -> i64 {
+        match self {
+            Self::Act365 => (to - from).num_days(),
+            // Counting from a day number makes the days of consecutive periods add up, so daily
+            // accruals over a month always come to 30 days
+            Self::Thirty360 => {
+                let day_number = |date: NaiveDate| {
+                    let day = if is_posting_date(date) {
+                        30
+                    } else {
+                        date.day()
+                    };
+                    360 * date.year() as i64 + 30 * date.month0() as i64 + day as i64
+                };
+                day_number(to) - day_number(from)
+            },
+        }
+    }
+
+    pub fn days_in_year(self) -> u32 {
+        match self {
+            Self::Act365 => 365,
+            Self::Thirty360 => 360,
+        }
+    }
+}
+
+/// Account product, which sets the interest an account earns and the fees it is charged.
+/// Interest is accrued daily on the balance at the end of each day and posted to the account at
+/// the end of each month.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Product {
+    /// Annual interest rate in percent, e.g. `2.5`, none for products that earn no interest.
+    pub rate: Option<Rate>,
+    #[serde(default)]
+    pub day_count: DayCount,
+    #[serde(default)]
+    pub fees: FeeSchedule,
+}
+
+impl Product {
+    /// Interest earned by a balance over the period from one day to a later one, in billionths of
+    /// the minor unit. Only positive balances earn interest.
+    pub(crate) fn interest(&self, balance: i64, from: NaiveDate, to: NaiveDate) -> u128 {
+        let days = self.day_count.days(from, to);
+        let Some(rate) = self.rate.filter(|_| balance > 0 && days > 0) else {
+            return 0;
+        };
+        // The rate is in billionths of a percent
+        balance as u128 * rate.0 as u128 * days as u128
+            / (100 * self.day_count.days_in_year() as u128)
+    }
+}
+
+/// Whether interest accrued up to and including the date is posted, which is on the last day of
+/// every month.
+pub(crate) fn is_posting_date(date: NaiveDate) -> bool {
+    date.succ_opt().is_none_or(|x| x.month() != date.month())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_day_count() {
+        let days = |day_count: DayCount, from, to| day_count.days(date(from), date(to));
+
+        assert_eq!(days(DayCount::Act365, "2024-01-31", "2024-03-01"), 30);
+        assert_eq!(days(DayCount::Act365, "2023-01-31", "2023-03-01"), 29);
+        assert_eq!(days(DayCount::Thirty360, "2024-01-31", "2024-03-01"), 31);
+        assert_eq!(days(DayCount::Thirty360, "2024-01-01", "2024-02-01"), 30);
+        assert_eq!(days(DayCount::Thirty360, "2024-01-30", "2024-01-31"), 0);
+        assert_eq!(days(DayCount::Thirty360, "2024-01-31", "2024-02-01"), 1);
+        assert_eq!(days(DayCount::Thirty360, "2023-02-28", "2023-03-01"), 1);
+        assert_eq!(days(DayCount::Thirty360, "2024-02-28", "2024-02-29"), 2);
+        assert_eq!(days(DayCount::Thirty360, "2023-12-31", "2024-12-31"), 360);
+    }
+
+    #[test]
+    fn test_interest() {
+        let product = Product {
+            rate: Some("3.65".parse().unwrap()),
+            day_count: DayCount::Act365,
+            ..Product::default()
+        };
+        // $1000.00 at 3.65% is 10 cents a day
+        assert_eq!(
+            product.interest(100_000, date("2024-01-01"), date("2024-01-02")),
+            10 * ACCRUAL_SCALE
+        );
+        assert_eq!(
+            product.interest(100_000, date("2024-01-01"), date("2024-01-31")),
+            300 * ACCRUAL_SCALE
+        );
+        assert_eq!(
+            product.interest(-100_000, date("2024-01-01"), date("2024-01-02")),
+            0
+        );
+        assert_eq!(
+            Product::default().interest(100_000, date("2024-01-01"), date("2024-01-02")),
+            0
+        );
+
+        let product = Product {
+            rate: Some("3.6".parse().unwrap()),
+            day_count: DayCount::Thirty360,
+            ..Product::default()
+        };
+        // A month is always 30 days, so February earns as much as March
+        assert_eq!(
+            product.interest(100_000, date("2023-01-31"), date("2023-02-28")),
+            300 * ACCRUAL_SCALE
+        );
+        assert_eq!(
+            product.interest(100_000, date("2023-02-28"), date("2023-03-31")),
+            300 * ACCRUAL_SCALE
+        );
+    }
+
+    #[test]
+    fn test_posting_date() {
+        assert!(is_posting_date(date("2024-01-31")));
+        assert!(is_posting_date(date("2024-02-29")));
+        assert!(!is_posting_date(date("2024-02-28")));
+        assert!(is_posting_date(date("2023-02-28")));
+        assert!(!is_posting_date(date("2024-03-01")));
+    }
+}
@@ -0,0 +1,328 @@
+use std::fmt::Display;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::account::{Cents, SignedCents};
+use crate::currency::{Currency, Money};
+use crate::ledger::{Entry, Side};
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionKind {
+    /// Balance of an account that existed before it was tracked by the journal.
+    Opening,
+    Create,
+    Deposit,
+    Withdraw,
+    Transfer,
+    /// Transfer of the balance of an account that is closed.
+    Close,
+    /// Correction of a balance by a signed amount, posted against the external account.
+    Adjustment,
+    /// Interest accrued over a month, posted against the interest account.
+    Interest,
+    /// Payment of funds reserved by a hold, posted against the external account.
+    Capture,
+    /// Debits and credits between any number of accounts, staged with a transaction builder.
+    Settlement,
+    /// Compensating entries of an earlier transaction, with every debit and credit swapped.
+    Reversal,
+}
+
+impl Display for TransactionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            Self::Opening => "opening",
+            Self::Create => "create",
+            Self::Deposit => "deposit",
+            Self::Withdraw => "withdraw",
+            Self::Transfer => "transfer",
+            Self::Close => "close",
+            Self::Adjustment => "adjustment",
+            Self::Interest => "interest",
+            Self::Capture => "capture",
+            Self::Settlement => "settlement",
+            Self::Reversal => "reversal",
+        };
+        write!(f, "{kind}")
+    }
+}
+
+/// Append-only record of a single operation and the balanced entries it posted to the ledger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    pub(crate) id: u64,
+    pub(crate) timestamp: DateTime<Utc>,
+    pub(crate) kind: TransactionKind,
+    pub(crate) entries: Vec<Entry>,
+    /// Id of the transaction reversed by a reversal.
+    pub(crate) reverses: Option<u64>,
+    /// Id of the standing order and due date of the instance a transfer was run for.
+    pub(crate) order: Option<(u64, NaiveDate)>,
+}
+
+impl Display for Transaction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "#{}\t{}\t{} {}",
+            self.id,
+            self.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            self.kind,
+            self.amount()
+        )?;
+        if let Some(id) = self.reverses {
+            write!(f, " of #{id}")?;
+        }
+        if let Some((id, date)) = self.order {
+            write!(f, " for order {id} due {date}")?;
+        }
+        for entry in self.entries.iter() {
+            write!(f, "\t{entry}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Transaction {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    pub fn kind(&self) -> TransactionKind {
+        self.kind
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Id of the transaction reversed by a reversal.
+    pub fn reverses(&self) -> Option<u64> {
+        self.reverses
+    }
+
+    /// Id of the standing order and due date of the instance a transfer was run for.
+    pub fn order(&self) -> Option<(u64, NaiveDate)> {
+        self.order
+    }
+
+    /// Currency of the transaction, which is the currency of its first entry.
+    pub fn currency(&self) -> Currency {
+        self.entries.first().map(|x| x.currency).unwrap_or_default()
+    }
+
+    /// Total amount moved by the transaction, the sum of its debits in its currency.
+    pub fn amount(&self) -> Money {
+        let currency = self.currency();
+        Money::new(
+            Cents(
+                self.entries
+                    .iter()
+                    .filter(|x| x.side == Side::Debit && x.currency == currency)
+                    .map(|x| x.amount.0)
+                    .sum(),
+            ),
+            currency,
+        )
+    }
+
+    /// Whether the transaction posted an entry to the named account.
+    pub fn involves(&self, name: &str) -> bool {
+        self.entries.iter().any(|x| x.account == name)
+    }
+
+    /// Flatten the transaction into one record per entry for storage.
+    pub fn to_records(&self) -> impl Iterator<Item = JournalRecord> + '_ {
+        self.entries.iter().map(|entry| JournalRecord {
+            id: self.id,
+            timestamp: self.timestamp,
+            kind: self.kind,
+            account: entry.account.clone(),
+            side: entry.side,
+            amount: entry.amount,
+            balance: entry.balance,
+            currency: entry.currency,
+            reverses: self.reverses,
+            order: self.order.map(|(id, _)| id),
+            order_date: self.order.map(|(_, date)| date),
+        })
+    }
+
+    /// Group records written by `to_records` back into transactions.
+    pub fn from_records(records: impl IntoIterator<Item = JournalRecord>) -> Vec<Self> {
+        let mut transactions: Vec<Self> = Vec::new();
+
+        for record in records {
+            let entry = Entry {
+                account: record.account,
+                side: record.side,
+                amount: record.amount,
+                balance: record.balance,
+                currency: record.currency,
+            };
+            match transactions.last_mut() {
+                Some(transaction) if transaction.id == record.id => transaction.entries.push(entry),
+                _ => transactions.push(Self {
+                    id: record.id,
+                    timestamp: record.timestamp,
+                    kind: record.kind,
+                    entries: Vec::from([entry]),
+                    reverses: record.reverses,
+                    order: record.order.zip(record.order_date),
+                }),
+            }
+        }
+
+        transactions
+    }
+}
+
+/// A single journal entry as stored on disk.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct JournalRecord {
+    pub(crate) id: u64,
+    pub(crate) timestamp: DateTime<Utc>,
+    pub(crate) kind: TransactionKind,
+    pub(crate) account: String,
+    pub(crate) side: Side,
+    pub(crate) amount: Cents,
+    pub(crate) balance: Option<SignedCents>,
+    /// Journals written before amounts had a currency are in US dollars.
+    #[serde(default)]
+    pub(crate) currency: Currency,
+    #[serde(default)]
+    pub(crate) reverses: Option<u64>,
+    #[serde(default)]
+    pub(crate) order: Option<u64>,
+    #[serde(default)]
+    pub(crate) order_date: Option<NaiveDate>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer() -> Transaction {
+        Transaction {
+            id: 3,
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            kind: TransactionKind::Transfer,
+            entries: Vec::from([
+                Entry {
+                    account: String::from("user1"),
+                    side: Side::Debit,
+                    amount: Cents(1000),
+                    balance: Some(SignedCents(500)),
+                    currency: Currency::USD,
+                },
+                Entry {
+                    account: String::from("user2"),
+                    side: Side::Credit,
+                    amount: Cents(1000),
+                    balance: Some(SignedCents(1500)),
+                    currency: Currency::USD,
+                },
+            ]),
+            reverses: None,
+            order: None,
+        }
+    }
+
+    #[test]
+    fn test_involves() {
+        let transaction = transfer();
+
+        assert!(transaction.involves("user1"));
+        assert!(transaction.involves("user2"));
+        assert!(!transaction.involves("user3"));
+    }
+
+    #[test]
+    fn test_amount() {
+        assert_eq!(transfer().amount(), Money::usd(Cents(1000)));
+    }
+
+    #[test]
+    fn test_display_transaction() {
+        assert_eq!(
+            transfer().to_string(),
+            "#3\t2023-11-14 22:13:20\ttransfer $10.00\tdebit user1 $10.00 (balance $5.00)\tcredit user2 $10.00 (balance $15.00)"
+        );
+    }
+
+    #[test]
+    fn test_display_reversal() {
+        let mut reversal = transfer();
+        reversal.kind = TransactionKind::Reversal;
+        reversal.reverses = Some(2);
+
+        assert!(reversal
+            .to_string()
+            .starts_with("#3\t2023-11-14 22:13:20\treversal $10.00 of #2\tdebit user1"));
+    }
+
+    #[test]
+    fn test_display_order() {
+        let mut transfer = transfer();
+        transfer.order = Some((2, "2024-01-01".parse().unwrap()));
+
+        assert!(transfer
+            .to_string()
+            .starts_with("#3\t2023-11-14 22:13:20\ttransfer $10.00 for order 2 due 2024-01-01\t"));
+    }
+
+    #[test]
+    fn test_records_round_trip() {
+        let mut second = transfer();
+        second.id = 4;
+        second.reverses = Some(3);
+        let mut third = transfer();
+        third.id = 5;
+        third.order = Some((1, "2024-01-01".parse().unwrap()));
+        let transactions = Vec::from([transfer(), second, third]);
+
+        let records = transactions
+            .iter()
+            .flat_map(|x| x.to_records())
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 6);
+
+        assert_eq!(Transaction::from_records(records), transactions);
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let mut wtr = csv::Writer::from_writer(Vec::new());
+        for record in transfer().to_records() {
+            wtr.serialize(record).unwrap();
+        }
+        let data = wtr.into_inner().unwrap();
+
+        let mut rdr = csv::Reader::from_reader(data.as_slice());
+        let records = rdr
+            .deserialize::<JournalRecord>()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(Transaction::from_records(records), Vec::from([transfer()]));
+    }
+
+    #[test]
+    fn test_csv_currency_default() {
+        let data = "id,timestamp,kind,account,side,amount,balance\n\
+                    3,2023-11-14T22:13:20Z,transfer,user1,debit,1000,500\n";
+
+        let record = csv::Reader::from_reader(data.as_bytes())
+            .deserialize::<JournalRecord>()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.currency, Currency::USD);
+    }
+}
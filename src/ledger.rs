@@ -0,0 +1,494 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::account::{Cents, SignedCents};
+use crate::currency::{Currency, Money};
+use crate::journal::{Transaction, TransactionKind};
+
+/// Account representing money held outside the banking system. Deposits are posted against it
+/// and withdrawals back to it, so its balance is the negated sum of all customer balances. It has
+/// a balance in every currency posted to it.
+pub const EXTERNAL_ACCOUNT: &str = "@external";
+
+/// Account through which money changes currency. Foreign exchange transfers credit it in the
+/// source currency and debit it in the destination currency, so its balances are the currency
+/// position of the house.
+pub const EXCHANGE_ACCOUNT: &str = "@exchange";
+
+/// Account of the income of the house, credited with the spread of foreign exchange transfers.
+pub const HOUSE_ACCOUNT: &str = "@house";
+
+/// Account debited with the interest paid to savings accounts, so its balance is the interest
+/// expense of the house.
+pub const INTEREST_ACCOUNT: &str = "@interest";
+
+/// Account credited with the fees charged to customer accounts, so its balance is the fee income
+/// of the house.
+pub const FEE_ACCOUNT: &str = "@fees";
+
+/// Every account of the system itself.
+pub(crate) const SYSTEM_ACCOUNTS: [&str; 5] = [
+    EXTERNAL_ACCOUNT,
+    EXCHANGE_ACCOUNT,
+    HOUSE_ACCOUNT,
+    INTEREST_ACCOUNT,
+    FEE_ACCOUNT,
+];
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Debit,
+    Credit,
+}
+
+impl Display for Side {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Debit => write!(f, "debit"),
+            Self::Credit => write!(f, "credit"),
+        }
+    }
+}
+
+/// One side of a transaction posted to a single account.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub(crate) account: String,
+    pub(crate) side: Side,
+    pub(crate) amount: Cents,
+    /// Balance of a customer account after the entry was posted. The external account has none.
+    pub(crate) balance: Option<SignedCents>,
+    /// Currency of the amount and balance.
+    pub(crate) currency: Currency,
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.side,
+            self.account,
+            Money::new(self.amount, self.currency)
+        )?;
+        if let Some(balance) = self.balance {
+            write!(f, " (balance {})", Money::new(balance, self.currency))?;
+        }
+        Ok(())
+    }
+}
+
+impl Entry {
+    pub fn account(&self) -> &str {
+        &self.account
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    pub fn amount(&self) -> Cents {
+        self.amount
+    }
+
+    pub fn balance(&self) -> Option<SignedCents> {
+        self.balance
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    /// Amount of the entry in its currency.
+    pub fn money(&self) -> Money {
+        Money::new(self.amount, self.currency)
+    }
+}
+
+/// State of a ledger to return to with `Ledger::rollback`.
+#[derive(Debug, Clone)]
+pub(crate) struct LedgerSavepoint {
+    totals: BTreeMap<Currency, Totals>,
+    /// System accounts with postings and the currencies they were posted in.
+    system: Vec<(&'static str, Vec<Currency>)>,
+}
+
+/// Total debits and credits posted in a currency.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Totals {
+    pub(crate) debits: u128,
+    pub(crate) credits: u128,
+}
+
+impl Totals {
+    pub fn debits(&self) -> u128 {
+        self.debits
+    }
+
+    pub fn credits(&self) -> u128 {
+        self.credits
+    }
+}
+
+/// Account balances derived from the postings of every transaction in the journal.
+///
+/// Balances are credits minus debits, so customer accounts are positive and the books sum to zero.
+/// Amounts in different currencies are never added up, so every account has a balance per
+/// currency and the books must balance in each currency on its own.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ledger {
+    balances: BTreeMap<String, BTreeMap<Currency, i128>>,
+    totals: BTreeMap<Currency, Totals>,
+}
+
+impl Ledger {
+    pub fn from_journal(journal: &[Transaction]) -> Self {
+        let mut ledger = Self::default();
+        for transaction in journal {
+            ledger.post(transaction);
+        }
+        ledger
+    }
+
+    pub(crate) fn post(&mut self, transaction: &Transaction) {
+        for entry in transaction.entries() {
+            let balance = self
+                .balances
+                .entry(entry.account.clone())
+                .or_default()
+                .entry(entry.currency)
+                .or_default();
+            let totals = self.totals.entry(entry.currency).or_default();
+            let amount = entry.amount.0 as i128;
+            match entry.side {
+                Side::Debit => {
+                    *balance -= amount;
+                    totals.debits += amount as u128;
+                },
+                Side::Credit => {
+                    *balance += amount;
+                    totals.credits += amount as u128;
+                },
+            }
+        }
+    }
+
+    /// Record the state to return to when the transactions posted after it are undone with
+    /// `rollback`.
+    pub(crate) fn savepoint(&self) -> LedgerSavepoint {
+        LedgerSavepoint {
+            totals: self.totals.clone(),
+            system: SYSTEM_ACCOUNTS
+                .iter()
+                .filter_map(|&account| {
+                    let currencies = self.balances.get(account)?.keys().copied().collect();
+                    Some((account, currencies))
+                })
+                .collect(),
+        }
+    }
+
+    /// Undo the postings of the transactions posted since the savepoint, in the order they were
+    /// posted. Balances of the accounts they created and of system accounts in currencies posted
+    /// for the first time are removed, as if they had never been posted.
+    pub(crate) fn rollback(&mut self, savepoint: LedgerSavepoint, transactions: &[Transaction]) {
+        for transaction in transactions.iter().rev() {
+            for entry in transaction.entries() {
+                let balance = self
+                    .balances
+                    .get_mut(&entry.account)
+                    .and_then(|x| x.get_mut(&entry.currency))
+                    .expect("undone entry should have been posted");
+                let amount = entry.amount.0 as i128;
+                match entry.side {
+                    Side::Debit => *balance += amount,
+                    Side::Credit => *balance -= amount,
+                }
+            }
+            if transaction.kind == TransactionKind::Create {
+                for entry in transaction.entries().iter().filter(|x| x.balance.is_some()) {
+                    self.balances.remove(&entry.account);
+                }
+            }
+        }
+
+        for account in SYSTEM_ACCOUNTS {
+            match savepoint.system.iter().find(|(x, _)| *x == account) {
+                Some((_, currencies)) => {
+                    if let Some(balances) = self.balances.get_mut(account) {
+                        balances.retain(|currency, _| currencies.contains(currency));
+                    }
+                },
+                None => {
+                    self.balances.remove(account);
+                },
+            }
+        }
+        self.totals = savepoint.totals;
+    }
+
+    /// Whether any transaction has been posted to the account.
+    pub fn contains(&self, account: &str) -> bool {
+        self.balances.contains_key(account)
+    }
+
+    /// Balance of the account in the currency as credits minus debits.
+    pub fn balance(&self, account: &str, currency: Currency) -> i128 {
+        self.balances
+            .get(account)
+            .and_then(|x| x.get(&currency))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn trial_balance(&self) -> TrialBalance {
+        TrialBalance {
+            balances: self
+                .balances
+                .iter()
+                .flat_map(|(account, balances)| {
+                    balances
+                        .iter()
+                        .map(|(currency, balance)| (account.clone(), *currency, *balance))
+                })
+                .collect(),
+            totals: self
+                .totals
+                .iter()
+                .map(|(currency, totals)| (*currency, *totals))
+                .collect(),
+            discrepancies: Vec::new(),
+        }
+    }
+}
+
+/// Proof that the books balance: in every currency total debits equal total credits and all
+/// balances sum to zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrialBalance {
+    pub(crate) balances: Vec<(String, Currency, i128)>,
+    pub(crate) totals: Vec<(Currency, Totals)>,
+    /// Accounts whose recorded balance does not match the balance derived from the ledger.
+    pub(crate) discrepancies: Vec<String>,
+}
+
+impl Display for TrialBalance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (account, currency, balance) in self.balances.iter() {
+            write!(f, "{account}\t")?;
+            currency.fmt_amount(f, *balance)?;
+            writeln!(f)?;
+        }
+        for (currency, totals) in self.totals.iter() {
+            write!(f, "debits: ")?;
+            currency.fmt_amount(f, totals.debits as i128)?;
+            write!(f, "\tcredits: ")?;
+            currency.fmt_amount(f, totals.credits as i128)?;
+            writeln!(f)?;
+        }
+        for account in self.discrepancies.iter() {
+            writeln!(f, "account {account} does not match its ledger balance")?;
+        }
+        if self.is_balanced() {
+            write!(f, "books are balanced")
+        } else {
+            write!(f, "books are NOT balanced")
+        }
+    }
+}
+
+impl TrialBalance {
+    /// Balance of every ledger account in every currency posted to it as credits minus debits.
+    pub fn balances(&self) -> &[(String, Currency, i128)] {
+        &self.balances
+    }
+
+    /// Total debits and credits in every currency.
+    pub fn totals(&self) -> &[(Currency, Totals)] {
+        &self.totals
+    }
+
+    pub fn discrepancies(&self) -> &[String] {
+        &self.discrepancies
+    }
+
+    pub fn is_balanced(&self) -> bool {
+        self.totals.iter().all(|(currency, totals)| {
+            totals.debits == totals.credits
+                && self
+                    .balances
+                    .iter()
+                    .filter(|(_, x, _)| x == currency)
+                    .map(|(_, _, x)| x)
+                    .sum::<i128>()
+                    == 0
+        }) && self.discrepancies.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    fn entry(account: &str, side: Side, amount: u64) -> Entry {
+        Entry {
+            account: account.to_owned(),
+            side,
+            amount: Cents(amount),
+            balance: None,
+            currency: Currency::USD,
+        }
+    }
+
+    fn transaction(id: u64, entries: Vec<Entry>) -> Transaction {
+        Transaction {
+            id,
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            kind: TransactionKind::Transfer,
+            entries,
+            reverses: None,
+            order: None,
+        }
+    }
+
+    #[test]
+    fn test_balances_from_journal() {
+        let ledger = Ledger::from_journal(&[
+            transaction(
+                1,
+                Vec::from([
+                    entry(EXTERNAL_ACCOUNT, Side::Debit, 1000),
+                    entry("user1", Side::Credit, 1000),
+                ]),
+            ),
+            transaction(
+                2,
+                Vec::from([
+                    entry("user1", Side::Debit, 300),
+                    entry("user2", Side::Credit, 300),
+                ]),
+            ),
+        ]);
+
+        assert_eq!(ledger.balance("user1", Currency::USD), 700);
+        assert_eq!(ledger.balance("user2", Currency::USD), 300);
+        assert_eq!(ledger.balance(EXTERNAL_ACCOUNT, Currency::USD), -1000);
+        assert_eq!(ledger.balance("user1", Currency::EUR), 0);
+        assert_eq!(ledger.balance("user3", Currency::USD), 0);
+        assert!(ledger.contains("user2"));
+        assert!(!ledger.contains("user3"));
+    }
+
+    #[test]
+    fn test_trial_balance() {
+        let ledger = Ledger::from_journal(&[transaction(
+            1,
+            Vec::from([
+                entry(EXTERNAL_ACCOUNT, Side::Debit, 1000),
+                entry("user1", Side::Credit, 1000),
+            ]),
+        )]);
+        let trial_balance = ledger.trial_balance();
+
+        assert_eq!(
+            trial_balance.totals(),
+            &[(
+                Currency::USD,
+                Totals {
+                    debits: 1000,
+                    credits: 1000
+                }
+            )]
+        );
+        assert!(trial_balance.is_balanced());
+        assert_eq!(
+            trial_balance.to_string(),
+            "@external\t-$10.00\nuser1\t$10.00\ndebits: $10.00\tcredits: $10.00\nbooks are balanced"
+        );
+    }
+
+    #[test]
+    fn test_trial_balance_per_currency() {
+        let eur = |account, side, amount| Entry {
+            currency: Currency::EUR,
+            ..entry(account, side, amount)
+        };
+        let ledger = Ledger::from_journal(&[
+            transaction(
+                1,
+                Vec::from([
+                    entry(EXTERNAL_ACCOUNT, Side::Debit, 1000),
+                    entry("user1", Side::Credit, 1000),
+                ]),
+            ),
+            transaction(
+                2,
+                Vec::from([
+                    eur(EXTERNAL_ACCOUNT, Side::Debit, 500),
+                    eur("user2", Side::Credit, 500),
+                ]),
+            ),
+        ]);
+        let trial_balance = ledger.trial_balance();
+
+        assert_eq!(ledger.balance(EXTERNAL_ACCOUNT, Currency::EUR), -500);
+        assert!(trial_balance.is_balanced());
+        assert_eq!(
+            trial_balance.to_string(),
+            "@external\t-€5.00\n@external\t-$10.00\nuser1\t$10.00\nuser2\t€5.00\n\
+             debits: €5.00\tcredits: €5.00\ndebits: $10.00\tcredits: $10.00\nbooks are balanced"
+        );
+
+        // Amounts in different currencies do not balance each other
+        let ledger = Ledger::from_journal(&[transaction(
+            1,
+            Vec::from([
+                entry(EXTERNAL_ACCOUNT, Side::Debit, 1000),
+                eur("user1", Side::Credit, 1000),
+            ]),
+        )]);
+        assert!(!ledger.trial_balance().is_balanced());
+    }
+
+    #[test]
+    fn test_unbalanced_trial_balance() {
+        let ledger = Ledger::from_journal(&[transaction(
+            1,
+            Vec::from([
+                entry(EXTERNAL_ACCOUNT, Side::Debit, 1000),
+                entry("user1", Side::Credit, 900),
+            ]),
+        )]);
+
+        assert!(!ledger.trial_balance().is_balanced());
+    }
+
+    #[test]
+    fn test_display_entry() {
+        assert_eq!(
+            entry(EXTERNAL_ACCOUNT, Side::Debit, 1000).to_string(),
+            "debit @external $10.00"
+        );
+        assert_eq!(
+            Entry {
+                balance: Some(SignedCents(1500)),
+                ..entry("user1", Side::Credit, 1000)
+            }
+            .to_string(),
+            "credit user1 $10.00 (balance $15.00)"
+        );
+        assert_eq!(
+            Entry {
+                balance: Some(SignedCents(-1500)),
+                currency: Currency::JPY,
+                ..entry("user1", Side::Debit, 1000)
+            }
+            .to_string(),
+            "debit user1 ¥1000 (balance -¥1500)"
+        );
+    }
+}
@@ -1,2 +1,15 @@
-pub mod account;
-pub mod banking_system;
+pub mod account;
+pub mod account_store;
+pub mod banking_system;
+pub mod batch;
+pub mod config;
+pub mod currency;
+pub mod exchange;
+pub mod fee;
+pub mod hold;
+pub mod idempotency;
+pub mod interest;
+pub mod journal;
+pub mod ledger;
+pub mod standing_order;
+pub mod storage;
@@ -1,92 +1,910 @@
-use std::fs::File;
-use std::path::Path;
-
-use anyhow::Result;
-use clap::{Args, Parser, Subcommand};
-
-use banking_rs::account::Account;
-use banking_rs::banking_system::BankingSystem;
-
-#[derive(Parser)]
-#[command(version, about)]
-#[command(propagate_version = true)]
-struct Cli {
-    #[command(subcommand)]
-    command: Commands,
-}
-
-#[derive(Subcommand)]
-enum Commands {
-    /// Show all accounts
-    Show,
-    /// Create account
-    Create(SingleAccountOpArgs),
-    /// Deposit amount to account
-    Deposit(SingleAccountOpArgs),
-    /// Withdraw amount from account
-    Withdraw(SingleAccountOpArgs),
-    /// Transfer amount between acounts
-    Transfer(TransferOpArgs),
-}
-
-#[derive(Args)]
-struct SingleAccountOpArgs {
-    #[arg(short, long)]
-    name: String,
-    #[arg(short, long)]
-    amount: String,
-}
-
-#[derive(Args)]
-struct TransferOpArgs {
-    #[arg(short, long)]
-    from: String,
-    #[arg(short, long)]
-    to: String,
-    #[arg(short, long)]
-    amount: String,
-}
-
-const PATH: &str = "./banking_system.csv";
-
-fn create_system() -> Result<BankingSystem> {
-    let file = if !Path::new(PATH).exists() {
-        File::create_new(PATH)?
-    } else {
-        File::open(PATH)?
-    };
-
-    let mut rdr = csv::Reader::from_reader(file);
-
-    Ok(BankingSystem(
-        rdr.deserialize::<Account>()
-            .collect::<Result<Vec<_>, _>>()?,
-    ))
-}
-
-fn save_system(bs: BankingSystem) -> Result<()> {
-    let mut wtr = csv::Writer::from_writer(File::create(PATH)?);
-
-    for account in bs.0 {
-        wtr.serialize(account)?;
-    }
-
-    Ok(())
-}
-
-fn main() -> Result<()> {
-    let mut bs = create_system()?;
-    let cli = Cli::parse();
-
-    match &cli.command {
-        Commands::Show => bs.show(),
-        Commands::Create(SingleAccountOpArgs { name, amount }) => bs.create(name, amount)?,
-        Commands::Deposit(SingleAccountOpArgs { name, amount }) => bs.deposit(name, amount)?,
-        Commands::Withdraw(SingleAccountOpArgs { name, amount }) => bs.withdraw(name, amount)?,
-        Commands::Transfer(TransferOpArgs { from, to, amount }) => bs.transfer(from, to, amount)?,
-    }
-
-    save_system(bs)?;
-
-    Ok(())
-}
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{NaiveDate, Utc};
+use clap::{Args, Parser, Subcommand};
+
+use banking_rs::account::{AccountId, SignedCents};
+use banking_rs::banking_system::{BankingSystem, Receipt};
+use banking_rs::batch::{apply_batch, read_batch};
+use banking_rs::config::{Config, StorageBackend};
+use banking_rs::currency::Money;
+use banking_rs::exchange::{ForeignExchange, RateTable, RoundingMode};
+use banking_rs::standing_order::{RunStatus, Schedule};
+#[cfg(feature = "sqlite")]
+use banking_rs::storage::SqliteStorage;
+use banking_rs::storage::{CsvStorage, FileLock, Storage};
+
+use crate::output::{Output, OutputFormat};
+
+mod output;
+
+#[derive(Parser)]
+#[command(version, about)]
+#[command(propagate_version = true)]
+struct Cli {
+    /// Data file to use instead of a configured ledger [default: ./banking_system.csv]
+    #[arg(long, global = true, env = "BANKING_RS_DATA")]
+    data: Option<PathBuf>,
+    /// Name of a ledger defined in the config file
+    #[arg(long, global = true, env = "BANKING_RS_LEDGER")]
+    ledger: Option<String>,
+    /// Config file defining named ledgers [default: banking-rs/config.toml in the user config directory]
+    #[arg(long, global = true, env = "BANKING_RS_CONFIG")]
+    config: Option<PathBuf>,
+    /// Storage backend for accounts and the journal, inferred from the data file if not given
+    #[arg(long, global = true, value_enum)]
+    storage: Option<StorageBackend>,
+    /// Seconds to wait for another banking-rs process to release the data file [default: 10]
+    #[arg(long, global = true)]
+    lock_timeout: Option<f64>,
+    /// CSV file of exchange rates for transfers between currencies [default: from the config file]
+    #[arg(long, global = true, env = "BANKING_RS_RATES")]
+    rates: Option<PathBuf>,
+    /// Rounding of amounts converted between currencies [default: half-even]
+    #[arg(long, global = true, value_enum)]
+    rounding: Option<RoundingMode>,
+    /// Output format, amounts in JSON and CSV output are integers in the minor currency unit
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Show all accounts
+    Show,
+    /// Create account
+    Create(SingleAccountOpArgs),
+    /// Deposit amount to account
+    Deposit(SingleAccountOpArgs),
+    /// Withdraw amount from account
+    Withdraw(SingleAccountOpArgs),
+    /// Transfer amount between acounts
+    Transfer(TransferOpArgs),
+    /// Correct the balance of an account by a positive or negative amount
+    Adjust(AdjustArgs),
+    /// Change the name of an account, keeping its id
+    Rename(RenameArgs),
+    /// Block withdrawals and transfers from an account
+    Freeze(AccountArgs),
+    /// Reopen a frozen or dormant account
+    Unfreeze(AccountArgs),
+    /// Close an account with a zero balance, or sweep its balance into another account first
+    Close(CloseArgs),
+    /// Set how far the balance of an account may go below zero
+    Overdraft(OverdraftArgs),
+    /// Set the product an account earns interest and is charged fees under
+    Product(ProductArgs),
+    /// Accrue interest up to a date and post the interest of every month that ended
+    Accrue(AccrueArgs),
+    /// Reserve funds of an account until they are captured, released or the hold expires
+    Hold(HoldArgs),
+    /// Pay out the funds reserved by a hold, in full or in part
+    Capture(CaptureArgs),
+    /// Release the funds reserved by a hold
+    Release(ReleaseArgs),
+    /// Transfer an amount between accounts on a schedule
+    Order(OrderArgs),
+    /// Show standing orders
+    Orders,
+    /// Run every instance of standing orders due up to a date that has not run yet
+    RunDue(RunDueArgs),
+    /// Apply a file of operations, committing either all of them or none if any fails
+    Batch(BatchArgs),
+    /// Undo a transaction by posting a reversal linked to it
+    Reverse(ReverseArgs),
+    /// Show transaction history, optionally for a single account
+    History(HistoryArgs),
+    /// Check that the ledger balances
+    TrialBalance,
+}
+
+impl Commands {
+    /// Whether the command may transfer between currencies, and so needs the exchange rates.
+    fn converts(&self) -> bool {
+        matches!(self, Self::Transfer(_) | Self::RunDue(_) | Self::Batch(_))
+    }
+}
+
+#[derive(Args)]
+struct IdempotencyArgs {
+    /// Key of the request, so that retrying it with the same key applies it only once
+    #[arg(long)]
+    idempotency_key: Option<String>,
+}
+
+impl IdempotencyArgs {
+    fn key(&self) -> Option<&str> {
+        self.idempotency_key.as_deref()
+    }
+}
+
+#[derive(Args)]
+struct SingleAccountOpArgs {
+    /// Name of the new account, or id or name of an existing account
+    #[arg(short, long)]
+    name: String,
+    /// Amount in the currency of the account, or with a currency code to create an account in
+    /// that currency, e.g. "10.50 EUR"
+    #[arg(short, long)]
+    amount: String,
+    #[command(flatten)]
+    idempotency: IdempotencyArgs,
+}
+
+#[derive(Args)]
+struct TransferOpArgs {
+    /// Id or name of the account to transfer from
+    #[arg(short, long)]
+    from: String,
+    /// Id or name of the account to transfer to
+    #[arg(short, long)]
+    to: String,
+    /// Amount in the currency of the account to transfer from, converted if the other account
+    /// is in another currency
+    #[arg(short, long)]
+    amount: String,
+    #[command(flatten)]
+    idempotency: IdempotencyArgs,
+}
+
+#[derive(Args)]
+struct AdjustArgs {
+    /// Id or name of the account
+    #[arg(short, long)]
+    name: String,
+    /// Amount to add to the balance, negative to subtract from it
+    #[arg(short, long, allow_hyphen_values = true)]
+    amount: String,
+    #[command(flatten)]
+    idempotency: IdempotencyArgs,
+}
+
+#[derive(Args)]
+struct RenameArgs {
+    /// Id or current name of the account
+    #[arg(short, long)]
+    name: String,
+    /// New name of the account
+    #[arg(short, long)]
+    to: String,
+    #[command(flatten)]
+    idempotency: IdempotencyArgs,
+}
+
+#[derive(Args)]
+struct AccountArgs {
+    /// Id or name of the account
+    #[arg(short, long)]
+    name: String,
+    #[command(flatten)]
+    idempotency: IdempotencyArgs,
+}
+
+#[derive(Args)]
+struct CloseArgs {
+    /// Id or name of the account
+    #[arg(short, long)]
+    name: String,
+    /// Id or name of the account to transfer the remaining balance to
+    #[arg(short, long)]
+    sweep_to: Option<String>,
+    #[command(flatten)]
+    idempotency: IdempotencyArgs,
+}
+
+#[derive(Args)]
+struct OverdraftArgs {
+    /// Id or name of the account
+    #[arg(short, long)]
+    name: String,
+    /// Overdraft limit, 0 to allow no overdraft
+    #[arg(short, long)]
+    limit: String,
+    #[command(flatten)]
+    idempotency: IdempotencyArgs,
+}
+
+#[derive(Args)]
+struct ProductArgs {
+    /// Id or name of the account
+    #[arg(short, long)]
+    name: String,
+    /// Product defined in the config file, none to stop earning interest and being charged fees
+    #[arg(short, long)]
+    product: Option<String>,
+    /// First day to earn interest under the product [default: today]
+    #[arg(long)]
+    from: Option<NaiveDate>,
+    #[command(flatten)]
+    idempotency: IdempotencyArgs,
+}
+
+#[derive(Args)]
+struct AccrueArgs {
+    /// Last day to accrue interest for [default: today]
+    #[arg(long)]
+    as_of: Option<NaiveDate>,
+    #[command(flatten)]
+    idempotency: IdempotencyArgs,
+}
+
+#[derive(Args)]
+struct HoldArgs {
+    /// Id or name of the account
+    #[arg(short, long)]
+    name: String,
+    /// Amount to reserve in the currency of the account
+    #[arg(short, long)]
+    amount: String,
+    /// Days until the hold expires and its funds are released
+    #[arg(long, default_value_t = DEFAULT_HOLD_DAYS)]
+    days: u32,
+    #[command(flatten)]
+    idempotency: IdempotencyArgs,
+}
+
+#[derive(Args)]
+struct CaptureArgs {
+    /// Id of the hold
+    #[arg(short, long)]
+    id: u64,
+    /// Amount to capture, releasing the rest [default: the full amount held]
+    #[arg(short, long)]
+    amount: Option<String>,
+    #[command(flatten)]
+    idempotency: IdempotencyArgs,
+}
+
+#[derive(Args)]
+struct ReleaseArgs {
+    /// Id of the hold
+    #[arg(short, long)]
+    id: u64,
+    #[command(flatten)]
+    idempotency: IdempotencyArgs,
+}
+
+#[derive(Args)]
+struct OrderArgs {
+    /// Id or name of the account to transfer from
+    #[arg(short, long)]
+    from: String,
+    /// Id or name of the account to transfer to
+    #[arg(short, long)]
+    to: String,
+    /// Amount in the currency of the account transferred from
+    #[arg(short, long)]
+    amount: String,
+    /// When the order is due: daily, weekly:<weekday> or monthly:<day of month>
+    #[arg(short, long)]
+    schedule: Schedule,
+    /// First day the order can be due on [default: today]
+    #[arg(long)]
+    start: Option<NaiveDate>,
+    /// Last day the order can be due on [default: no end]
+    #[arg(long)]
+    end: Option<NaiveDate>,
+    #[command(flatten)]
+    idempotency: IdempotencyArgs,
+}
+
+#[derive(Args)]
+struct RunDueArgs {
+    /// Last day to run instances due on [default: today]
+    #[arg(long)]
+    as_of: Option<NaiveDate>,
+    #[command(flatten)]
+    idempotency: IdempotencyArgs,
+}
+
+#[derive(Args)]
+struct BatchArgs {
+    /// CSV file with the columns op, account, to and amount, or a JSON array of such objects if
+    /// its extension is json
+    file: PathBuf,
+    #[command(flatten)]
+    idempotency: IdempotencyArgs,
+}
+
+#[derive(Args)]
+struct ReverseArgs {
+    /// Id of the transaction to reverse
+    id: u64,
+    #[command(flatten)]
+    idempotency: IdempotencyArgs,
+}
+
+#[derive(Args)]
+struct HistoryArgs {
+    /// Id or name of the account
+    #[arg(short, long)]
+    name: Option<String>,
+}
+
+const DEFAULT_LOCK_TIMEOUT: f64 = 10.0;
+const DEFAULT_HOLD_DAYS: u32 = 7;
+
+/// Balance of an account involved in the operation of the receipt, in the currency of the
+/// account.
+fn balance(bs: &BankingSystem, receipt: &Receipt, id: AccountId) -> Money<SignedCents> {
+    let balance = receipt
+        .balance(id)
+        .expect("receipt should include the balance of the account");
+    bs.get_account(id)
+        .expect("account of the receipt should exist")
+        .money(balance)
+}
+
+/// Fee charged for the operation of the receipt, to follow the balance of the account charged.
+fn fee(receipt: &Receipt) -> String {
+    receipt
+        .fee()
+        .map(|fee| format!(" after a fee of {fee}"))
+        .unwrap_or_default()
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let output = Output::new(cli.output);
+
+    match run(&cli, &output) {
+        Ok(code) => code,
+        Err(error) => {
+            output.error(&error);
+            ExitCode::FAILURE
+        },
+    }
+}
+
+/// Run the command, with a failure exit code for commands that report their own failure.
+fn run(cli: &Cli, output: &Output) -> Result<ExitCode> {
+    let config = match &cli.config {
+        Some(path) => Config::load(path)?,
+        None => Config::load_default()?,
+    };
+    let ledger = config.resolve(cli.data.as_deref(), cli.ledger.as_deref(), cli.storage)?;
+
+    let lock_timeout = cli
+        .lock_timeout
+        .or(config.lock_timeout)
+        .unwrap_or(DEFAULT_LOCK_TIMEOUT);
+    let timeout = Duration::try_from_secs_f64(lock_timeout)
+        .map_err(|_| anyhow!("invalid lock timeout {lock_timeout}"))?;
+    // Held until the changes are saved
+    let _lock = FileLock::acquire(&ledger.path, timeout)?;
+
+    let (mut storage, mut bs): (Box<dyn Storage>, _) = match ledger.storage() {
+        StorageBackend::Csv => {
+            let mut storage = CsvStorage::new(&ledger.path);
+            let bs = storage.load()?;
+            if storage.recovered() {
+                eprintln!(
+                    "Warning: {} was corrupt, accounts were recovered from {} and {}",
+                    storage.path().display(),
+                    storage.backup_path().display(),
+                    storage.journal_path().display()
+                );
+            }
+            (Box::new(storage), bs)
+        },
+        #[cfg(feature = "sqlite")]
+        StorageBackend::Sqlite => {
+            let mut storage = SqliteStorage::open(&ledger.path)?;
+            let bs = storage.load()?;
+            (Box::new(storage), bs)
+        },
+    };
+
+    // Other commands do not fail over a missing or malformed rates file
+    let rates = cli
+        .rates
+        .as_ref()
+        .or(config.exchange.rates.as_ref())
+        .filter(|_| cli.command.converts());
+    if let Some(rates) = rates {
+        let table = RateTable::load(rates)
+            .with_context(|| format!("could not read exchange rates {}", rates.display()))?;
+        let rounding = cli
+            .rounding
+            .or(config.exchange.rounding)
+            .unwrap_or_default();
+        bs.set_exchange(ForeignExchange::new(
+            table,
+            rounding,
+            config.exchange.spread_bps,
+        )?);
+    }
+    bs.set_products(config.products.clone())?;
+    let now = Utc::now();
+    let today = now.date_naive();
+    bs.expire_holds(now);
+
+    let mut code = ExitCode::SUCCESS;
+    let rendered = match &cli.command {
+        Commands::Show => output.accounts(bs.accounts())?,
+        Commands::Create(SingleAccountOpArgs {
+            name,
+            amount,
+            idempotency,
+        }) => {
+            let request = format!("create {name:?} {amount:?}");
+            let receipt = bs.idempotent(idempotency.key(), &request, |bs| {
+                bs.create_str(name, amount)
+            })?;
+            let id = receipt
+                .account_id()
+                .expect("receipt should include the created account");
+            output.receipt(
+                &receipt,
+                format_args!(
+                    "Account created with id {id}, name {} and balance {}",
+                    bs.get_account(id)?.name(),
+                    balance(&bs, &receipt, id)
+                ),
+            )?
+        },
+        Commands::Deposit(SingleAccountOpArgs {
+            name,
+            amount,
+            idempotency,
+        }) => {
+            let request = format!("deposit {name:?} {amount:?}");
+            let receipt = bs.idempotent(idempotency.key(), &request, |bs| {
+                bs.deposit_str(name, amount)
+            })?;
+            let id = receipt
+                .account_id()
+                .expect("receipt should include the account deposited to");
+            output.receipt(
+                &receipt,
+                format_args!("Account balance is now {}", balance(&bs, &receipt, id)),
+            )?
+        },
+        Commands::Withdraw(SingleAccountOpArgs {
+            name,
+            amount,
+            idempotency,
+        }) => {
+            let request = format!("withdraw {name:?} {amount:?}");
+            let receipt = bs.idempotent(idempotency.key(), &request, |bs| {
+                bs.withdraw_str(name, amount)
+            })?;
+            let id = receipt
+                .account_id()
+                .expect("receipt should include the account withdrawn from");
+            output.receipt(
+                &receipt,
+                format_args!(
+                    "Account balance is now {}{}",
+                    balance(&bs, &receipt, id),
+                    fee(&receipt)
+                ),
+            )?
+        },
+        Commands::Transfer(TransferOpArgs {
+            from,
+            to,
+            amount,
+            idempotency,
+        }) => {
+            let request = format!("transfer {from:?} {to:?} {amount:?}");
+            let receipt = bs.idempotent(idempotency.key(), &request, |bs| {
+                bs.transfer_str(from, to, amount)
+            })?;
+            // Balances are in the order the accounts were posted to, the source first
+            let [(from_id, _), (to_id, _)] = receipt.balances() else {
+                unreachable!("receipt of a transfer should include both accounts");
+            };
+            let (from_id, to_id) = (*from_id, *to_id);
+            let balances = format!(
+                "{from} balance is now {}{}, {to} balance is now {}",
+                balance(&bs, &receipt, from_id),
+                fee(&receipt),
+                balance(&bs, &receipt, to_id)
+            );
+            match receipt.conversion() {
+                Some(conversion) => {
+                    output.receipt(&receipt, format_args!("{conversion}, {balances}"))?
+                },
+                None => output.receipt(&receipt, balances)?,
+            }
+        },
+        Commands::Adjust(AdjustArgs {
+            name,
+            amount,
+            idempotency,
+        }) => {
+            let request = format!("adjust {name:?} {amount:?}");
+            let receipt = bs.idempotent(idempotency.key(), &request, |bs| {
+                bs.adjust_str(name, amount)
+            })?;
+            let id = receipt
+                .account_id()
+                .expect("receipt should include the account adjusted");
+            output.receipt(
+                &receipt,
+                format_args!("Account balance is now {}", balance(&bs, &receipt, id)),
+            )?
+        },
+        Commands::Rename(RenameArgs {
+            name,
+            to,
+            idempotency,
+        }) => {
+            let request = format!("rename {name:?} {to:?}");
+            let account = bs.idempotent(idempotency.key(), &request, |bs| {
+                let id = bs.resolve(name)?;
+                Ok(bs.rename(id, to)?.clone())
+            })?;
+            output.account(
+                &account,
+                format_args!("Account {} renamed to {}", account.id(), account.name()),
+            )?
+        },
+        Commands::Freeze(AccountArgs { name, idempotency }) => {
+            let account = bs.idempotent(idempotency.key(), &format!("freeze {name:?}"), |bs| {
+                let id = bs.resolve(name)?;
+                Ok(bs.freeze(id)?.clone())
+            })?;
+            let id = account.id();
+            output.account(&account, format_args!("Account {id} is now frozen"))?
+        },
+        Commands::Unfreeze(AccountArgs { name, idempotency }) => {
+            let account =
+                bs.idempotent(idempotency.key(), &format!("unfreeze {name:?}"), |bs| {
+                    let id = bs.resolve(name)?;
+                    Ok(bs.unfreeze(id)?.clone())
+                })?;
+            let id = account.id();
+            output.account(&account, format_args!("Account {id} is now open"))?
+        },
+        Commands::Close(CloseArgs {
+            name,
+            sweep_to,
+            idempotency,
+        }) => {
+            let request = match sweep_to {
+                Some(to) => format!("close {name:?} {to:?}"),
+                None => format!("close {name:?}"),
+            };
+            let (id, sweep_to, overdrawn, receipt) =
+                bs.idempotent(idempotency.key(), &request, |bs| {
+                    let id = bs.resolve(name)?;
+                    let sweep_to = sweep_to.as_deref().map(|x| bs.resolve(x)).transpose()?;
+                    let overdrawn = bs.get_account(id)?.balance().is_negative();
+                    Ok((id, sweep_to, overdrawn, bs.close(id, sweep_to)?))
+                })?;
+            match receipt {
+                Some(receipt) => {
+                    let to = sweep_to.expect("only swept balances have a receipt");
+                    let amount = receipt.amount();
+                    let swept = if overdrawn {
+                        format!("an overdraft of {amount} was settled from")
+                    } else {
+                        format!("{amount} was swept into")
+                    };
+                    output.receipt(
+                        &receipt,
+                        format_args!(
+                            "Account {id} closed, {swept} {to} with balance now {}",
+                            balance(&bs, &receipt, to)
+                        ),
+                    )?
+                },
+                None => output.account(bs.get_account(id)?, format_args!("Account {id} closed"))?,
+            }
+        },
+        Commands::Overdraft(OverdraftArgs {
+            name,
+            limit,
+            idempotency,
+        }) => {
+            let request = format!("overdraft {name:?} {limit:?}");
+            let account = bs.idempotent(idempotency.key(), &request, |bs| {
+                let id = bs.resolve(name)?;
+                let limit = bs.get_account(id)?.currency().parse_amount(limit)?;
+                Ok(bs.set_overdraft_limit(id, limit)?.clone())
+            })?;
+            output.account(
+                &account,
+                format_args!(
+                    "Account {} overdraft limit is now {}, available {}",
+                    account.id(),
+                    account.money(account.overdraft_limit()),
+                    account.money(account.available())
+                ),
+            )?
+        },
+        Commands::Product(ProductArgs {
+            name,
+            product,
+            from,
+            idempotency,
+        }) => {
+            let from = from.unwrap_or(today);
+            let request = match product {
+                Some(product) => format!("product {name:?} {from} {product:?}"),
+                None => format!("product {name:?} {from}"),
+            };
+            let account = bs.idempotent(idempotency.key(), &request, |bs| {
+                let id = bs.resolve(name)?;
+                Ok(bs.set_product(id, product.as_deref(), from)?.clone())
+            })?;
+            let id = account.id();
+            let message = match account.product() {
+                Some(product) => format!("Account {id} is now on product {product}"),
+                None => format!("Account {id} no longer has a product"),
+            };
+            output.account(&account, message)?
+        },
+        Commands::Accrue(AccrueArgs { as_of, idempotency }) => {
+            let as_of = as_of.unwrap_or(today);
+            let receipts = bs.idempotent(idempotency.key(), &format!("accrue {as_of}"), |bs| {
+                bs.accrue(as_of)
+            })?;
+            let mut message = format!(
+                "Interest accrued to {as_of}, {} posting(s) made",
+                receipts.len()
+            );
+            for receipt in &receipts {
+                let id = receipt
+                    .account_id()
+                    .expect("interest should be posted to an account");
+                message += &format!(
+                    "\nInterest of {} posted to {id}, balance is now {}",
+                    receipt.amount(),
+                    balance(&bs, receipt, id)
+                );
+            }
+            output.receipts(&receipts, message)?
+        },
+        Commands::Hold(HoldArgs {
+            name,
+            amount,
+            days,
+            idempotency,
+        }) => {
+            let expires = now
+                .checked_add_signed(chrono::Duration::days(i64::from(*days)))
+                .ok_or_else(|| anyhow!("invalid hold duration of {days} days, it would expire too far in the future"))?;
+            let request = format!("hold {name:?} {amount:?} {days}");
+            let hold = bs.idempotent(idempotency.key(), &request, |bs| {
+                let id = bs.resolve(name)?;
+                let amount = Money::parse(amount, bs.get_account(id)?.currency())?;
+                Ok(bs.hold(id, amount, expires)?.clone())
+            })?;
+            let account = bs.get_account(hold.account())?;
+            output.hold(
+                &hold,
+                format_args!(
+                    "Hold {} placed on {} for {} until {}, available is now {}",
+                    hold.id(),
+                    hold.account(),
+                    hold.amount(),
+                    hold.expires().format("%Y-%m-%d %H:%M:%S"),
+                    account.money(account.available())
+                ),
+            )?
+        },
+        Commands::Capture(CaptureArgs {
+            id,
+            amount,
+            idempotency,
+        }) => {
+            let hold = bs.get_hold(*id)?.clone();
+            let amount = amount
+                .as_deref()
+                .map(|x| Money::parse(x, hold.amount().currency()))
+                .transpose()?;
+            let request = match amount {
+                Some(amount) => format!("capture {id} {amount}"),
+                None => format!("capture {id}"),
+            };
+            let receipt =
+                bs.idempotent(idempotency.key(), &request, |bs| bs.capture(*id, amount))?;
+            output.receipt(
+                &receipt,
+                format_args!(
+                    "Hold {id} captured {}, account balance is now {}",
+                    receipt.amount(),
+                    balance(&bs, &receipt, hold.account())
+                ),
+            )?
+        },
+        Commands::Release(ReleaseArgs { id, idempotency }) => {
+            let hold = bs.idempotent(idempotency.key(), &format!("release {id}"), |bs| {
+                Ok(bs.release(*id)?.clone())
+            })?;
+            output.hold(
+                &hold,
+                format_args!("Hold {id} released, {} is available again", hold.amount()),
+            )?
+        },
+        Commands::Order(OrderArgs {
+            from,
+            to,
+            amount,
+            schedule,
+            start,
+            end,
+            idempotency,
+        }) => {
+            let start = start.unwrap_or(today);
+            let mut request = format!("order {from:?} {to:?} {amount:?} {schedule} {start}");
+            if let Some(end) = end {
+                request += &format!(" {end}");
+            }
+            let order = bs.idempotent(idempotency.key(), &request, |bs| {
+                let from = bs.resolve(from)?;
+                let to = bs.resolve(to)?;
+                let amount = Money::parse(amount, bs.get_account(from)?.currency())?;
+                Ok(bs
+                    .create_order(from, to, amount, *schedule, start, *end)?
+                    .clone())
+            })?;
+            let mut message = format!(
+                "Standing order {} transfers {} from {} to {} on schedule {schedule}",
+                order.id(),
+                order.amount(),
+                order.from(),
+                order.to()
+            );
+            if order.is_finished() {
+                message += ", but is never due before its end date";
+            } else {
+                message += &format!(", next on {}", order.next());
+            }
+            output.order(&order, message)?
+        },
+        Commands::Orders => output.orders(&bs.orders().collect::<Vec<_>>())?,
+        Commands::RunDue(RunDueArgs { as_of, idempotency }) => {
+            let as_of = as_of.unwrap_or(today);
+            let runs = bs.idempotent(idempotency.key(), &format!("run-due {as_of}"), |bs| {
+                bs.run_due(as_of)
+            })?;
+            let executed = runs
+                .iter()
+                .filter(|x| x.status() == RunStatus::Executed)
+                .count();
+            let mut message = format!(
+                "Standing orders run to {as_of}, {executed} of {} instance(s) executed",
+                runs.len()
+            );
+            for run in &runs {
+                message += &format!("\n{run}");
+            }
+            output.runs(&runs, message)?
+        },
+        Commands::Batch(BatchArgs { file, idempotency }) => {
+            let lines = read_batch(file)
+                .with_context(|| format!("could not read batch file {}", file.display()))?;
+            // A batch that was rolled back is a failure, so its key is not recorded
+            let request = format!("batch {}", serde_json::to_string(&lines)?);
+            let mut rolled_back = None;
+            let committed = bs.idempotent(idempotency.key(), &request, |bs| {
+                match apply_batch(bs, &lines) {
+                    (reports, true) => Ok(reports),
+                    (reports, false) => {
+                        rolled_back = Some(reports);
+                        Err(anyhow!("batch rolled back"))
+                    },
+                }
+            });
+            let (reports, committed) = match rolled_back {
+                Some(reports) => (reports, false),
+                None => (committed?, true),
+            };
+            let mut message = if committed {
+                format!("Batch of {} operation(s) committed", reports.len())
+            } else {
+                format!("Batch of {} operation(s) rolled back", reports.len())
+            };
+            for report in &reports {
+                message += &format!("\n{report}");
+            }
+            if !committed {
+                code = ExitCode::FAILURE;
+            }
+            output.batch(&reports, committed, message)?
+        },
+        Commands::Reverse(ReverseArgs { id, idempotency }) => {
+            let receipt = bs.idempotent(idempotency.key(), &format!("reverse {id}"), |bs| {
+                bs.reverse(*id)
+            })?;
+            let mut message = format!(
+                "Transaction {id} reversed by transaction {}",
+                receipt.transaction_id()
+            );
+            for (account, _) in receipt.balances() {
+                message += &format!(
+                    ", {account} balance is now {}",
+                    balance(&bs, &receipt, *account)
+                );
+            }
+            output.receipt(&receipt, message)?
+        },
+        Commands::History(HistoryArgs { name }) => {
+            let id = name.as_deref().map(|x| bs.resolve(x)).transpose()?;
+            output.history(&bs.history(id)?, |x| bs.reversed_by(x))?
+        },
+        Commands::TrialBalance => output.trial_balance(&bs.trial_balance())?,
+    };
+
+    // Written only once the changes are saved, so nothing is reported that could still be lost
+    storage.commit(&mut bs)?;
+    print!("{rendered}");
+
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn run_command(dir: &Path, args: &[&str]) -> Result<ExitCode> {
+        let config = dir.join("config.toml");
+        std::fs::write(&config, "").unwrap();
+        let data = dir.join("banking_system.csv");
+        let cli = Cli::try_parse_from(
+            [
+                "banking-rs",
+                "--config",
+                config.to_str().unwrap(),
+                "--data",
+                data.to_str().unwrap(),
+            ]
+            .iter()
+            .chain(args),
+        )
+        .unwrap();
+        run(&cli, &Output::new(OutputFormat::Json))
+    }
+
+    #[test]
+    fn test_retry_rename() {
+        let dir = tempfile::tempdir().unwrap();
+        run_command(dir.path(), &["create", "-n", "alice", "-a", "10"]).unwrap();
+
+        // The retry finds the stored outcome before looking up the old name
+        let rename = [
+            "rename",
+            "-n",
+            "alice",
+            "-t",
+            "bob",
+            "--idempotency-key",
+            "k1",
+        ];
+        run_command(dir.path(), &rename).unwrap();
+        run_command(dir.path(), &rename).unwrap();
+
+        let bs = CsvStorage::new(dir.path().join("banking_system.csv"))
+            .load()
+            .unwrap();
+        assert_eq!(bs.resolve("bob").unwrap(), "00000018".parse().unwrap());
+        assert!(bs.resolve("alice").is_err());
+        assert_eq!(bs.idempotency_keys().count(), 1);
+
+        let error = run_command(
+            dir.path(),
+            &[
+                "rename",
+                "-n",
+                "bob",
+                "-t",
+                "carol",
+                "--idempotency-key",
+                "k1",
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(output::error_code(&error), "idempotency_key_reused");
+    }
+}
@@ -0,0 +1,697 @@
+use std::fmt::Display;
+use std::io;
+
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+
+use banking_rs::account::{Account, AccountId, AccountStatus, Cents, SignedCents};
+use banking_rs::banking_system::{self, Receipt};
+use banking_rs::batch::{BatchOp, LineReport, LineStatus};
+use banking_rs::config::ConfigError;
+use banking_rs::currency::Currency;
+use banking_rs::exchange::{Conversion, Rate};
+use banking_rs::hold::{Hold, HoldStatus};
+use banking_rs::journal::{Transaction, TransactionKind};
+use banking_rs::ledger::{Entry, TrialBalance};
+use banking_rs::standing_order::{OrderRun, RunStatus, Schedule, StandingOrder};
+use banking_rs::storage::LockError;
+
+/// Format of everything written to stdout. Amounts in JSON and CSV output are integers in the
+/// minor unit of their currency, e.g. cents.
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable text
+    #[default]
+    Text,
+    /// A single JSON value per command
+    Json,
+    /// CSV with a header row
+    Csv,
+}
+
+#[derive(Serialize)]
+struct AccountRow<'a> {
+    id: AccountId,
+    name: &'a str,
+    balance: SignedCents,
+    available: Cents,
+    held: Cents,
+    overdraft_limit: Cents,
+    status: AccountStatus,
+    currency: Currency,
+    product: Option<&'a str>,
+}
+
+impl<'a> From<&'a Account> for AccountRow<'a> {
+    fn from(account: &'a Account) -> Self {
+        Self {
+            id: account.id(),
+            name: account.name(),
+            balance: account.balance(),
+            available: account.available(),
+            held: account.held(),
+            overdraft_limit: account.overdraft_limit(),
+            status: account.status(),
+            currency: account.currency(),
+            product: account.product(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct HoldRow {
+    id: u64,
+    account: AccountId,
+    amount: Cents,
+    captured: Cents,
+    currency: Currency,
+    status: HoldStatus,
+    placed: DateTime<Utc>,
+    expires: DateTime<Utc>,
+}
+
+impl From<&Hold> for HoldRow {
+    fn from(hold: &Hold) -> Self {
+        Self {
+            id: hold.id(),
+            account: hold.account(),
+            amount: hold.amount().amount(),
+            captured: hold.captured().amount(),
+            currency: hold.amount().currency(),
+            status: hold.status(),
+            placed: hold.placed(),
+            expires: hold.expires(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct OrderRow {
+    id: u64,
+    from: AccountId,
+    to: AccountId,
+    amount: Cents,
+    currency: Currency,
+    schedule: Schedule,
+    next: Option<NaiveDate>,
+    end: Option<NaiveDate>,
+}
+
+impl From<&StandingOrder> for OrderRow {
+    fn from(order: &StandingOrder) -> Self {
+        Self {
+            id: order.id(),
+            from: order.from(),
+            to: order.to(),
+            amount: order.amount().amount(),
+            currency: order.amount().currency(),
+            schedule: order.schedule(),
+            next: Some(order.next()).filter(|_| !order.is_finished()),
+            end: order.end(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RunRow<'a> {
+    order: u64,
+    date: NaiveDate,
+    status: RunStatus,
+    transaction_id: Option<u64>,
+    reason: Option<&'a str>,
+    message: Option<&'a str>,
+}
+
+impl<'a> From<&'a OrderRun> for RunRow<'a> {
+    fn from(run: &'a OrderRun) -> Self {
+        Self {
+            order: run.order(),
+            date: run.date(),
+            status: run.status(),
+            transaction_id: run.transaction_id(),
+            reason: run.reason(),
+            message: run.message(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct LineRow<'a> {
+    line: u64,
+    op: BatchOp,
+    status: LineStatus,
+    transaction_id: Option<u64>,
+    reason: Option<&'a str>,
+    message: Option<&'a str>,
+}
+
+impl<'a> From<&'a LineReport> for LineRow<'a> {
+    fn from(report: &'a LineReport) -> Self {
+        Self {
+            line: report.line,
+            op: report.op,
+            status: report.status,
+            transaction_id: report.transaction_id,
+            reason: report.reason.as_deref(),
+            message: report.message.as_deref(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BatchJson<'a> {
+    committed: bool,
+    lines: Vec<LineRow<'a>>,
+}
+
+#[derive(Serialize)]
+struct ReceiptJson {
+    transaction_id: u64,
+    kind: TransactionKind,
+    amount: Cents,
+    currency: Currency,
+    balances: Vec<BalanceJson>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conversion: Option<ConversionJson>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fee: Option<Cents>,
+}
+
+impl From<&Receipt> for ReceiptJson {
+    fn from(receipt: &Receipt) -> Self {
+        Self {
+            transaction_id: receipt.transaction_id(),
+            kind: receipt.kind(),
+            amount: receipt.amount().amount(),
+            currency: receipt.amount().currency(),
+            balances: receipt
+                .balances()
+                .iter()
+                .map(|(account, balance)| BalanceJson {
+                    account: *account,
+                    balance: *balance,
+                })
+                .collect(),
+            conversion: receipt.conversion().map(ConversionJson::from),
+            fee: receipt.fee().map(|x| x.amount()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ConversionJson {
+    source_amount: Cents,
+    source_currency: Currency,
+    rate: Rate,
+    destination_amount: Cents,
+    destination_currency: Currency,
+    spread: Cents,
+}
+
+impl From<&Conversion> for ConversionJson {
+    fn from(conversion: &Conversion) -> Self {
+        Self {
+            source_amount: conversion.source().amount(),
+            source_currency: conversion.source().currency(),
+            rate: conversion.rate(),
+            destination_amount: conversion.destination().amount(),
+            destination_currency: conversion.destination().currency(),
+            spread: conversion.spread().amount(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BalanceJson {
+    account: AccountId,
+    balance: SignedCents,
+}
+
+#[derive(Serialize)]
+struct ReceiptRow {
+    transaction_id: u64,
+    kind: TransactionKind,
+    amount: Cents,
+    currency: Currency,
+    account: AccountId,
+    balance: SignedCents,
+    /// Conversion of a transfer between currencies, empty for other receipts.
+    rate: Option<Rate>,
+    destination_amount: Option<Cents>,
+    destination_currency: Option<Currency>,
+    spread: Option<Cents>,
+    /// Fee charged to the account debited, empty if none was charged.
+    fee: Option<Cents>,
+}
+
+impl ReceiptRow {
+    /// One row for every account of the receipt.
+    fn from_receipt(receipt: &Receipt) -> impl Iterator<Item = Self> + '_ {
+        receipt
+            .balances()
+            .iter()
+            .map(|(account, balance)| ReceiptRow {
+                transaction_id: receipt.transaction_id(),
+                kind: receipt.kind(),
+                amount: receipt.amount().amount(),
+                currency: receipt.amount().currency(),
+                account: *account,
+                balance: *balance,
+                rate: receipt.conversion().map(|x| x.rate()),
+                destination_amount: receipt.conversion().map(|x| x.destination().amount()),
+                destination_currency: receipt.conversion().map(|x| x.destination().currency()),
+                spread: receipt.conversion().map(|x| x.spread().amount()),
+                fee: receipt.fee().map(|x| x.amount()),
+            })
+    }
+}
+
+#[derive(Serialize)]
+struct TransactionJson<'a> {
+    id: u64,
+    timestamp: DateTime<Utc>,
+    kind: TransactionKind,
+    amount: Cents,
+    currency: Currency,
+    entries: &'a [Entry],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reverses: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reversed_by: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    order: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    order_date: Option<NaiveDate>,
+}
+
+#[derive(Serialize)]
+struct TrialBalanceJson<'a> {
+    balances: Vec<LedgerBalance<'a>>,
+    totals: Vec<TotalsJson>,
+    discrepancies: &'a [String],
+    balanced: bool,
+}
+
+#[derive(Serialize)]
+struct LedgerBalance<'a> {
+    account: &'a str,
+    currency: Currency,
+    balance: i128,
+}
+
+#[derive(Serialize)]
+struct TotalsJson {
+    currency: Currency,
+    debits: u128,
+    credits: u128,
+}
+
+#[derive(Serialize)]
+struct TrialBalanceRow<'a> {
+    account: &'a str,
+    currency: Currency,
+    balance: i128,
+    discrepancy: bool,
+}
+
+#[derive(Serialize)]
+struct ErrorJson<'a> {
+    error: ErrorRow<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorRow<'a> {
+    code: &'a str,
+    message: String,
+}
+
+/// Stable identifier of an error for machine-readable output, including the errors of loading
+/// and saving the system on top of those of its operations.
+pub fn error_code(error: &anyhow::Error) -> &'static str {
+    let code = banking_system::error_code(error);
+    if code != "error" {
+        code
+    } else if let Some(error) = error.downcast_ref::<ConfigError>() {
+        error.code()
+    } else if let Some(error) = error.downcast_ref::<LockError>() {
+        error.code()
+    } else if error.is::<io::Error>() || error.is::<csv::Error>() {
+        "storage_error"
+    } else {
+        #[cfg(feature = "sqlite")]
+        if error.is::<rusqlite::Error>() {
+            return "storage_error";
+        }
+        "error"
+    }
+}
+
+/// Renders the results of commands in the selected format, to be written to stdout once the
+/// changes of the command are saved.
+pub struct Output {
+    format: OutputFormat,
+}
+
+impl Output {
+    pub fn new(format: OutputFormat) -> Self {
+        Self { format }
+    }
+
+    /// Render a result as text, a JSON value or CSV rows, ending with a newline.
+    fn render<J, R>(&self, text: impl Display, json: &J, rows: R) -> Result<String>
+    where
+        J: Serialize + ?Sized,
+        R: IntoIterator,
+        R::Item: Serialize,
+    {
+        match self.format {
+            OutputFormat::Text => Ok(format!("{text}\n")),
+            OutputFormat::Json => Ok(format!("{}\n", serde_json::to_string(json)?)),
+            OutputFormat::Csv => {
+                let mut wtr = csv::Writer::from_writer(Vec::new());
+                for row in rows {
+                    wtr.serialize(row)?;
+                }
+                Ok(String::from_utf8(
+                    wtr.into_inner().map_err(|x| x.into_error())?,
+                )?)
+            },
+        }
+    }
+
+    pub fn accounts(&self, accounts: &[Account]) -> Result<String> {
+        if self.format == OutputFormat::Text && accounts.is_empty() {
+            return Ok(String::new());
+        }
+
+        let text = accounts
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let rows = accounts.iter().map(AccountRow::from).collect::<Vec<_>>();
+        self.render(text, &rows, &rows)
+    }
+
+    /// Render a single account, as the given message in text format.
+    pub fn account(&self, account: &Account, message: impl Display) -> Result<String> {
+        let row = AccountRow::from(account);
+        self.render(message, &row, Some(&row))
+    }
+
+    /// Render a hold, as the given message in text format.
+    pub fn hold(&self, hold: &Hold, message: impl Display) -> Result<String> {
+        let row = HoldRow::from(hold);
+        self.render(message, &row, Some(&row))
+    }
+
+    pub fn orders(&self, orders: &[&StandingOrder]) -> Result<String> {
+        if self.format == OutputFormat::Text && orders.is_empty() {
+            return Ok(String::new());
+        }
+
+        let text = orders
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let rows = orders
+            .iter()
+            .map(|x| OrderRow::from(*x))
+            .collect::<Vec<_>>();
+        self.render(text, &rows, &rows)
+    }
+
+    /// Render a single standing order, as the given message in text format.
+    pub fn order(&self, order: &StandingOrder, message: impl Display) -> Result<String> {
+        let row = OrderRow::from(order);
+        self.render(message, &row, Some(&row))
+    }
+
+    /// Render the runs of standing orders, as the given message in text format.
+    pub fn runs(&self, runs: &[OrderRun], message: impl Display) -> Result<String> {
+        let rows = runs.iter().map(RunRow::from).collect::<Vec<_>>();
+        self.render(message, &rows, &rows)
+    }
+
+    /// Render the report of every line of a batch, as the given message in text format.
+    pub fn batch(
+        &self,
+        reports: &[LineReport],
+        committed: bool,
+        message: impl Display,
+    ) -> Result<String> {
+        let json = BatchJson {
+            committed,
+            lines: reports.iter().map(LineRow::from).collect(),
+        };
+        self.render(message, &json, &json.lines)
+    }
+
+    /// Render a receipt, as the given message in text format.
+    pub fn receipt(&self, receipt: &Receipt, message: impl Display) -> Result<String> {
+        self.render(
+            message,
+            &ReceiptJson::from(receipt),
+            ReceiptRow::from_receipt(receipt),
+        )
+    }
+
+    /// Render the receipts of an operation that posted any number of transactions, as the given
+    /// message in text format.
+    pub fn receipts(&self, receipts: &[Receipt], message: impl Display) -> Result<String> {
+        let json = receipts.iter().map(ReceiptJson::from).collect::<Vec<_>>();
+        let rows = receipts.iter().flat_map(ReceiptRow::from_receipt);
+        self.render(message, &json, rows)
+    }
+
+    /// Render transactions, marking those that were reversed with the id of their reversal.
+    pub fn history(
+        &self,
+        transactions: &[&Transaction],
+        reversed_by: impl Fn(u64) -> Option<u64>,
+    ) -> Result<String> {
+        if self.format == OutputFormat::Text && transactions.is_empty() {
+            return Ok(String::new());
+        }
+
+        let text = transactions
+            .iter()
+            .map(|x| match reversed_by(x.id()) {
+                Some(id) => format!("{x}\treversed by #{id}"),
+                None => x.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let json = transactions
+            .iter()
+            .map(|x| TransactionJson {
+                id: x.id(),
+                timestamp: x.timestamp(),
+                kind: x.kind(),
+                amount: x.amount().amount(),
+                currency: x.currency(),
+                entries: x.entries(),
+                reverses: x.reverses(),
+                reversed_by: reversed_by(x.id()),
+                order: x.order().map(|(id, _)| id),
+                order_date: x.order().map(|(_, date)| date),
+            })
+            .collect::<Vec<_>>();
+        let rows = transactions.iter().flat_map(|x| x.to_records());
+
+        self.render(text, &json, rows)
+    }
+
+    pub fn trial_balance(&self, trial_balance: &TrialBalance) -> Result<String> {
+        let discrepancies = trial_balance.discrepancies();
+        let json = TrialBalanceJson {
+            balances: trial_balance
+                .balances()
+                .iter()
+                .map(|(account, currency, balance)| LedgerBalance {
+                    account,
+                    currency: *currency,
+                    balance: *balance,
+                })
+                .collect(),
+            totals: trial_balance
+                .totals()
+                .iter()
+                .map(|(currency, totals)| TotalsJson {
+                    currency: *currency,
+                    debits: totals.debits(),
+                    credits: totals.credits(),
+                })
+                .collect(),
+            discrepancies,
+            balanced: trial_balance.is_balanced(),
+        };
+        let rows = trial_balance
+            .balances()
+            .iter()
+            .map(|(account, currency, balance)| TrialBalanceRow {
+                account,
+                currency: *currency,
+                balance: *balance,
+                discrepancy: discrepancies.contains(account),
+            });
+
+        self.render(trial_balance, &json, rows)
+    }
+
+    /// Render an error. Text goes to stderr, structured errors to stdout like any other output.
+    pub fn error(&self, error: &anyhow::Error) {
+        let row = ErrorRow {
+            code: error_code(error),
+            message: error.to_string(),
+        };
+
+        let rendered = match self.format {
+            OutputFormat::Text => {
+                eprintln!("Error: {error:?}");
+                return;
+            },
+            OutputFormat::Json => self.render("", &ErrorJson { error: row }, None::<()>),
+            OutputFormat::Csv => self.render("", &(), Some(row)),
+        };
+        match rendered {
+            Ok(rendered) => print!("{rendered}"),
+            Err(write_error) => {
+                eprintln!("Error: {error:?}\nError writing output: {write_error:?}")
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use banking_rs::banking_system::BankingSystem;
+    use banking_rs::batch::{apply_batch, BatchLine};
+
+    use super::*;
+
+    fn deposit() -> (BankingSystem, Receipt) {
+        let mut bs = BankingSystem::new(Vec::new(), Vec::new()).unwrap();
+        bs.create_str("user1", "10").unwrap();
+        let receipt = bs.deposit_str("user1", "5").unwrap();
+        (bs, receipt)
+    }
+
+    #[test]
+    fn test_receipt() {
+        let (_, receipt) = deposit();
+
+        assert_eq!(
+            Output::new(OutputFormat::Text)
+                .receipt(&receipt, "Account balance is now $15.00")
+                .unwrap(),
+            "Account balance is now $15.00\n"
+        );
+        assert_eq!(
+            Output::new(OutputFormat::Json)
+                .receipt(&receipt, "")
+                .unwrap(),
+            "{\"transaction_id\":2,\"kind\":\"deposit\",\"amount\":500,\"currency\":\"USD\",\
+             \"balances\":[{\"account\":\"00000018\",\"balance\":1500}]}\n"
+        );
+        assert_eq!(
+            Output::new(OutputFormat::Csv)
+                .receipt(&receipt, "")
+                .unwrap(),
+            "transaction_id,kind,amount,currency,account,balance,rate,destination_amount,\
+             destination_currency,spread,fee\n\
+             2,deposit,500,USD,00000018,1500,,,,,\n"
+        );
+    }
+
+    #[test]
+    fn test_empty_lists() {
+        let text = Output::new(OutputFormat::Text);
+        assert_eq!(text.accounts(&[]).unwrap(), "");
+        assert_eq!(text.orders(&[]).unwrap(), "");
+        assert_eq!(text.history(&[], |_| None).unwrap(), "");
+        assert_eq!(
+            Output::new(OutputFormat::Json).accounts(&[]).unwrap(),
+            "[]\n"
+        );
+    }
+
+    #[test]
+    fn test_history_reversed_by() {
+        let (mut bs, receipt) = deposit();
+        let reversal = bs.reverse(receipt.transaction_id()).unwrap();
+        let history = bs.history(None).unwrap();
+
+        let text = Output::new(OutputFormat::Text)
+            .history(&history, |x| bs.reversed_by(x))
+            .unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].ends_with(&format!("\treversed by #{}", reversal.transaction_id())));
+        assert!(lines[2].contains(&format!("reversal $5.00 of #{}", receipt.transaction_id())));
+
+        let json = Output::new(OutputFormat::Json)
+            .history(&history, |x| bs.reversed_by(x))
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(json[1]["reversed_by"], reversal.transaction_id());
+        assert_eq!(json[2]["reverses"], receipt.transaction_id());
+        assert!(json[0].get("reverses").is_none());
+    }
+
+    #[test]
+    fn test_batch() {
+        let (mut bs, _) = deposit();
+        let line = |op, amount: &str| BatchLine {
+            op,
+            account: String::from("user1"),
+            to: None,
+            amount: amount.to_owned(),
+        };
+        let lines = [
+            (2, line(BatchOp::Deposit, "1")),
+            (3, line(BatchOp::Withdraw, "100")),
+        ];
+        let (reports, committed) = apply_batch(&mut bs, &lines);
+
+        let json = Output::new(OutputFormat::Json)
+            .batch(&reports, committed, "")
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(json["committed"], false);
+        assert_eq!(json["lines"][0]["status"], "rolled_back");
+        assert_eq!(json["lines"][1]["reason"], "account_overdraft");
+
+        let csv = Output::new(OutputFormat::Csv)
+            .batch(&reports, committed, "")
+            .unwrap();
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            [
+                "line,op,status,transaction_id,reason,message",
+                "2,deposit,rolled_back,,,",
+                &format!(
+                    "3,withdraw,failed,,account_overdraft,\"{}\"",
+                    reports[1].message.as_deref().unwrap()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_error_code() {
+        let (mut bs, _) = deposit();
+        let error = bs.withdraw_str("user1", "100").unwrap_err();
+        assert_eq!(error_code(&error), "account_overdraft");
+        let error = bs.deposit_str("user2", "1").unwrap_err();
+        assert_eq!(error_code(&error), "account_not_found");
+        let error = anyhow::Error::from(io::Error::other("disk full"));
+        assert_eq!(error_code(&error), "storage_error");
+        assert_eq!(error_code(&anyhow::anyhow!("other")), "error");
+    }
+}
@@ -0,0 +1,336 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use chrono::{Datelike, NaiveDate, Weekday};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::account::{AccountId, Cents};
+use crate::banking_system::BankingSystemError;
+use crate::currency::{Currency, Money};
+use crate::interest::is_posting_date;
+
+/// How often a standing order is due, written `daily`, `weekly:<weekday>`, e.g. `weekly:fri`, or
+/// `monthly:<day>`, e.g. `monthly:1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    Daily,
+    Weekly(Weekday),
+    /// On a day of the month, or on its last day in months that are shorter.
+    Monthly(u32),
+}
+
+impl Schedule {
+    /// Whether an instance of the order is due on the date.
+    pub fn is_due(self, date: NaiveDate) -> bool {
+        match self {
+            Self::Daily => true,
+            Self::Weekly(weekday) => date.weekday() == weekday,
+            Self::Monthly(day) => date.day() == day || (date.day() < day && is_posting_date(date)),
+        }
+    }
+
+    /// First date the order is due on, on or after a date.
+    pub fn next_due(self, from: NaiveDate) -> Option<NaiveDate> {
+        from.iter_days().find(|x| self.is_due(*x))
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = BankingSystemError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || BankingSystemError::InvalidSchedule(s.to_owned());
+        let lowercase = s.to_ascii_lowercase();
+        match lowercase.split_once(':') {
+            None if lowercase == "daily" => Ok(Self::Daily),
+            Some(("weekly", weekday)) => weekday.parse().map(Self::Weekly).map_err(|_| invalid()),
+            Some(("monthly", day)) => match day.parse() {
+                Ok(day @ 1..=31) => Ok(Self::Monthly(day)),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Daily => write!(f, "daily"),
+            Self::Weekly(weekday) => write!(f, "weekly:{}", weekday.to_string().to_lowercase()),
+            Self::Monthly(day) => write!(f, "monthly:{day}"),
+        }
+    }
+}
+
+impl Serialize for Schedule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Schedule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Instruction to transfer an amount between two accounts on a schedule, e.g. for rent or
+/// payroll, until an optional end date.
+///
+/// The order keeps the date its next instance is due, which only moves on once that instance has
+/// been run, so every instance is run exactly once.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct StandingOrder {
+    pub(crate) id: u64,
+    pub(crate) from: AccountId,
+    pub(crate) to: AccountId,
+    /// Amount transferred, in the currency of the account transferred from.
+    pub(crate) amount: Cents,
+    pub(crate) currency: Currency,
+    pub(crate) schedule: Schedule,
+    /// Date the next instance is due, after the end date once the order has finished.
+    pub(crate) next: NaiveDate,
+    /// Last date an instance can be due on.
+    pub(crate) end: Option<NaiveDate>,
+}
+
+impl Display for StandingOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "order: {}\tfrom: {}\tto: {}\tamount: {}\tschedule: {}",
+            self.id,
+            self.from,
+            self.to,
+            self.amount(),
+            self.schedule
+        )?;
+        if self.is_finished() {
+            write!(f, "\tfinished")?;
+        } else {
+            write!(f, "\tnext: {}", self.next)?;
+        }
+        if let Some(end) = self.end {
+            write!(f, "\tend: {end}")?;
+        }
+        Ok(())
+    }
+}
+
+impl StandingOrder {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn from(&self) -> AccountId {
+        self.from
+    }
+
+    pub fn to(&self) -> AccountId {
+        self.to
+    }
+
+    pub fn amount(&self) -> Money {
+        Money::new(self.amount, self.currency)
+    }
+
+    pub fn schedule(&self) -> Schedule {
+        self.schedule
+    }
+
+    pub fn next(&self) -> NaiveDate {
+        self.next
+    }
+
+    pub fn end(&self) -> Option<NaiveDate> {
+        self.end
+    }
+
+    /// Whether every instance up to the end date has been run.
+    pub fn is_finished(&self) -> bool {
+        self.end.is_some_and(|x| self.next > x)
+    }
+
+    /// Date of the next instance if it is due by a date.
+    pub(crate) fn due(&self, as_of: NaiveDate) -> Option<NaiveDate> {
+        Some(self.next).filter(|x| *x <= as_of && !self.is_finished())
+    }
+
+    /// Move on to the instance after the one due on the next date.
+    pub(crate) fn advance(&mut self) {
+        self.next = self
+            .next
+            .succ_opt()
+            .and_then(|x| self.schedule.next_due(x))
+            .unwrap_or(NaiveDate::MAX);
+    }
+}
+
+/// Outcome of running an instance of a standing order.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    /// The transfer was posted.
+    Executed,
+    /// Not transferred because an account was frozen, dormant or closed.
+    Skipped,
+    /// Not transferred for any other reason, e.g. insufficient funds.
+    Failed,
+}
+
+impl Display for RunStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            Self::Executed => "executed",
+            Self::Skipped => "skipped",
+            Self::Failed => "failed",
+        };
+        write!(f, "{status}")
+    }
+}
+
+/// Record of running the instance of a standing order due on a date. Instances that were not
+/// transferred keep the code and message of the error, and are not run again.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct OrderRun {
+    pub(crate) order: u64,
+    pub(crate) date: NaiveDate,
+    pub(crate) status: RunStatus,
+    pub(crate) transaction_id: Option<u64>,
+    pub(crate) reason: Option<String>,
+    pub(crate) message: Option<String>,
+}
+
+impl Display for OrderRun {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "order: {}\tdate: {}\tstatus: {}",
+            self.order, self.date, self.status
+        )?;
+        if let Some(id) = self.transaction_id {
+            write!(f, "\ttransaction: {id}")?;
+        }
+        if let Some(message) = &self.message {
+            write!(f, "\treason: {message}")?;
+        }
+        Ok(())
+    }
+}
+
+impl OrderRun {
+    pub fn order(&self) -> u64 {
+        self.order
+    }
+
+    pub fn date(&self) -> NaiveDate {
+        self.date
+    }
+
+    pub fn status(&self) -> RunStatus {
+        self.status
+    }
+
+    pub fn transaction_id(&self) -> Option<u64> {
+        self.transaction_id
+    }
+
+    /// Error code of an instance that was not transferred.
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_schedule() {
+        assert_eq!("daily".parse(), Ok(Schedule::Daily));
+        assert_eq!("weekly:fri".parse(), Ok(Schedule::Weekly(Weekday::Fri)));
+        assert_eq!("Weekly:Friday".parse(), Ok(Schedule::Weekly(Weekday::Fri)));
+        assert_eq!("monthly:1".parse(), Ok(Schedule::Monthly(1)));
+        for s in [
+            "monthly:0",
+            "monthly:32",
+            "weekly:x",
+            "monthly",
+            "yearly:1",
+            "",
+        ] {
+            assert_eq!(
+                s.parse::<Schedule>(),
+                Err(BankingSystemError::InvalidSchedule(s.to_owned()))
+            );
+        }
+
+        for schedule in [
+            Schedule::Daily,
+            Schedule::Weekly(Weekday::Mon),
+            Schedule::Monthly(31),
+        ] {
+            assert_eq!(schedule.to_string().parse(), Ok(schedule));
+        }
+    }
+
+    #[test]
+    fn test_next_due() {
+        let next = |schedule: Schedule, from| schedule.next_due(date(from)).unwrap();
+
+        assert_eq!(next(Schedule::Daily, "2024-01-05"), date("2024-01-05"));
+        // 2024-01-05 is a Friday
+        assert_eq!(
+            next(Schedule::Weekly(Weekday::Mon), "2024-01-05"),
+            date("2024-01-08")
+        );
+        assert_eq!(
+            next(Schedule::Weekly(Weekday::Fri), "2024-01-05"),
+            date("2024-01-05")
+        );
+        assert_eq!(next(Schedule::Monthly(1), "2024-01-02"), date("2024-02-01"));
+        // Days past the end of a month fall on its last day
+        assert_eq!(
+            next(Schedule::Monthly(31), "2024-02-01"),
+            date("2024-02-29")
+        );
+        assert_eq!(
+            next(Schedule::Monthly(30), "2024-03-01"),
+            date("2024-03-30")
+        );
+    }
+
+    #[test]
+    fn test_advance() {
+        let mut order = StandingOrder {
+            id: 1,
+            from: AccountId(1),
+            to: AccountId(2),
+            amount: Cents(100),
+            currency: Currency::USD,
+            schedule: Schedule::Monthly(31),
+            next: date("2024-01-31"),
+            end: Some(date("2024-03-15")),
+        };
+
+        assert_eq!(order.due(date("2024-01-30")), None);
+        assert_eq!(order.due(date("2024-02-01")), Some(date("2024-01-31")));
+        order.advance();
+        assert_eq!(order.next(), date("2024-02-29"));
+        order.advance();
+        assert_eq!(order.next(), date("2024-03-31"));
+        assert!(order.is_finished());
+        assert_eq!(order.due(date("2024-12-31")), None);
+    }
+}
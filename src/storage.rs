@@ -0,0 +1,39 @@
+use anyhow::Result;
+
+use crate::banking_system::BankingSystem;
+use crate::journal::Transaction;
+
+mod csv;
+mod lock;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use self::csv::CsvStorage;
+pub use self::lock::{FileLock, LockError};
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteStorage;
+
+/// Backend that persists a banking system between runs.
+pub trait Storage {
+    /// Load all accounts and the journal.
+    fn load(&mut self) -> Result<BankingSystem>;
+
+    /// Save the current state of all accounts.
+    fn save(&mut self, bs: &BankingSystem) -> Result<()>;
+
+    /// Append a transaction to the stored journal.
+    fn append_transaction(&mut self, transaction: &Transaction) -> Result<()>;
+
+    /// Persist every change made since the system was loaded or last committed.
+    ///
+    /// Transactions are appended to the journal before the accounts are saved, so an interrupted
+    /// commit shows up as a discrepancy in the trial balance rather than as untraceable balances.
+    fn commit(&mut self, bs: &mut BankingSystem) -> Result<()> {
+        for transaction in bs.unsaved_transactions() {
+            self.append_transaction(transaction)?;
+        }
+        self.save(bs)?;
+        bs.mark_saved();
+        Ok(())
+    }
+}
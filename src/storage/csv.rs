@@ -0,0 +1,718 @@
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::account::{Account, SignedCents};
+use crate::banking_system::BankingSystem;
+use crate::hold::Hold;
+use crate::idempotency::IdempotencyKey;
+use crate::journal::{JournalRecord, Transaction};
+use crate::standing_order::{OrderRun, StandingOrder};
+use crate::storage::Storage;
+
+/// Storage of accounts in a CSV file, with the journal in a second CSV file next to it.
+///
+/// For accounts stored in `banking_system.csv` the journal is stored in
+/// `banking_system_journal.csv`, holds in `banking_system_holds.csv`, standing orders in
+/// `banking_system_orders.csv`, their runs in `banking_system_order_runs.csv` and idempotency
+/// keys in `banking_system_idempotency_keys.csv`. Accounts are saved by writing
+/// `banking_system.csv.tmp` and renaming it over the accounts file, keeping the previous version
+/// as `banking_system.csv.bak`.
+///
+/// Accounts files written before accounts had ids are migrated when loaded, rewriting the journal
+/// to refer to accounts by id.
+#[derive(Debug, Clone)]
+pub struct CsvStorage {
+    path: PathBuf,
+    journal_path: PathBuf,
+    holds_path: PathBuf,
+    orders_path: PathBuf,
+    runs_path: PathBuf,
+    keys_path: PathBuf,
+    recovered: bool,
+}
+
+impl CsvStorage {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let stem = path
+            .file_stem()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let journal_path = path.with_file_name(format!("{stem}_journal.csv"));
+        let holds_path = path.with_file_name(format!("{stem}_holds.csv"));
+        let orders_path = path.with_file_name(format!("{stem}_orders.csv"));
+        let runs_path = path.with_file_name(format!("{stem}_order_runs.csv"));
+        let keys_path = path.with_file_name(format!("{stem}_idempotency_keys.csv"));
+
+        Self {
+            path,
+            journal_path,
+            holds_path,
+            orders_path,
+            runs_path,
+            keys_path,
+            recovered: false,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn journal_path(&self) -> &Path {
+        &self.journal_path
+    }
+
+    pub fn holds_path(&self) -> &Path {
+        &self.holds_path
+    }
+
+    pub fn orders_path(&self) -> &Path {
+        &self.orders_path
+    }
+
+    pub fn runs_path(&self) -> &Path {
+        &self.runs_path
+    }
+
+    pub fn keys_path(&self) -> &Path {
+        &self.keys_path
+    }
+
+    pub fn backup_path(&self) -> PathBuf {
+        with_extension_suffix(&self.path, "bak")
+    }
+
+    fn temp_path(&self) -> PathBuf {
+        with_extension_suffix(&self.path, "tmp")
+    }
+
+    /// Whether the last load found the accounts file corrupt and recovered the accounts from the
+    /// backup and the journal.
+    pub fn recovered(&self) -> bool {
+        self.recovered
+    }
+
+    fn read_accounts(path: &Path) -> Result<Vec<Account>> {
+        Ok(::csv::Reader::from_reader(File::open(path)?)
+            .deserialize::<Account>()
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Whether the accounts file was written before accounts had ids, with only a name and balance.
+    fn has_named_accounts(path: &Path) -> bool {
+        let Ok(file) = File::open(path) else {
+            return false;
+        };
+        let mut rdr = ::csv::Reader::from_reader(file);
+        rdr.headers()
+            .is_ok_and(|x| x.iter().any(|x| x == "name") && !x.iter().any(|x| x == "id"))
+    }
+
+    fn read_named_accounts(path: &Path) -> Result<Vec<(String, SignedCents)>> {
+        Ok(::csv::Reader::from_reader(File::open(path)?)
+            .deserialize::<(String, SignedCents)>()
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn migrate_named_accounts(
+        &mut self,
+        accounts: Vec<(String, SignedCents)>,
+        journal: Vec<Transaction>,
+    ) -> Result<BankingSystem> {
+        let mut bs = BankingSystem::from_named_accounts(accounts, journal)?;
+
+        // The journal is rewritten first, so if the accounts are not saved the migration is
+        // repeated with the same ids on the next load
+        self.write_journal(bs.journal())?;
+
+        self.save(&bs)?;
+        bs.mark_saved();
+        Ok(bs)
+    }
+
+    /// Whether the journal was written before entries had a currency or could be reversals or
+    /// standing order transfers. New entries cannot be appended to it until it is rewritten with
+    /// the missing columns, which is only done when the next transaction is appended, so reading
+    /// never changes the file.
+    fn has_outdated_journal(&self) -> bool {
+        let Ok(file) = File::open(&self.journal_path) else {
+            return false;
+        };
+        let mut rdr = ::csv::Reader::from_reader(file);
+        rdr.headers().is_ok_and(|x| {
+            !x.is_empty()
+                && ["currency", "reverses", "order", "order_date"]
+                    .iter()
+                    .any(|c| !x.iter().any(|x| x == *c))
+        })
+    }
+
+    /// Replace the journal with the given transactions by renaming a fully written file over it.
+    fn write_journal(&self, journal: &[Transaction]) -> Result<()> {
+        let temp_path = with_extension_suffix(&self.journal_path, "tmp");
+        let mut wtr = ::csv::Writer::from_writer(File::create(&temp_path)?);
+        for record in journal.iter().flat_map(|x| x.to_records()) {
+            wtr.serialize(record)?;
+        }
+        wtr.into_inner().map_err(|x| x.into_error())?.sync_all()?;
+        fs::rename(&temp_path, &self.journal_path)?;
+        sync_parent(&self.journal_path)?;
+        Ok(())
+    }
+
+    /// Read every record of a file next to the accounts, none if it does not exist.
+    fn read_records<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        Ok(::csv::Reader::from_reader(File::open(path)?)
+            .deserialize::<T>()
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Replace a file next to the accounts with the records, unless there are none and it does
+    /// not exist yet.
+    fn write_records<'a, T: Serialize + 'a>(
+        path: &Path,
+        records: impl IntoIterator<Item = &'a T>,
+    ) -> Result<()> {
+        let mut records = records.into_iter().peekable();
+        if records.peek().is_none() && !path.exists() {
+            return Ok(());
+        }
+
+        let temp_path = with_extension_suffix(path, "tmp");
+        let mut wtr = ::csv::Writer::from_writer(File::create(&temp_path)?);
+        for record in records {
+            wtr.serialize(record)?;
+        }
+        wtr.into_inner().map_err(|x| x.into_error())?.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        sync_parent(path)?;
+        Ok(())
+    }
+
+    /// Save everything but the idempotency keys, which a commit writes before the journal.
+    fn save_accounts(&self, bs: &BankingSystem) -> Result<()> {
+        let temp_path = self.temp_path();
+        let mut wtr = ::csv::Writer::from_writer(File::create(&temp_path)?);
+
+        for account in bs.accounts() {
+            wtr.serialize(account)?;
+        }
+        let file = wtr.into_inner().map_err(|x| x.into_error())?;
+        file.sync_all()?;
+        drop(file);
+
+        // Keep the previous version by linking it as the backup, so that an accounts file exists
+        // at every point in time
+        let backup_path = self.backup_path();
+        if self.path.exists() {
+            if backup_path.exists() {
+                fs::remove_file(&backup_path)?;
+            }
+            if fs::hard_link(&self.path, &backup_path).is_err() {
+                fs::copy(&self.path, &backup_path)?;
+            }
+        }
+
+        fs::rename(&temp_path, &self.path)?;
+        sync_parent(&self.path)?;
+
+        Self::write_records(&self.holds_path, bs.holds())?;
+        Self::write_records(&self.orders_path, bs.orders())?;
+        Self::write_records(&self.runs_path, bs.runs())
+    }
+
+    /// Load the accounts and the journal, migrating or recovering the accounts if needed.
+    fn load_accounts(&mut self) -> Result<BankingSystem> {
+        if !self.path.exists() && !self.journal_path.exists() {
+            File::create_new(&self.path)?;
+        }
+
+        let journal = self.read_journal()?;
+        self.recovered = false;
+
+        // A corrupt file of either format is recovered below
+        if Self::has_named_accounts(&self.path) {
+            if let Ok(accounts) = Self::read_named_accounts(&self.path) {
+                return self.migrate_named_accounts(accounts, journal);
+            }
+        }
+
+        // An accounts file that is missing or empty while the journal is not was lost mid-save
+        let error = match Self::read_accounts(&self.path) {
+            Ok(accounts) if !accounts.is_empty() || journal.is_empty() => {
+                return Ok(BankingSystem::new(accounts, journal)?);
+            },
+            Ok(_) => anyhow::anyhow!("accounts file {} is empty", self.path.display()),
+            Err(error) => error,
+        };
+
+        let backup = Self::read_accounts(&self.backup_path());
+        if backup.is_err() && journal.is_empty() {
+            return Err(error);
+        }
+
+        self.recovered = true;
+        Ok(BankingSystem::recover(backup.unwrap_or_default(), journal)?)
+    }
+
+    fn read_journal(&self) -> Result<Vec<Transaction>> {
+        if !self.journal_path.exists() {
+            return Ok(Vec::new());
+        }
+
+        Ok(Transaction::from_records(
+            ::csv::Reader::from_reader(File::open(&self.journal_path)?)
+                .deserialize::<JournalRecord>()
+                .collect::<Result<Vec<_>, _>>()?,
+        ))
+    }
+}
+
+/// Append a suffix to the full file name, e.g. `banking_system.csv` to `banking_system.csv.bak`.
+fn with_extension_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_owned();
+    file_name.push(".");
+    file_name.push(suffix);
+    path.with_file_name(file_name)
+}
+
+/// Flush the directory entry of a renamed file to disk.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+impl Storage for CsvStorage {
+    fn load(&mut self) -> Result<BankingSystem> {
+        let mut bs = self.load_accounts()?;
+        bs.set_holds(Self::read_records::<Hold>(&self.holds_path)?);
+        bs.set_orders(
+            Self::read_records::<StandingOrder>(&self.orders_path)?,
+            Self::read_records::<OrderRun>(&self.runs_path)?,
+        );
+        bs.set_idempotency_keys(Self::read_records::<IdempotencyKey>(&self.keys_path)?);
+        Ok(bs)
+    }
+
+    fn save(&mut self, bs: &BankingSystem) -> Result<()> {
+        self.save_accounts(bs)?;
+        Self::write_records(&self.keys_path, bs.idempotency_keys())
+    }
+
+    fn append_transaction(&mut self, transaction: &Transaction) -> Result<()> {
+        if self.has_outdated_journal() {
+            self.write_journal(&self.read_journal()?)?;
+        }
+
+        let write_header = !self.journal_path.exists();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.journal_path)?;
+        let mut wtr = ::csv::WriterBuilder::new()
+            .has_headers(write_header)
+            .from_writer(file);
+
+        for record in transaction.to_records() {
+            wtr.serialize(record)?;
+        }
+        wtr.into_inner().map_err(|x| x.into_error())?.sync_all()?;
+
+        Ok(())
+    }
+
+    /// Commit like the default, but write the idempotency keys before the journal, so a request
+    /// whose transactions were appended is never retried for want of its key. Keys of requests
+    /// whose transactions did not reach the journal are dropped again on load.
+    fn commit(&mut self, bs: &mut BankingSystem) -> Result<()> {
+        Self::write_records(&self.keys_path, bs.idempotency_keys())?;
+        for transaction in bs.unsaved_transactions() {
+            self.append_transaction(transaction)?;
+        }
+        self.save_accounts(bs)?;
+        bs.mark_saved();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, Utc};
+
+    use super::*;
+    use crate::account::{AccountId, Cents};
+    use crate::currency::Money;
+    use crate::standing_order::{RunStatus, Schedule};
+
+    #[test]
+    fn test_journal_path() {
+        let storage = CsvStorage::new("data/banking_system.csv");
+
+        assert_eq!(
+            storage.journal_path(),
+            Path::new("data/banking_system_journal.csv")
+        );
+    }
+
+    #[test]
+    fn test_load_missing_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = CsvStorage::new(dir.path().join("banking_system.csv"));
+        let bs = storage.load().unwrap();
+
+        assert!(bs.accounts().is_empty());
+        assert!(bs.journal().is_empty());
+    }
+
+    /// Create two accounts and transfer between them, committing after each step.
+    fn commit_transfer(storage: &mut CsvStorage) -> BankingSystem {
+        let mut bs = storage.load().unwrap();
+        let user1 = bs
+            .create("user1", Money::usd(Cents(2000)))
+            .unwrap()
+            .account_id()
+            .unwrap();
+        let user2 = bs
+            .create("user2", Money::usd(Cents(500)))
+            .unwrap()
+            .account_id()
+            .unwrap();
+        storage.commit(&mut bs).unwrap();
+        assert!(bs.unsaved_transactions().is_empty());
+
+        bs.transfer(user1, user2, Money::usd(Cents(250))).unwrap();
+        storage.commit(&mut bs).unwrap();
+        bs
+    }
+
+    #[test]
+    fn test_commit_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = CsvStorage::new(dir.path().join("banking_system.csv"));
+
+        let mut bs = commit_transfer(&mut storage);
+        bs.rename(AccountId(1), "user3").unwrap();
+        bs.freeze(AccountId(2)).unwrap();
+        storage.commit(&mut bs).unwrap();
+
+        let loaded = storage.load().unwrap();
+        assert_eq!(loaded.accounts(), bs.accounts());
+        assert_eq!(loaded.journal(), bs.journal());
+        assert!(loaded.trial_balance().is_balanced());
+    }
+
+    #[test]
+    fn test_holds() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = CsvStorage::new(dir.path().join("banking_system.csv"));
+        let mut bs = commit_transfer(&mut storage);
+        assert!(!storage.holds_path().exists());
+
+        let expires = Utc::now() + chrono::Duration::days(7);
+        let released = bs
+            .hold(AccountId(1), Money::usd(Cents(500)), expires)
+            .unwrap()
+            .id();
+        bs.hold(AccountId(1), Money::usd(Cents(700)), expires)
+            .unwrap();
+        bs.release(released).unwrap();
+        storage.commit(&mut bs).unwrap();
+
+        let loaded = storage.load().unwrap();
+        assert_eq!(
+            loaded.holds().collect::<Vec<_>>(),
+            bs.holds().collect::<Vec<_>>()
+        );
+        assert_eq!(loaded.get_account(AccountId(1)).unwrap().held(), Cents(700));
+    }
+
+    #[test]
+    fn test_standing_orders() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = CsvStorage::new(dir.path().join("banking_system.csv"));
+        let mut bs = commit_transfer(&mut storage);
+        assert!(!storage.orders_path().exists());
+
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        bs.create_order(
+            AccountId(1),
+            AccountId(2),
+            Money::usd(Cents(1000)),
+            Schedule::Monthly(1),
+            date,
+            None,
+        )
+        .unwrap();
+        bs.create_order(
+            AccountId(2),
+            AccountId(1),
+            Money::usd(Cents(10_000)),
+            Schedule::Daily,
+            date,
+            Some(date),
+        )
+        .unwrap();
+        let runs = bs.run_due(date).unwrap();
+        assert_eq!(runs[1].status(), RunStatus::Failed);
+
+        // A crash after the transfer was appended to the journal does not pay the instance again
+        for transaction in bs.unsaved_transactions() {
+            storage.append_transaction(transaction).unwrap();
+        }
+        let mut crashed = storage.load().unwrap();
+        assert_eq!(crashed.runs(), &runs[..1]);
+        assert!(crashed
+            .run_due(date)
+            .unwrap()
+            .iter()
+            .all(|x| x.order() != runs[0].order()));
+
+        // Complete the commit the crash interrupted
+        storage.save(&bs).unwrap();
+        bs.mark_saved();
+
+        let loaded = storage.load().unwrap();
+        assert_eq!(
+            loaded.orders().collect::<Vec<_>>(),
+            bs.orders().collect::<Vec<_>>()
+        );
+        assert_eq!(loaded.runs(), runs);
+        assert!(loaded.unsaved_runs().is_empty());
+    }
+
+    #[test]
+    fn test_idempotency_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = CsvStorage::new(dir.path().join("banking_system.csv"));
+        let mut bs = commit_transfer(&mut storage);
+        assert!(!storage.keys_path().exists());
+
+        let deposit = |bs: &mut BankingSystem| {
+            bs.idempotent(Some("key1"), "deposit 00000018 $5.00", |bs| {
+                bs.deposit(AccountId(1), Money::usd(Cents(500)))
+            })
+            .unwrap()
+        };
+        let receipt = deposit(&mut bs);
+        storage.commit(&mut bs).unwrap();
+
+        let mut loaded = storage.load().unwrap();
+        assert_eq!(
+            loaded.idempotency_keys().collect::<Vec<_>>(),
+            bs.idempotency_keys().collect::<Vec<_>>()
+        );
+        assert_eq!(deposit(&mut loaded), receipt);
+        assert_eq!(loaded.journal(), bs.journal());
+
+        // A key written without the transaction of its request, as the commit was interrupted
+        // before the journal, is dropped so the request can be retried
+        let withdraw = |bs: &mut BankingSystem| {
+            bs.idempotent(Some("key2"), "withdraw 00000018 $1.00", |bs| {
+                bs.withdraw(AccountId(1), Money::usd(Cents(100)))
+            })
+            .unwrap()
+        };
+        withdraw(&mut bs);
+        CsvStorage::write_records(storage.keys_path(), bs.idempotency_keys()).unwrap();
+        let mut loaded = storage.load().unwrap();
+        assert_eq!(loaded.idempotency_keys().count(), 1);
+        let journal = loaded.journal().len();
+        withdraw(&mut loaded);
+        assert_eq!(loaded.journal().len(), journal + 1);
+
+        // Once the transaction is in the journal the key is kept with it
+        for transaction in bs.unsaved_transactions() {
+            storage.append_transaction(transaction).unwrap();
+        }
+        let mut loaded = storage.load().unwrap();
+        assert_eq!(loaded.idempotency_keys().count(), 2);
+        let journal = loaded.journal().len();
+        withdraw(&mut loaded);
+        assert_eq!(loaded.journal().len(), journal);
+    }
+
+    #[test]
+    fn test_save_keeps_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = CsvStorage::new(dir.path().join("banking_system.csv"));
+
+        let mut bs = storage.load().unwrap();
+        let id = bs
+            .create("user1", Money::usd(Cents(2000)))
+            .unwrap()
+            .account_id()
+            .unwrap();
+        storage.commit(&mut bs).unwrap();
+        let first = fs::read(storage.path()).unwrap();
+
+        bs.deposit(id, Money::usd(Cents(500))).unwrap();
+        storage.commit(&mut bs).unwrap();
+
+        assert_eq!(fs::read(storage.backup_path()).unwrap(), first);
+        assert_ne!(fs::read(storage.path()).unwrap(), first);
+        assert!(!storage.temp_path().exists());
+    }
+
+    #[test]
+    fn test_recover_corrupt_accounts_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = CsvStorage::new(dir.path().join("banking_system.csv"));
+
+        let bs = commit_transfer(&mut storage);
+
+        // The backup is one save behind, the journal brings the balances up to date
+        fs::write(
+            storage.path(),
+            "id,name,balance\n00000018,user1,not a number\n",
+        )
+        .unwrap();
+        let loaded = storage.load().unwrap();
+        assert!(storage.recovered());
+        assert_eq!(loaded.accounts(), bs.accounts());
+        assert!(loaded.trial_balance().is_balanced());
+
+        // Without a backup the accounts are rebuilt from the journal alone, named after their ids
+        fs::write(storage.path(), "").unwrap();
+        fs::remove_file(storage.backup_path()).unwrap();
+        let loaded = storage.load().unwrap();
+        assert!(storage.recovered());
+        assert_eq!(
+            loaded
+                .accounts()
+                .iter()
+                .map(|x| (x.id(), x.name().to_owned(), x.balance()))
+                .collect::<Vec<_>>(),
+            Vec::from([
+                (AccountId(1), String::from("00000018"), SignedCents(1750)),
+                (AccountId(2), String::from("00000026"), SignedCents(750)),
+            ])
+        );
+
+        storage.save(&loaded).unwrap();
+        storage.load().unwrap();
+        assert!(!storage.recovered());
+    }
+
+    #[test]
+    fn test_unrecoverable_accounts_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = CsvStorage::new(dir.path().join("banking_system.csv"));
+        fs::write(storage.path(), "name,balance\nuser1,not a number\n").unwrap();
+
+        assert!(storage.load().is_err());
+    }
+
+    #[test]
+    fn test_migrate_named_accounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = CsvStorage::new(dir.path().join("banking_system.csv"));
+        fs::write(storage.path(), "name,balance\nuser1,1750\nuser2,750\n").unwrap();
+        fs::write(
+            storage.journal_path(),
+            "id,timestamp,kind,account,side,amount,balance\n\
+             1,2024-01-01T00:00:00Z,create,@external,debit,2000,\n\
+             1,2024-01-01T00:00:00Z,create,user1,credit,2000,2000\n\
+             2,2024-01-01T00:00:00Z,create,@external,debit,500,\n\
+             2,2024-01-01T00:00:00Z,create,user2,credit,500,500\n\
+             3,2024-01-01T00:00:00Z,transfer,user1,debit,250,1750\n\
+             3,2024-01-01T00:00:00Z,transfer,user2,credit,250,750\n",
+        )
+        .unwrap();
+
+        let bs = storage.load().unwrap();
+        assert_eq!(bs.resolve("user1"), Ok(AccountId(1)));
+        assert_eq!(bs.resolve("user2"), Ok(AccountId(2)));
+        assert_eq!(bs.history(Some(AccountId(2))).unwrap().len(), 2);
+        assert!(bs.trial_balance().is_balanced());
+
+        // Both files were rewritten with ids
+        let loaded = storage.load().unwrap();
+        assert_eq!(loaded.accounts(), bs.accounts());
+        assert_eq!(loaded.journal(), bs.journal());
+        assert!(fs::read_to_string(storage.journal_path())
+            .unwrap()
+            .contains("transfer,00000026,credit"));
+    }
+
+    #[test]
+    fn test_add_journal_currency() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = CsvStorage::new(dir.path().join("banking_system.csv"));
+        fs::write(
+            storage.path(),
+            "id,name,balance,status,overdraft_limit\n00000018,user1,2000,open,0\n",
+        )
+        .unwrap();
+        fs::write(
+            storage.journal_path(),
+            "id,timestamp,kind,account,side,amount,balance\n\
+             1,2024-01-01T00:00:00Z,create,@external,debit,2000,\n\
+             1,2024-01-01T00:00:00Z,create,00000018,credit,2000,2000\n",
+        )
+        .unwrap();
+
+        // Entries with a currency can be appended once the journal is rewritten with the column
+        let mut bs = storage.load().unwrap();
+        bs.deposit(AccountId(1), Money::usd(Cents(500))).unwrap();
+        storage.commit(&mut bs).unwrap();
+
+        let loaded = storage.load().unwrap();
+        assert_eq!(loaded.journal(), bs.journal());
+        assert!(loaded.trial_balance().is_balanced());
+        assert!(fs::read_to_string(storage.journal_path())
+            .unwrap()
+            .starts_with(
+            "id,timestamp,kind,account,side,amount,balance,currency,reverses,order,order_date\n"
+        ));
+    }
+
+    #[test]
+    fn test_add_journal_reverses() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = CsvStorage::new(dir.path().join("banking_system.csv"));
+        fs::write(
+            storage.path(),
+            "id,name,balance,status,overdraft_limit,currency\n00000018,user1,2000,open,0,USD\n",
+        )
+        .unwrap();
+        fs::write(
+            storage.journal_path(),
+            "id,timestamp,kind,account,side,amount,balance,currency\n\
+             1,2024-01-01T00:00:00Z,create,@external,debit,2000,,USD\n\
+             1,2024-01-01T00:00:00Z,create,00000018,credit,2000,2000,USD\n",
+        )
+        .unwrap();
+
+        // Loading leaves the journal as it is, and a reversal can be appended once the journal is
+        // rewritten with the column
+        let journal = fs::read_to_string(storage.journal_path()).unwrap();
+        let mut bs = storage.load().unwrap();
+        assert_eq!(fs::read_to_string(storage.journal_path()).unwrap(), journal);
+        let reversal = bs.reverse(1).unwrap().transaction_id();
+        storage.commit(&mut bs).unwrap();
+
+        let loaded = storage.load().unwrap();
+        assert_eq!(loaded.journal(), bs.journal());
+        assert_eq!(loaded.reversed_by(1), Some(reversal));
+        assert!(loaded.trial_balance().is_balanced());
+    }
+}
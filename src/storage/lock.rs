@@ -0,0 +1,129 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+/// Time between attempts to take a lock held by another process.
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Error, Debug)]
+pub enum LockError {
+    #[error("{} is locked by another process, gave up waiting after {} seconds", path.display(), timeout.as_secs_f64())]
+    Timeout { path: PathBuf, timeout: Duration },
+    #[error("could not lock {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+impl LockError {
+    /// Stable identifier of the error for machine-readable output.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Timeout { .. } => "lock_timeout",
+            Self::Io { .. } => "lock_failed",
+        }
+    }
+}
+
+/// Exclusive advisory lock on a data file, held until dropped.
+///
+/// The lock is taken on a separate `.lock` file next to the data file, so the data file itself can
+/// be replaced while the lock is held. Processes that take the lock for the whole cycle of loading,
+/// changing and saving the data cannot overwrite each other's updates.
+#[derive(Debug)]
+pub struct FileLock {
+    path: PathBuf,
+    _file: File,
+}
+
+impl FileLock {
+    /// Path of the lock file used for a data file, e.g. `banking_system.csv.lock`.
+    pub fn path_for(data_path: impl AsRef<Path>) -> PathBuf {
+        let data_path = data_path.as_ref();
+        let mut file_name = data_path.file_name().unwrap_or_default().to_owned();
+        file_name.push(".lock");
+        data_path.with_file_name(file_name)
+    }
+
+    /// Lock the data file, waiting up to `timeout` for another process to release it.
+    pub fn acquire(data_path: impl AsRef<Path>, timeout: Duration) -> Result<Self, LockError> {
+        let path = Self::path_for(data_path);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|source| LockError::Io {
+                path: path.clone(),
+                source,
+            })?;
+
+        let start = Instant::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(Self { path, _file: file }),
+                Err(TryLockError::WouldBlock) => {
+                    // Read the elapsed time once, as it may pass the timeout between two reads
+                    match timeout.checked_sub(start.elapsed()) {
+                        Some(remaining) if !remaining.is_zero() => {
+                            thread::sleep(RETRY_INTERVAL.min(remaining))
+                        },
+                        _ => return Err(LockError::Timeout { path, timeout }),
+                    }
+                },
+                Err(TryLockError::Error(source)) => return Err(LockError::Io { path, source }),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_for() {
+        assert_eq!(
+            FileLock::path_for("data/banking_system.csv"),
+            Path::new("data/banking_system.csv.lock")
+        );
+    }
+
+    #[test]
+    fn test_lock_is_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("banking_system.csv");
+
+        let lock = FileLock::acquire(&path, Duration::ZERO).unwrap();
+        assert!(matches!(
+            FileLock::acquire(&path, Duration::from_millis(100)),
+            Err(LockError::Timeout { .. })
+        ));
+
+        drop(lock);
+        FileLock::acquire(&path, Duration::ZERO).unwrap();
+    }
+
+    #[test]
+    fn test_wait_for_release() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("banking_system.csv");
+
+        let lock = FileLock::acquire(&path, Duration::ZERO).unwrap();
+        let release = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(lock);
+        });
+
+        FileLock::acquire(&path, Duration::from_secs(10)).unwrap();
+        release.join().unwrap();
+    }
+}
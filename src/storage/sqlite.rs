@@ -0,0 +1,970 @@
+use std::path::Path;
+
+use anyhow::Result;
+use rusqlite::{params, Connection};
+use serde::de::{DeserializeOwned, IntoDeserializer};
+use serde::Serialize;
+
+use crate::account::{Account, AccountId, AccountStatus, Cents, SignedCents};
+use crate::banking_system::BankingSystem;
+use crate::currency::Currency;
+use crate::hold::{Hold, HoldStatus};
+use crate::idempotency::IdempotencyKey;
+use crate::journal::{JournalRecord, Transaction};
+use crate::standing_order::{OrderRun, RunStatus, Schedule, StandingOrder};
+use crate::storage::Storage;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS accounts (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    balance INTEGER NOT NULL,
+    status TEXT NOT NULL DEFAULT 'open',
+    overdraft_limit INTEGER NOT NULL DEFAULT 0,
+    currency TEXT NOT NULL DEFAULT 'USD',
+    product TEXT,
+    accrued_to TEXT,
+    accrued_interest INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS journal (
+    transaction_id INTEGER NOT NULL,
+    entry INTEGER NOT NULL,
+    timestamp TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    account TEXT NOT NULL,
+    side TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    balance INTEGER,
+    currency TEXT NOT NULL DEFAULT 'USD',
+    reverses INTEGER,
+    order_id INTEGER,
+    order_date TEXT,
+    PRIMARY KEY (transaction_id, entry)
+);
+CREATE TABLE IF NOT EXISTS holds (
+    id INTEGER PRIMARY KEY,
+    account INTEGER NOT NULL,
+    amount INTEGER NOT NULL,
+    captured INTEGER NOT NULL,
+    currency TEXT NOT NULL,
+    status TEXT NOT NULL,
+    placed TEXT NOT NULL,
+    expires TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS standing_orders (
+    id INTEGER PRIMARY KEY,
+    from_account INTEGER NOT NULL,
+    to_account INTEGER NOT NULL,
+    amount INTEGER NOT NULL,
+    currency TEXT NOT NULL,
+    schedule TEXT NOT NULL,
+    next TEXT NOT NULL,
+    end_date TEXT
+);
+CREATE TABLE IF NOT EXISTS order_runs (
+    order_id INTEGER NOT NULL,
+    date TEXT NOT NULL,
+    status TEXT NOT NULL,
+    transaction_id INTEGER,
+    reason TEXT,
+    message TEXT,
+    PRIMARY KEY (order_id, date)
+);
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    key TEXT PRIMARY KEY,
+    request TEXT NOT NULL,
+    transaction_id INTEGER,
+    response TEXT NOT NULL,
+    used TEXT NOT NULL
+);
+";
+
+/// Storage in an embedded SQLite database.
+///
+/// Committing only writes the accounts, holds and standing orders modified since the last commit,
+/// and does so together with the journal entries, runs of standing orders and idempotency keys in
+/// a single database transaction, so a commit is either fully applied or not at all.
+///
+/// Databases created before accounts had ids are migrated when opened.
+#[derive(Debug)]
+pub struct SqliteStorage {
+    conn: Connection,
+}
+
+impl SqliteStorage {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(mut conn: Connection) -> Result<Self> {
+        add_columns(&conn, "journal", &ADDED_JOURNAL_COLUMNS)?;
+        migrate_named_accounts(&mut conn)?;
+        conn.execute_batch(SCHEMA)?;
+        add_columns(&conn, "accounts", &ADDED_ACCOUNT_COLUMNS)?;
+        Ok(Self { conn })
+    }
+}
+
+fn columns(conn: &Connection, table: &str) -> Result<Vec<String>> {
+    Ok(conn
+        .prepare("SELECT name FROM pragma_table_info(?1)")?
+        .query_map([table], |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Columns added to the accounts table after it was first created, with the definitions they are
+/// added with to databases that do not have them yet.
+const ADDED_ACCOUNT_COLUMNS: [(&str, &str); 6] = [
+    // Accounts stored before they had a status are open
+    ("status", "TEXT NOT NULL DEFAULT 'open'"),
+    // Accounts stored before overdrafts have no overdraft facility
+    ("overdraft_limit", "INTEGER NOT NULL DEFAULT 0"),
+    // Accounts stored before currencies are in dollars
+    ("currency", "TEXT NOT NULL DEFAULT 'USD'"),
+    // Accounts stored before products earn no interest
+    ("product", "TEXT"),
+    ("accrued_to", "TEXT"),
+    ("accrued_interest", "INTEGER NOT NULL DEFAULT 0"),
+];
+
+/// Columns added to the journal table after it was first created.
+const ADDED_JOURNAL_COLUMNS: [(&str, &str); 4] = [
+    ("currency", "TEXT NOT NULL DEFAULT 'USD'"),
+    ("reverses", "INTEGER"),
+    ("order_id", "INTEGER"),
+    ("order_date", "TEXT"),
+];
+
+/// Add the columns a table does not have yet. Tables that do not exist are left to be created.
+fn add_columns(conn: &Connection, table: &str, added: &[(&str, &str)]) -> Result<()> {
+    let columns = columns(conn, table)?;
+    if columns.is_empty() {
+        return Ok(());
+    }
+    for (column, definition) in added {
+        if !columns.iter().any(|x| x == column) {
+            conn.execute_batch(&format!(
+                "ALTER TABLE {table} ADD COLUMN {column} {definition}"
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Give ids to accounts of a database that stored them by name only, rewriting the accounts and
+/// the journal in one database transaction.
+fn migrate_named_accounts(conn: &mut Connection) -> Result<()> {
+    let columns = columns(conn, "accounts")?;
+    if columns.is_empty() || columns.iter().any(|x| x == "id") {
+        return Ok(());
+    }
+
+    let accounts = conn
+        .prepare("SELECT name, balance FROM accounts ORDER BY rowid")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .map(|row| {
+            let (name, balance): (String, i64) = row?;
+            Ok((name, SignedCents(balance)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let bs = BankingSystem::from_named_accounts(accounts, read_journal(conn)?)?;
+
+    let tx = conn.transaction()?;
+    tx.execute_batch("DROP TABLE accounts; DELETE FROM journal;")?;
+    tx.execute_batch(SCHEMA)?;
+    for transaction in bs.journal() {
+        insert_transaction(&tx, transaction)?;
+    }
+    for account in bs.accounts() {
+        upsert_account(&tx, account)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Store an enum as the same lowercase text used in CSV files.
+fn to_text<T: Serialize>(value: T) -> Result<String> {
+    let mut wtr = ::csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    wtr.serialize(value)?;
+    Ok(String::from_utf8(wtr.into_inner()?)?.trim_end().to_owned())
+}
+
+fn from_text<T: DeserializeOwned>(text: String) -> Result<T> {
+    Ok(T::deserialize(
+        IntoDeserializer::<serde::de::value::Error>::into_deserializer(text),
+    )?)
+}
+
+fn to_sql_cents(amount: Cents) -> Result<i64> {
+    Ok(i64::try_from(amount.0)?)
+}
+
+fn from_sql_cents(amount: i64) -> Result<Cents> {
+    Ok(Cents(u64::try_from(amount)?))
+}
+
+fn upsert_account(conn: &Connection, account: &Account) -> Result<()> {
+    conn.execute(
+        "INSERT INTO accounts
+             (id, name, balance, status, overdraft_limit, currency, product, accrued_to,
+              accrued_interest)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+         ON CONFLICT (id) DO UPDATE
+         SET name = excluded.name, balance = excluded.balance, status = excluded.status,
+             overdraft_limit = excluded.overdraft_limit, currency = excluded.currency,
+             product = excluded.product, accrued_to = excluded.accrued_to,
+             accrued_interest = excluded.accrued_interest",
+        params![
+            i64::try_from(account.id.0)?,
+            account.name,
+            account.balance.0,
+            to_text(account.status)?,
+            to_sql_cents(account.overdraft_limit)?,
+            account.currency.code(),
+            account.product,
+            account.accrued_to,
+            i64::try_from(account.accrued_interest)?,
+        ],
+    )?;
+    Ok(())
+}
+
+fn upsert_hold(conn: &Connection, hold: &Hold) -> Result<()> {
+    conn.execute(
+        "INSERT INTO holds (id, account, amount, captured, currency, status, placed, expires)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+         ON CONFLICT (id) DO UPDATE
+         SET captured = excluded.captured, status = excluded.status",
+        params![
+            i64::try_from(hold.id)?,
+            i64::try_from(hold.account.0)?,
+            to_sql_cents(hold.amount)?,
+            to_sql_cents(hold.captured)?,
+            hold.currency.code(),
+            to_text(hold.status)?,
+            hold.placed,
+            hold.expires,
+        ],
+    )?;
+    Ok(())
+}
+
+fn read_holds(conn: &Connection) -> Result<Vec<Hold>> {
+    conn.prepare(
+        "SELECT id, account, amount, captured, currency, status, placed, expires
+         FROM holds ORDER BY id",
+    )?
+    .query_map([], |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+            row.get(5)?,
+            row.get(6)?,
+            row.get(7)?,
+        ))
+    })?
+    .map(|row| {
+        let (id, account, amount, captured, currency, status, placed, expires): (
+            i64,
+            i64,
+            i64,
+            i64,
+            String,
+            String,
+            _,
+            _,
+        ) = row?;
+        Ok(Hold {
+            id: u64::try_from(id)?,
+            account: AccountId(u64::try_from(account)?),
+            amount: from_sql_cents(amount)?,
+            captured: from_sql_cents(captured)?,
+            currency: currency.parse::<Currency>()?,
+            status: from_text::<HoldStatus>(status)?,
+            placed,
+            expires,
+        })
+    })
+    .collect()
+}
+
+fn upsert_order(conn: &Connection, order: &StandingOrder) -> Result<()> {
+    conn.execute(
+        "INSERT INTO standing_orders
+             (id, from_account, to_account, amount, currency, schedule, next, end_date)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+         ON CONFLICT (id) DO UPDATE
+         SET next = excluded.next, end_date = excluded.end_date",
+        params![
+            i64::try_from(order.id)?,
+            i64::try_from(order.from.0)?,
+            i64::try_from(order.to.0)?,
+            to_sql_cents(order.amount)?,
+            order.currency.code(),
+            order.schedule.to_string(),
+            order.next,
+            order.end,
+        ],
+    )?;
+    Ok(())
+}
+
+fn read_orders(conn: &Connection) -> Result<Vec<StandingOrder>> {
+    conn.prepare(
+        "SELECT id, from_account, to_account, amount, currency, schedule, next, end_date
+         FROM standing_orders ORDER BY id",
+    )?
+    .query_map([], |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+            row.get(5)?,
+            row.get(6)?,
+            row.get(7)?,
+        ))
+    })?
+    .map(|row| {
+        let (id, from, to, amount, currency, schedule, next, end): (
+            i64,
+            i64,
+            i64,
+            i64,
+            String,
+            String,
+            _,
+            _,
+        ) = row?;
+        Ok(StandingOrder {
+            id: u64::try_from(id)?,
+            from: AccountId(u64::try_from(from)?),
+            to: AccountId(u64::try_from(to)?),
+            amount: from_sql_cents(amount)?,
+            currency: currency.parse::<Currency>()?,
+            schedule: schedule.parse::<Schedule>()?,
+            next,
+            end,
+        })
+    })
+    .collect()
+}
+
+fn insert_run(conn: &Connection, run: &OrderRun) -> Result<()> {
+    conn.execute(
+        "INSERT INTO order_runs (order_id, date, status, transaction_id, reason, message)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT (order_id, date) DO NOTHING",
+        params![
+            i64::try_from(run.order)?,
+            run.date,
+            to_text(run.status)?,
+            run.transaction_id.map(i64::try_from).transpose()?,
+            run.reason,
+            run.message,
+        ],
+    )?;
+    Ok(())
+}
+
+fn read_runs(conn: &Connection) -> Result<Vec<OrderRun>> {
+    conn.prepare(
+        "SELECT order_id, date, status, transaction_id, reason, message
+         FROM order_runs ORDER BY rowid",
+    )?
+    .query_map([], |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+            row.get(5)?,
+        ))
+    })?
+    .map(|row| {
+        let (order, date, status, transaction_id, reason, message): (
+            i64,
+            _,
+            String,
+            Option<i64>,
+            _,
+            _,
+        ) = row?;
+        Ok(OrderRun {
+            order: u64::try_from(order)?,
+            date,
+            status: from_text::<RunStatus>(status)?,
+            transaction_id: transaction_id.map(u64::try_from).transpose()?,
+            reason,
+            message,
+        })
+    })
+    .collect()
+}
+
+fn insert_key(conn: &Connection, key: &IdempotencyKey) -> Result<()> {
+    conn.execute(
+        "INSERT INTO idempotency_keys
+             (key, request, transaction_id, response, used)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT (key) DO NOTHING",
+        params![
+            key.key,
+            key.request,
+            key.transaction_id.map(i64::try_from).transpose()?,
+            key.response,
+            key.used,
+        ],
+    )?;
+    Ok(())
+}
+
+fn read_keys(conn: &Connection) -> Result<Vec<IdempotencyKey>> {
+    conn.prepare(
+        "SELECT key, request, transaction_id, response, used
+         FROM idempotency_keys ORDER BY key",
+    )?
+    .query_map([], |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+        ))
+    })?
+    .map(|row| {
+        let (key, request, transaction_id, response, used): (_, _, Option<i64>, _, _) = row?;
+        Ok(IdempotencyKey {
+            key,
+            request,
+            transaction_id: transaction_id.map(u64::try_from).transpose()?,
+            response,
+            used,
+        })
+    })
+    .collect()
+}
+
+fn insert_transaction(conn: &Connection, transaction: &Transaction) -> Result<()> {
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO journal
+             (transaction_id, entry, timestamp, kind, account, side, amount, balance, currency,
+              reverses, order_id, order_date)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+    )?;
+    for (entry, record) in transaction.to_records().enumerate() {
+        stmt.execute(params![
+            i64::try_from(record.id)?,
+            i64::try_from(entry)?,
+            record.timestamp,
+            to_text(record.kind)?,
+            record.account,
+            to_text(record.side)?,
+            to_sql_cents(record.amount)?,
+            record.balance.map(|x| x.0),
+            record.currency.code(),
+            record.reverses.map(i64::try_from).transpose()?,
+            record.order.map(i64::try_from).transpose()?,
+            record.order_date,
+        ])?;
+    }
+    Ok(())
+}
+
+fn read_journal(conn: &Connection) -> Result<Vec<Transaction>> {
+    let records = conn
+        .prepare(
+            "SELECT transaction_id, timestamp, kind, account, side, amount, balance, currency,
+                 reverses, order_id, order_date
+             FROM journal ORDER BY transaction_id, entry",
+        )?
+        .query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+                row.get(5)?,
+                row.get(6)?,
+                row.get(7)?,
+                row.get(8)?,
+                (row.get::<_, Option<i64>>(9)?, row.get(10)?),
+            ))
+        })?
+        .map(|row| {
+            let (id, timestamp, kind, account, side, amount, balance, currency, reverses, order): (
+                i64,
+                _,
+                String,
+                String,
+                String,
+                i64,
+                Option<i64>,
+                String,
+                Option<i64>,
+                _,
+            ) = row?;
+            let (order, order_date) = order;
+            Ok(JournalRecord {
+                id: u64::try_from(id)?,
+                timestamp,
+                kind: from_text(kind)?,
+                account,
+                side: from_text(side)?,
+                amount: from_sql_cents(amount)?,
+                balance: balance.map(SignedCents),
+                currency: currency.parse::<Currency>()?,
+                reverses: reverses.map(u64::try_from).transpose()?,
+                order: order.map(u64::try_from).transpose()?,
+                order_date,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Transaction::from_records(records))
+}
+
+impl Storage for SqliteStorage {
+    fn load(&mut self) -> Result<BankingSystem> {
+        let accounts = self
+            .conn
+            .prepare(
+                "SELECT id, name, balance, status, overdraft_limit, currency, product, accrued_to,
+                     accrued_interest
+                 FROM accounts ORDER BY id",
+            )?
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                    row.get(6)?,
+                    row.get(7)?,
+                    row.get(8)?,
+                ))
+            })?
+            .map(|row| {
+                let (
+                    id,
+                    name,
+                    balance,
+                    status,
+                    overdraft_limit,
+                    currency,
+                    product,
+                    accrued_to,
+                    accrued_interest,
+                ): (i64, String, i64, String, i64, String, _, _, i64) = row?;
+                Ok(Account {
+                    id: AccountId(u64::try_from(id)?),
+                    name,
+                    balance: SignedCents(balance),
+                    status: from_text::<AccountStatus>(status)?,
+                    overdraft_limit: from_sql_cents(overdraft_limit)?,
+                    currency: currency.parse::<Currency>()?,
+                    product,
+                    accrued_to,
+                    accrued_interest: u64::try_from(accrued_interest)?,
+                    held: Cents::ZERO,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut bs = BankingSystem::new(accounts, read_journal(&self.conn)?)?;
+        bs.set_holds(read_holds(&self.conn)?);
+        bs.set_orders(read_orders(&self.conn)?, read_runs(&self.conn)?);
+        bs.set_idempotency_keys(read_keys(&self.conn)?);
+        Ok(bs)
+    }
+
+    fn save(&mut self, bs: &BankingSystem) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        for account in bs.accounts() {
+            upsert_account(&tx, account)?;
+        }
+        for hold in bs.holds() {
+            upsert_hold(&tx, hold)?;
+        }
+        for order in bs.orders() {
+            upsert_order(&tx, order)?;
+        }
+        for run in bs.runs() {
+            insert_run(&tx, run)?;
+        }
+        for key in bs.idempotency_keys() {
+            insert_key(&tx, key)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn append_transaction(&mut self, transaction: &Transaction) -> Result<()> {
+        insert_transaction(&self.conn, transaction)
+    }
+
+    fn commit(&mut self, bs: &mut BankingSystem) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        for transaction in bs.unsaved_transactions() {
+            insert_transaction(&tx, transaction)?;
+        }
+        for account in bs.modified_accounts() {
+            upsert_account(&tx, account)?;
+        }
+        for hold in bs.modified_holds() {
+            upsert_hold(&tx, hold)?;
+        }
+        for order in bs.modified_orders() {
+            upsert_order(&tx, order)?;
+        }
+        for run in bs.unsaved_runs() {
+            insert_run(&tx, run)?;
+        }
+        for key in bs.unsaved_idempotency_keys() {
+            insert_key(&tx, key)?;
+        }
+        tx.commit()?;
+
+        bs.mark_saved();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::{NaiveDate, Utc};
+
+    use super::*;
+    use crate::currency::Money;
+    use crate::interest::{DayCount, Product};
+
+    #[test]
+    fn test_commit_and_load() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+
+        let mut bs = storage.load().unwrap();
+        let user1 = bs
+            .create("user1", Money::usd(Cents(2000)))
+            .unwrap()
+            .account_id()
+            .unwrap();
+        let user2 = bs
+            .create("user2", Money::usd(Cents(500)))
+            .unwrap()
+            .account_id()
+            .unwrap();
+        storage.commit(&mut bs).unwrap();
+        assert!(bs.unsaved_transactions().is_empty());
+
+        bs.transfer(user1, user2, Money::usd(Cents(250))).unwrap();
+        storage.commit(&mut bs).unwrap();
+        // Renaming and freezing post no transaction but are still committed
+        bs.rename(user1, "user3").unwrap();
+        bs.freeze(user2).unwrap();
+        storage.commit(&mut bs).unwrap();
+        // Negative balances are stored as is
+        bs.set_overdraft_limit(user1, Cents(5000)).unwrap();
+        bs.withdraw(user1, Money::usd(Cents(3000))).unwrap();
+        storage.commit(&mut bs).unwrap();
+
+        let loaded = storage.load().unwrap();
+        assert_eq!(loaded.accounts(), bs.accounts());
+        assert_eq!(loaded.journal(), bs.journal());
+        assert!(loaded.trial_balance().is_balanced());
+    }
+
+    #[test]
+    fn test_failed_commit_is_rolled_back() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+
+        let mut bs = storage.load().unwrap();
+        bs.create("user1", Money::usd(Cents(2000))).unwrap();
+        storage.commit(&mut bs).unwrap();
+        let committed = storage.load().unwrap();
+
+        // Inserting the new account fails after its journal entries were inserted
+        storage
+            .conn
+            .execute_batch(
+                "CREATE TEMP TRIGGER reject_user2 BEFORE INSERT ON accounts
+                 WHEN NEW.name = 'user2' BEGIN SELECT RAISE(ABORT, 'rejected'); END",
+            )
+            .unwrap();
+        let user2 = bs
+            .create("user2", Money::usd(Cents(0)))
+            .unwrap()
+            .account_id()
+            .unwrap();
+        bs.deposit(user2, Money::usd(Cents(500))).unwrap();
+        assert!(storage.commit(&mut bs).is_err());
+        assert_eq!(bs.unsaved_transactions().len(), 2);
+
+        let loaded = storage.load().unwrap();
+        assert_eq!(loaded.accounts(), committed.accounts());
+        assert_eq!(loaded.journal(), committed.journal());
+    }
+
+    #[test]
+    fn test_reopen_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("banking_system.db");
+
+        let mut storage = SqliteStorage::open(&path).unwrap();
+        let mut bs = storage.load().unwrap();
+        bs.create("user1", Money::usd(Cents(2000))).unwrap();
+        storage.commit(&mut bs).unwrap();
+        drop(storage);
+
+        let loaded = SqliteStorage::open(&path).unwrap().load().unwrap();
+        assert_eq!(loaded.accounts(), bs.accounts());
+        assert_eq!(loaded.journal(), bs.journal());
+    }
+
+    #[test]
+    fn test_currency() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        let mut bs = storage.load().unwrap();
+        let id = bs
+            .create("user1", Money::new(Cents(1050), Currency::EUR))
+            .unwrap()
+            .account_id()
+            .unwrap();
+        storage.commit(&mut bs).unwrap();
+
+        let loaded = storage.load().unwrap();
+        assert_eq!(loaded.get_account(id).unwrap().currency(), Currency::EUR);
+        assert_eq!(loaded.journal(), bs.journal());
+        assert_eq!(loaded.journal()[0].currency(), Currency::EUR);
+    }
+
+    #[test]
+    fn test_product() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        let mut bs = storage.load().unwrap();
+        let id = bs
+            .create("user1", Money::usd(Cents(100_000)))
+            .unwrap()
+            .account_id()
+            .unwrap();
+        // Open the account in the past, as interest is only accrued up to today
+        storage.commit(&mut bs).unwrap();
+        storage
+            .conn
+            .execute("UPDATE journal SET timestamp = '2024-01-01T00:00:00Z'", [])
+            .unwrap();
+        let mut bs = storage.load().unwrap();
+        bs.set_products(BTreeMap::from([(
+            String::from("savings"),
+            Product {
+                rate: Some("3.65".parse().unwrap()),
+                day_count: DayCount::Act365,
+                ..Product::default()
+            },
+        )]))
+        .unwrap();
+        // A day that is not the last of a month, so nothing is posted yet
+        let day = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+        bs.set_product(id, Some("savings"), day).unwrap();
+        bs.accrue(day).unwrap();
+        storage.commit(&mut bs).unwrap();
+
+        let loaded = storage.load().unwrap();
+        assert_eq!(loaded.accounts(), bs.accounts());
+        let account = loaded.get_account(id).unwrap();
+        assert_eq!(account.product(), Some("savings"));
+        assert_eq!(account.accrued_to(), Some(day));
+        assert_eq!(account.accrued_interest(), 10_000_000_000);
+    }
+
+    #[test]
+    fn test_holds() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        let mut bs = storage.load().unwrap();
+        let id = bs
+            .create("user1", Money::usd(Cents(2000)))
+            .unwrap()
+            .account_id()
+            .unwrap();
+        let expires = Utc::now() + chrono::Duration::days(7);
+        let captured = bs.hold(id, Money::usd(Cents(500)), expires).unwrap().id();
+        bs.hold(id, Money::usd(Cents(700)), expires).unwrap();
+        storage.commit(&mut bs).unwrap();
+        bs.capture(captured, Some(Money::usd(Cents(400)))).unwrap();
+        storage.commit(&mut bs).unwrap();
+
+        let loaded = storage.load().unwrap();
+        assert_eq!(
+            loaded.holds().collect::<Vec<_>>(),
+            bs.holds().collect::<Vec<_>>()
+        );
+        assert_eq!(loaded.get_account(id).unwrap().held(), Cents(700));
+        assert_eq!(loaded.get_account(id).unwrap().available(), Cents(900));
+    }
+
+    #[test]
+    fn test_standing_orders() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        let mut bs = storage.load().unwrap();
+        let from = bs
+            .create("user1", Money::usd(Cents(2000)))
+            .unwrap()
+            .account_id()
+            .unwrap();
+        let to = bs
+            .create("user2", Money::usd(Cents(0)))
+            .unwrap()
+            .account_id()
+            .unwrap();
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        bs.create_order(
+            from,
+            to,
+            Money::usd(Cents(1500)),
+            Schedule::Monthly(1),
+            date,
+            None,
+        )
+        .unwrap();
+        storage.commit(&mut bs).unwrap();
+
+        // The second instance fails for lack of funds
+        bs.run_due(NaiveDate::from_ymd_opt(2024, 2, 1).unwrap())
+            .unwrap();
+        storage.commit(&mut bs).unwrap();
+
+        let loaded = storage.load().unwrap();
+        assert_eq!(
+            loaded.orders().collect::<Vec<_>>(),
+            bs.orders().collect::<Vec<_>>()
+        );
+        assert_eq!(loaded.runs(), bs.runs());
+        assert_eq!(loaded.journal(), bs.journal());
+        assert_eq!(loaded.journal().last().unwrap().order(), Some((1, date)));
+        assert_eq!(
+            loaded
+                .runs()
+                .iter()
+                .map(|x| (x.status(), x.reason()))
+                .collect::<Vec<_>>(),
+            [
+                (RunStatus::Executed, None),
+                (RunStatus::Failed, Some("account_overdraft"))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_idempotency_keys() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        let mut bs = storage.load().unwrap();
+        let id = bs
+            .create("user1", Money::usd(Cents(2000)))
+            .unwrap()
+            .account_id()
+            .unwrap();
+        let withdraw = |bs: &mut BankingSystem| {
+            bs.idempotent(Some("key1"), "withdraw 00000018 $5.00", |bs| {
+                bs.withdraw(id, Money::usd(Cents(500)))
+            })
+            .unwrap()
+        };
+        let receipt = withdraw(&mut bs);
+        storage.commit(&mut bs).unwrap();
+        storage.commit(&mut bs).unwrap();
+
+        let mut loaded = storage.load().unwrap();
+        assert_eq!(
+            loaded.idempotency_keys().collect::<Vec<_>>(),
+            bs.idempotency_keys().collect::<Vec<_>>()
+        );
+        assert_eq!(withdraw(&mut loaded), receipt);
+        assert_eq!(loaded.get_account(id).unwrap().balance(), SignedCents(1500));
+    }
+
+    #[test]
+    fn test_migrate_named_accounts() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE accounts (name TEXT PRIMARY KEY, balance INTEGER NOT NULL);
+             CREATE TABLE journal (
+                 transaction_id INTEGER NOT NULL,
+                 entry INTEGER NOT NULL,
+                 timestamp TEXT NOT NULL,
+                 kind TEXT NOT NULL,
+                 account TEXT NOT NULL,
+                 side TEXT NOT NULL,
+                 amount INTEGER NOT NULL,
+                 balance INTEGER,
+                 PRIMARY KEY (transaction_id, entry)
+             );
+             INSERT INTO accounts VALUES ('user1', 1500), ('user2', 0);
+             INSERT INTO journal VALUES
+                 (1, 0, '2024-01-01T00:00:00Z', 'create', '@external', 'debit', 2000, NULL),
+                 (1, 1, '2024-01-01T00:00:00Z', 'create', 'user1', 'credit', 2000, 2000),
+                 (2, 0, '2024-01-01T00:00:00Z', 'withdraw', 'user1', 'debit', 500, 1500),
+                 (2, 1, '2024-01-01T00:00:00Z', 'withdraw', '@external', 'credit', 500, NULL);",
+        )
+        .unwrap();
+
+        let bs = SqliteStorage::from_connection(conn)
+            .unwrap()
+            .load()
+            .unwrap();
+        assert_eq!(bs.resolve("user1"), Ok(AccountId(1)));
+        assert_eq!(
+            bs.get_account(AccountId(1)).unwrap().balance(),
+            SignedCents(1500)
+        );
+        assert_eq!(bs.history(Some(AccountId(1))).unwrap().len(), 2);
+        // The account without any history got an opening transaction
+        assert_eq!(bs.history(Some(AccountId(2))).unwrap().len(), 1);
+        assert!(bs.unsaved_transactions().is_empty());
+        assert!(bs.trial_balance().is_balanced());
+    }
+
+    #[test]
+    fn test_add_columns() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE accounts (
+                 id INTEGER PRIMARY KEY,
+                 name TEXT NOT NULL,
+                 balance INTEGER NOT NULL
+             );
+             INSERT INTO accounts VALUES (1, 'user1', 0);",
+        )
+        .unwrap();
+
+        let mut storage = SqliteStorage::from_connection(conn).unwrap();
+        let mut bs = storage.load().unwrap();
+        assert_eq!(bs.accounts()[0].status(), AccountStatus::Open);
+        assert_eq!(bs.accounts()[0].overdraft_limit(), Cents::ZERO);
+        assert_eq!(bs.accounts()[0].currency(), Currency::USD);
+        assert_eq!(bs.accounts()[0].product(), None);
+        assert_eq!(bs.accounts()[0].accrued_interest(), 0);
+
+        bs.close(AccountId(1), None).unwrap();
+        storage.commit(&mut bs).unwrap();
+        assert_eq!(
+            storage.load().unwrap().accounts()[0].status(),
+            AccountStatus::Closed
+        );
+    }
+}